@@ -3,4 +3,37 @@ import init, * as libreddit from '../pkg/libreddit.js'
 
 await init()
 
-serve(libreddit.serve)
\ No newline at end of file
+// Persistent storage for long-lived caches, when Deno KV is available
+if ('openKv' in Deno) {
+  const kv = await Deno.openKv()
+  Object.assign(globalThis, {
+    __libredditKvGet: async (key: string) => (await kv.get<string>(['libreddit', key])).value,
+    __libredditKvPut: async (key: string, value: string, ttl: number) => {
+      await kv.set(['libreddit', key], value, { expireIn: ttl * 1000 })
+    },
+  })
+}
+
+// Send requests to Reddit through a forward proxy ("http://host:port") or
+// from another local address ("bind:203.0.113.5"), as set in LIBREDDIT_EGRESS
+if ('createHttpClient' in Deno) {
+  const clients = new Map<string, Deno.HttpClient>()
+  Object.assign(globalThis, {
+    __libredditEgressFetch: (req: Request, route: string) => {
+      let client = clients.get(route)
+      if (!client) {
+        const options = route.startsWith('bind:') ? { localAddress: route.slice('bind:'.length) } : { proxy: { url: route } }
+        client = Deno.createHttpClient(options as Deno.CreateHttpClientOptions)
+        clients.set(route, client)
+      }
+      return fetch(req, { client })
+    },
+  })
+}
+
+// Refresh digests in the background, when Deno Cron is available
+if ('cron' in Deno) {
+  Deno.cron('libreddit scheduled tasks', '0 * * * *', () => libreddit.scheduled())
+}
+
+serve(libreddit.serve)
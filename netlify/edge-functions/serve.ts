@@ -2,12 +2,12 @@ import init, * as libreddit from '../../pkg/libreddit.js'
 
 let inited = false;
 
-export default async (req: Request) => {
+export default async (req: Request, context: unknown) => {
   if (!inited) {
     await init('https://raw.githubusercontent.com/wangbinyq/libreddit/wasm/pkg/libreddit_bg.wasm')
     inited = true;
   }
-  return libreddit.serve(req)
+  return libreddit.serve(req, context)
 }
 export const config = {
   path: '/*'
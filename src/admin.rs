@@ -0,0 +1,63 @@
+// Endpoints for the instance's operator. They're only served when
+// `LIBREDDIT_ADMIN_TOKEN` is set, to requests carrying that token as a bearer
+// token. It isn't accepted in the query string, which ends up in logs.
+
+// CRATES
+use crate::client::purge_json;
+use crate::config::get_setting;
+use crate::server::purge_pages;
+use crate::server::RequestExt;
+use crate::settings::constant_time_eq;
+use crate::subreddit::purge_about;
+use crate::utils::wasm_error;
+use web_sys::{Request, Response, ResponseInit};
+
+/// Evicts the cached Reddit responses, subreddit details and rendered pages
+/// for `?path=` and the paths below it, so a stale page is fetched again on
+/// its next visit. `/` empties these caches. Proxied media in the Cache API
+/// can't be listed, so it stays until it expires, and caches in front of the
+/// instance, like a CDN, are purged separately.
+pub async fn purge(req: Request) -> Result<Response, String> {
+	if let Some(refusal) = refuse(&req) {
+		return refusal;
+	}
+
+	let path = req.uri().search_params().get("path").unwrap_or_default();
+	if !path.starts_with('/') {
+		return text(400, "Give the path to purge, like ?path=/r/rust");
+	}
+
+	let purged = purge_json(&path) + purge_about(&path).await + purge_pages(&path);
+	text(
+		200,
+		&format!("Purged {} cached {} for {}", purged, if purged == 1 { "response" } else { "responses" }, path),
+	)
+}
+
+// A response refusing the request, unless it carries the admin token. Without
+// a token set, the endpoints don't exist.
+fn refuse(req: &Request) -> Option<Result<Response, String>> {
+	let token = match get_setting("LIBREDDIT_ADMIN_TOKEN").filter(|token| !token.is_empty()) {
+		Some(token) => token,
+		None => return Some(text(404, "Not found")),
+	};
+
+	let given = req
+		.headers()
+		.get("Authorization")
+		.ok()
+		.flatten()
+		.and_then(|header| header.strip_prefix("Bearer ").map(str::to_string))
+		.unwrap_or_default();
+
+	(!constant_time_eq(given.as_bytes(), token.as_bytes())).then(|| text(401, "Unauthorized"))
+}
+
+fn text(status: u16, body: &str) -> Result<Response, String> {
+	let mut init = ResponseInit::new();
+	init.status(status);
+	let res = Response::new_with_opt_str_and_init(Some(body), &init).map_err(wasm_error)?;
+	res.headers().set("content-type", "text/plain; charset=utf-8").ok();
+	res.headers().set("Cache-Control", "no-store").ok();
+	Ok(res)
+}
@@ -0,0 +1,82 @@
+// Handlers for legacy and Old Reddit path forms that people still have
+// bookmarked. Each of them redirects to the equivalent canonical route.
+
+use crate::server::RequestExt;
+use crate::utils::{error, instance_path, redirect};
+use web_sys::{Request, Response};
+
+/// Suffix Old Reddit appends to a path to request its mobile "compact" view.
+const COMPACT_SUFFIX: &str = "/.compact";
+
+/// Redirects a `.compact` path (e.g. `/r/rust/comments/abc/title/.compact`)
+/// to the same path without the suffix.
+pub async fn compact(req: Request) -> Result<Response, String> {
+	Ok(redirect(format!("{}{}", strip_compact(&req.uri().pathname()), query(&req))))
+}
+
+/// Redirects toolbar (`/tb/:id`) and gallery (`/gallery/:id`) links to the
+/// post they refer to.
+pub async fn post(req: Request) -> Result<Response, String> {
+	Ok(redirect(format!("/comments/{}", req.param("id").unwrap_or_default())))
+}
+
+/// Redirects `/i/:id` links. These point at an image if the ID carries a file
+/// extension, and at a post otherwise.
+pub async fn image_or_post(req: Request) -> Result<Response, String> {
+	let id = req.param("id").unwrap_or_default();
+
+	if id.contains('.') {
+		Ok(redirect(format!("/img/{}", id)))
+	} else {
+		Ok(redirect(format!("/comments/{}", id)))
+	}
+}
+
+/// Redirects `/redirect?url=...` to this instance's equivalent of the given
+/// Reddit URL. This lets browser extensions and bookmarklets target any
+/// instance uniformly. URLs on hosts other than Reddit's are rejected.
+pub async fn reddit_url(req: Request) -> Result<Response, String> {
+	let url = req.uri().search_params().get("url").unwrap_or_default();
+
+	match instance_path(url.trim()) {
+		Some(path) => Ok(redirect(path)),
+		None => error(req, "Only reddit.com and redd.it URLs can be redirected").await,
+	}
+}
+
+// Remove the `.compact` suffix (and any trailing slash) from a path
+fn strip_compact(path: &str) -> String {
+	let path = path.trim_end_matches('/');
+	let path = path.strip_suffix(COMPACT_SUFFIX).unwrap_or(path);
+
+	if path.is_empty() {
+		"/".to_string()
+	} else {
+		path.to_string()
+	}
+}
+
+// The query string of the request, without the placeholder that
+// `RequestExt::uri` inserts when the request has none.
+fn query(req: &Request) -> String {
+	let search = req.uri().search();
+
+	if search == "?_" {
+		String::new()
+	} else {
+		search
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::strip_compact;
+
+	#[test]
+	fn strip_compact_works() {
+		assert_eq!(strip_compact("/.compact"), "/");
+		assert_eq!(strip_compact("/r/rust/.compact"), "/r/rust");
+		assert_eq!(strip_compact("/r/rust/comments/abc/title/.compact/"), "/r/rust/comments/abc/title");
+		assert_eq!(strip_compact("/r/rust/new"), "/r/rust/new");
+	}
+}
@@ -1,25 +1,167 @@
+use askama::Template;
 use cached::proc_macro::cached;
 use futures_lite::{future::BoxedLocal, FutureExt};
-use js_sys::Promise;
+use js_sys::{Date, Promise};
+use once_cell::sync::Lazy;
 use percent_encoding::{percent_encode, CONTROLS};
 use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
 use std::result::Result;
+use std::sync::Mutex;
 use wasm_bindgen::prelude::*;
-use web_sys::{Headers, Request, RequestInit, RequestRedirect, Response, ResponseInit, Url};
+use web_sys::{Headers, ReadableStreamDefaultReader, Request, RequestInit, RequestRedirect, Response, ResponseInit, Url};
 
 use crate::{
+	config::get_setting,
+	instance_info::MessageTemplate,
+	kv, media_cache,
+	media_policy::{strip_jpeg_metadata, Policy, ALLOWED_HEADERS},
+	resize::Resize,
 	server::RequestExt,
-	utils::{promise, wasm_error},
+	utils::{promise, wasm_error, Preferences},
 };
 
 #[wasm_bindgen]
 extern "C" {
 	#[wasm_bindgen(js_name = fetch)]
 	fn fetch_with_request(input: &Request) -> Promise;
+
+	#[wasm_bindgen(js_name = setTimeout)]
+	fn set_timeout(handler: &js_sys::Function, timeout: i32) -> JsValue;
+
+	// Provided by hosts that can send requests through another route, see
+	// `deno/serve.ts`
+	#[wasm_bindgen(js_namespace = globalThis, js_name = __libredditEgressFetch, catch)]
+	fn egress_fetch(input: &Request, route: &str) -> Result<Promise, JsValue>;
 }
 
 const REDDIT_URL_BASE: &str = "https://www.reddit.com";
 
+/// Error returned when Reddit refuses to serve this instance at all, which
+/// usually means its IP address has been blocked.
+pub(crate) const BLOCKED: &str = "Reddit has blocked this instance";
+
+/// Error returned while requests to Reddit are held back after it failed
+/// repeatedly.
+pub(crate) const UNAVAILABLE: &str = "Reddit is unavailable right now, try again in a minute";
+
+// Failed requests in a row after which Reddit is left alone for a while, and
+// for how long, in milliseconds
+const BREAKER_THRESHOLD: u32 = 5;
+const BREAKER_COOLDOWN: f64 = 30_000.0;
+
+// Number of recent upstream requests kept for the status page
+pub(crate) const METRICS_SAMPLES: usize = 100;
+
+/// Metrics about the most recent requests made to Reddit, shown on the status
+/// page. These live in memory and are reset whenever the instance restarts.
+pub(crate) static METRICS: Lazy<Mutex<Metrics>> = Lazy::new(|| Mutex::new(Metrics::default()));
+
+#[derive(Default)]
+pub(crate) struct Metrics {
+	/// Latency in milliseconds and whether the request failed, most recent last
+	samples: VecDeque<(f64, bool)>,
+
+	/// Requests left in the current rate-limit window, as reported by Reddit
+	pub rate_limit_remaining: Option<f64>,
+
+	/// Time (in milliseconds since the epoch) at which the rate-limit window
+	/// resets
+	pub rate_limit_reset: Option<f64>,
+
+	/// Responses that were Reddit's block page rather than the API, and the
+	/// time (in milliseconds since the epoch) of the latest one
+	pub blocked: u64,
+	pub last_blocked: Option<f64>,
+
+	/// Requests that failed in a row, and the time (in milliseconds since the
+	/// epoch) until which no more are sent to Reddit because of them
+	consecutive_failures: u32,
+	pub breaker_open_until: Option<f64>,
+}
+
+impl Metrics {
+	fn record(&mut self, now: f64, latency: f64, failed: bool) {
+		if self.samples.len() == METRICS_SAMPLES {
+			self.samples.pop_front();
+		}
+		self.samples.push_back((latency, failed));
+
+		// After the cooldown, the next request probes whether Reddit is back
+		if failed {
+			self.consecutive_failures += 1;
+			if self.consecutive_failures >= BREAKER_THRESHOLD {
+				self.breaker_open_until = Some(now + BREAKER_COOLDOWN);
+			}
+		} else {
+			self.consecutive_failures = 0;
+			self.breaker_open_until = None;
+		}
+	}
+
+	/// Number of requests the metrics are computed from
+	pub fn count(&self) -> usize {
+		self.samples.len()
+	}
+
+	/// Mean latency of recent requests in milliseconds
+	pub fn mean_latency(&self) -> Option<f64> {
+		(!self.samples.is_empty()).then(|| self.samples.iter().map(|s| s.0).sum::<f64>() / self.samples.len() as f64)
+	}
+
+	/// Latency below which 95% of recent requests completed, in milliseconds
+	pub fn p95_latency(&self) -> Option<f64> {
+		let mut latencies: Vec<f64> = self.samples.iter().map(|s| s.0).collect();
+		latencies.sort_by(f64::total_cmp);
+		latencies.get((latencies.len() * 95 / 100).min(latencies.len().saturating_sub(1))).copied()
+	}
+
+	/// Share of recent requests that failed, between 0 and 1
+	pub fn error_rate(&self) -> Option<f64> {
+		(!self.samples.is_empty()).then(|| self.samples.iter().filter(|s| s.1).count() as f64 / self.samples.len() as f64)
+	}
+}
+
+// Whether a response is Reddit's network security block page. Reddit serves
+// it as HTML with a 403, where API errors (like private subreddits) are JSON.
+fn is_block_page(res: &Response) -> bool {
+	let content_type = res.headers().get("content-type").ok().flatten().unwrap_or_default();
+	res.status() == 403 && !content_type.contains("json")
+}
+
+fn record_block() {
+	if let Ok(mut metrics) = METRICS.lock() {
+		metrics.blocked += 1;
+		metrics.last_blocked = Some(Date::now());
+	}
+}
+
+/// Whether requests to Reddit are held back because it failed repeatedly.
+pub fn breaker_open() -> bool {
+	let now = Date::now();
+	METRICS.lock().ok().and_then(|metrics| metrics.breaker_open_until).is_some_and(|until| until > now)
+}
+
+// Record the outcome of an upstream request made at `start`
+fn record_metrics(start: f64, res: &Result<Response, String>) {
+	let now = Date::now();
+
+	if let Ok(mut metrics) = METRICS.lock() {
+		match res {
+			Ok(res) => {
+				metrics.record(now, now - start, res.status() == 429 || res.status() >= 500);
+
+				let header = |name: &str| res.headers().get(name).ok().flatten().and_then(|v| v.parse::<f64>().ok());
+				if let Some(remaining) = header("x-ratelimit-remaining") {
+					metrics.rate_limit_remaining = Some(remaining);
+					metrics.rate_limit_reset = header("x-ratelimit-reset").map(|reset| now + reset * 1000.0);
+				}
+			}
+			Err(_) => metrics.record(now, now - start, true),
+		}
+	}
+}
+
 /// Gets the canonical path for a resource on Reddit. This is accomplished by
 /// making a `HEAD` request to Reddit at the path given in `path`.
 ///
@@ -32,8 +174,27 @@ const REDDIT_URL_BASE: &str = "https://www.reddit.com";
 /// value is `Ok(None)` if Reddit responded with a 3xx, but did not provide a
 /// `Location` header. An `Err(String)` is returned if Reddit responds with a
 /// 429, or if we were unable to decode the value in the `Location` header.
+///
+/// Resolutions never change, so they are also kept in persistent storage when
+/// the host provides it, sparing Reddit the `HEAD` request after restarts.
 #[cached(size = 1024, time = 600, result = true)]
 pub async fn canonical_path(path: String) -> Result<Option<String>, String> {
+	let key = format!("canonical_path:{}", path);
+	if let Some(resolved) = kv::get(&key).await.and_then(|stored| serde_json::from_str(&stored).ok()) {
+		return Ok(resolved);
+	}
+
+	let resolved = resolve_canonical_path(path).await?;
+	if let Ok(stored) = serde_json::to_string(&resolved) {
+		kv::put(&key, &stored, CANONICAL_PATH_TTL).await;
+	}
+	Ok(resolved)
+}
+
+// How long resolved paths are kept in persistent storage, in seconds
+const CANONICAL_PATH_TTL: u32 = 90 * 24 * 60 * 60;
+
+async fn resolve_canonical_path(path: String) -> Result<Option<String>, String> {
 	let res = reddit_head(path.clone(), true).await?;
 
 	if res.status() == 429 {
@@ -60,7 +221,17 @@ pub async fn canonical_path(path: String) -> Result<Option<String>, String> {
 }
 
 pub async fn proxy(req: Request, format: &str) -> Result<Response, String> {
-	let mut url = format!("{}{}", format, req.uri().search());
+	// `?force=1` lifts the size limit, and `?w=` and `?q=` scale images down.
+	// They're handled here and not passed on to Reddit.
+	let uri = req.uri();
+	let params = uri.search_params();
+	let force = params.get("force").as_deref() == Some("1");
+	let resize = Resize::from_params(params.get("w"), params.get("q"));
+	for key in ["force", "w", "q"] {
+		params.delete(key);
+	}
+
+	let mut url = format!("{}{}", format, uri.search());
 
 	// For each parameter in request
 	req.params().for_each(&mut |value, key| {
@@ -69,12 +240,126 @@ pub async fn proxy(req: Request, format: &str) -> Result<Response, String> {
 		url = url.replace(&format!("{{{}}}", name), value);
 	});
 
-	stream(&url, &req).await
+	stream(&url, &req, force, resize).await
+}
+
+// Largest file the proxy serves without `?force=1`, in megabytes
+fn proxy_max_size() -> Option<f64> {
+	get_setting("LIBREDDIT_PROXY_MAX_SIZE")
+		.and_then(|val| val.trim().parse::<f64>().ok())
+		.filter(|&mb| mb > 0.0)
+}
+
+// Size in megabytes of the whole file behind a response, if it's known and
+// larger than `max_size`. Range responses report the full size in
+// Content-Range, so players can't slip past the limit a chunk at a time.
+fn oversized(content_range: Option<String>, content_length: Option<String>, max_size: f64) -> Option<f64> {
+	let bytes = content_range
+		.and_then(|range| range.rsplit('/').next().and_then(|total| total.parse::<f64>().ok()))
+		.or_else(|| content_length.and_then(|len| len.parse::<f64>().ok()))?;
+	let size = bytes / 1_048_576.0;
+
+	(size > max_size).then_some(size)
+}
+
+// How a client's Range header is handled by the proxy
+#[derive(Debug, PartialEq)]
+enum ByteRange<'a> {
+	/// One range of bytes, like `bytes=0-` or `bytes=-500`, passed on to the
+	/// upstream host as is
+	Single(&'a str),
+	/// Several ranges at once, which would need a multipart response that
+	/// media players don't use and the proxy doesn't assemble
+	Multiple,
+	/// Not a byte range; ignored, so the whole file is served
+	Invalid,
+}
+
+impl<'a> ByteRange<'a> {
+	fn parse(header: &'a str) -> Self {
+		let header = header.trim();
+		let spec = match header.strip_prefix("bytes=") {
+			Some(spec) => spec,
+			None => return Self::Invalid,
+		};
+		if spec.contains(',') {
+			return Self::Multiple;
+		}
+
+		let bound = |bound: &str| bound.trim().parse::<u64>().ok();
+		match spec.split_once('-') {
+			// From a byte to another, or to the end
+			Some((first, last)) if !first.trim().is_empty() => match (bound(first), last.trim()) {
+				(Some(_), "") => Self::Single(header),
+				(Some(first), last) if bound(last).is_some_and(|last| last >= first) => Self::Single(header),
+				_ => Self::Invalid,
+			},
+			// The last bytes of the file
+			Some((_, suffix)) if bound(suffix).is_some_and(|length| length > 0) => Self::Single(header),
+			_ => Self::Invalid,
+		}
+	}
+}
+
+// Answer a Range header asking for several ranges
+fn range_not_satisfiable() -> Result<Response, String> {
+	let mut init = ResponseInit::new();
+	init.status(416);
+	let res = Response::new_with_opt_str_and_init(Some("Only single byte ranges are supported"), &init).map_err(wasm_error)?;
+	res.headers().set("content-range", "bytes */*").ok();
+	res.headers().set("accept-ranges", "bytes").ok();
+	Ok(res)
+}
+
+fn too_large(req: &Request, size: f64, max_size: f64) -> Result<Response, String> {
+	let uri = req.uri();
+	uri.search_params().set("force", "1");
+
+	let body = MessageTemplate {
+		title: String::from("Media too large"),
+		body: format!(
+			"<p>This file is {:.0} MB, more than the {} MB this instance proxies by default.</p><br>\
+			<p><a href=\"{}{}\">Load it anyway</a></p>",
+			size,
+			max_size,
+			uri.pathname(),
+			uri.search()
+		),
+		prefs: Preferences::new(req),
+		url: uri.pathname(),
+	}
+	.render()
+	.map_err(|e| e.to_string())?;
+
+	let mut init = ResponseInit::new();
+	init.status(413);
+	let res = Response::new_with_opt_str_and_init(Some(&body), &init).map_err(wasm_error)?;
+	res.headers().set("content-type", "text/html").ok();
+
+	Ok(res)
+}
+
+pub(crate) async fn stream(url: &str, req: &Request, force: bool, resize: Option<Resize>) -> Result<Response, String> {
+	// Whole files are kept by this instance's URL, which includes the options
+	// to scale them. Ranges are left to Reddit.
+	let key = req.url();
+	let path = req.uri().pathname();
+	let whole = req.headers().get("Range").ok().flatten().is_none();
+	if whole {
+		if let Some(res) = media_cache::get(&key, &path).await {
+			return Ok(res);
+		}
+	}
+
+	let res = fetch_proxied(url, req, force, resize).await?;
+	Ok(if whole && res.status() == 200 { media_cache::put(&key, &path, res) } else { res })
 }
 
-async fn stream(url: &str, req: &Request) -> Result<Response, String> {
+async fn fetch_proxied(url: &str, req: &Request, force: bool, resize: Option<Resize>) -> Result<Response, String> {
 	// First parameter is target URL (mandatory).
-	Url::new(url).map_err(|_| "Couldn't parse URL".to_string())?;
+	let parsed = Url::new(url).map_err(|_| "Couldn't parse URL".to_string())?;
+	let policy = Policy::for_host(&parsed.hostname()).ok_or_else(|| "Media from this host isn't proxied".to_string())?;
+	let max_size = if force { None } else { policy.max_size.or_else(proxy_max_size) };
 
 	// Build the hyper client from the HTTPS connector.
 
@@ -82,37 +367,95 @@ async fn stream(url: &str, req: &Request) -> Result<Response, String> {
 	let headers = Headers::new().unwrap();
 
 	// Copy useful headers from original request
-	for &key in &["Range", "If-Modified-Since", "Cache-Control"] {
+	for &key in &["If-Modified-Since", "Cache-Control"] {
 		if let Some(value) = req.headers().get(key).ok().flatten() {
 			headers.set(key, &value).ok();
 		}
 	}
 
+	// Seeking in videos asks for one range of bytes at a time. If-Range only
+	// means something alongside a range.
+	if let Some(range) = req.headers().get("Range").ok().flatten() {
+		match ByteRange::parse(&range) {
+			ByteRange::Single(range) => {
+				headers.set("Range", range).ok();
+				if let Some(if_range) = req.headers().get("If-Range").ok().flatten() {
+					headers.set("If-Range", &if_range).ok();
+				}
+			}
+			ByteRange::Multiple => return range_not_satisfiable(),
+			ByteRange::Invalid => {}
+		}
+	}
+
 	req_init.headers(&headers);
 
-	let req = Request::new_with_str_and_init(url, &req_init).unwrap();
+	let upstream = Request::new_with_str_and_init(url, &req_init).unwrap();
 
-	let response: Response = promise(fetch_with_request(&req)).await?;
+	let response: Response = promise(upstream_fetch(&upstream)).await?;
 
 	let headers = response.headers();
 
-	let rm = |key: &str| headers.delete(key).ok();
-	rm("access-control-expose-headers");
-	rm("server");
-	rm("vary");
-	rm("etag");
-	rm("x-cdn");
-	rm("x-cdn-client-region");
-	rm("x-cdn-name");
-	rm("x-cdn-server-region");
-	rm("x-reddit-cdn");
-	rm("x-reddit-video-features");
+	if let Some(max_size) = max_size {
+		let header = |key: &str| headers.get(key).ok().flatten();
+		if let Some(size) = oversized(header("content-range"), header("content-length"), max_size) {
+			// Stop downloading the file from Reddit
+			if let Some(body) = response.body() {
+				let _ = body.cancel();
+			}
+			return too_large(req, size, max_size);
+		}
+	}
+
+	// Successful responses must be the kind of media the host is expected to
+	// serve
+	let content_type = headers.get("content-type").ok().flatten().unwrap_or_default();
+	let success = matches!(response.status(), 200 | 206);
+	if success && !policy.allows(&content_type) {
+		if let Some(body) = response.body() {
+			let _ = body.cancel();
+		}
+		return Err(format!("Refusing to proxy unexpected content ({})", content_type));
+	}
+
+	// Pass on only the headers the media needs
+	let kept = Headers::new().map_err(wasm_error)?;
+	for key in ALLOWED_HEADERS {
+		if let Some(value) = headers.get(key).ok().flatten() {
+			kept.set(key, &value).ok();
+		}
+	}
 
 	let mut init = ResponseInit::new();
 	init.status(response.status());
 	init.status_text(&response.status_text());
-	init.headers(&headers);
 
+	// Scaled images are encoded anew, which leaves their metadata behind
+	if let Some(resize) = resize.filter(|_| response.status() == 200 && (content_type.starts_with("image/jpeg") || content_type.starts_with("image/png"))) {
+		let buffer = promise::<JsValue>(response.array_buffer().map_err(wasm_error)?).await?;
+		let original = js_sys::Uint8Array::new(&buffer).to_vec();
+		let (mut image, content_type) = match resize.apply(&original) {
+			Some(resized) => resized,
+			None if policy.strip_metadata && content_type.starts_with("image/jpeg") => (strip_jpeg_metadata(&original), "image/jpeg"),
+			None => (original, ""),
+		};
+		kept.delete("content-length").ok();
+		if !content_type.is_empty() {
+			kept.set("content-type", content_type).ok();
+		}
+		init.headers(&kept);
+		return Response::new_with_opt_u8_array_and_init(Some(&mut image), &init).map_err(wasm_error);
+	}
+
+	if policy.strip_metadata && response.status() == 200 && content_type.starts_with("image/jpeg") {
+		let buffer = promise::<JsValue>(response.array_buffer().map_err(wasm_error)?).await?;
+		let mut image = strip_jpeg_metadata(&js_sys::Uint8Array::new(&buffer).to_vec());
+		kept.delete("content-length").ok();
+		init.headers(&kept);
+		return Response::new_with_opt_u8_array_and_init(Some(&mut image), &init).map_err(wasm_error);
+	}
+
+	init.headers(&kept);
 	Response::new_with_opt_readable_stream_and_init(response.body().as_ref(), &init).map_err(wasm_error)
 }
 
@@ -151,35 +494,388 @@ fn request(method: &'static str, path: String, redirect: bool, quarantine: bool)
 	req.redirect(if redirect { RequestRedirect::Follow } else { RequestRedirect::Manual });
 
 	let fut = async move {
+		if breaker_open() {
+			return Err(UNAVAILABLE.to_string());
+		}
+
 		let req = Request::new_with_str_and_init(&url, &req).map_err(wasm_error)?;
 
-		promise(fetch_with_request(&req)).await
+		let start = Date::now();
+		let res = promise(upstream_fetch(&req)).await;
+		record_metrics(start, &res);
+		crate::server::record_stats(|stats| stats.upstream = (stats.upstream.0 + 1, stats.upstream.1 + Date::now() - start));
+
+		res
 	};
 
 	fut.boxed_local()
 }
 
-// Make a request to a Reddit API and parse the JSON response
-#[cached(size = 100, time = 30, result = true)]
+// Send a request to Reddit through the route set in `LIBREDDIT_EGRESS`, such
+// as a forward proxy, or directly if there is none or the host can't use it
+fn upstream_fetch(req: &Request) -> Promise {
+	get_setting("LIBREDDIT_EGRESS")
+		.filter(|route| !route.is_empty())
+		.and_then(|route| egress_fetch(req, &route).ok())
+		.unwrap_or_else(|| fetch_with_request(req))
+}
+
+/// Fetches and parses JSON from a third-party API, such as the archive API
+/// used to show removed comments. Unlike `json`, this doesn't go to Reddit.
+#[cached(size = 100, time = 600, result = true)]
+pub async fn external_json(url: String) -> Result<Value, String> {
+	let headers = Headers::new().map_err(wasm_error)?;
+	headers.set("User-Agent", &format!("web:libreddit:{}", env!("CARGO_PKG_VERSION"))).ok();
+	headers.set("Accept", "application/json").ok();
+
+	let mut init = RequestInit::new();
+	init.headers(&headers);
+
+	let req = Request::new_with_str_and_init(&url, &init).map_err(wasm_error)?;
+	let res: Response = promise(fetch_with_request(&req)).await?;
+	if !res.ok() {
+		return Err(format!("The archive responded with an error ({})", res.status()));
+	}
+
+	let json = promise::<JsValue>(res.json().map_err(wasm_error)?).await?;
+	Ok(serde_wasm_bindgen::from_value(json).unwrap_or_default())
+}
+
+/// Posts `body` as JSON to a third-party API, such as a translation service,
+/// and parses the JSON response.
+pub async fn external_post_json(url: &str, body: &Value) -> Result<Value, String> {
+	let headers = Headers::new().map_err(wasm_error)?;
+	headers.set("Content-Type", "application/json").ok();
+	headers.set("Accept", "application/json").ok();
+
+	let mut init = RequestInit::new();
+	init.method("POST");
+	init.headers(&headers);
+	init.body(Some(&JsValue::from_str(&body.to_string())));
+
+	let req = Request::new_with_str_and_init(url, &init).map_err(wasm_error)?;
+	let res: Response = promise(fetch_with_request(&req)).await?;
+	if !res.ok() {
+		return Err(format!("The service responded with an error ({})", res.status()));
+	}
+
+	let json = promise::<JsValue>(res.json().map_err(wasm_error)?).await?;
+	Ok(serde_wasm_bindgen::from_value(json).unwrap_or_default())
+}
+
+/// Checks whether `url` answers successfully within `timeout` milliseconds,
+/// resolving to how long it took. The request starts right away, so several
+/// probes can run at once.
+pub fn probe(url: &str, timeout: i32) -> BoxedLocal<Option<f64>> {
+	let started = Date::now();
+	let first = Request::new_with_str(url).ok().map(|req| {
+		let timer = Promise::new(&mut |resolve, _| {
+			set_timeout(&resolve, timeout);
+		});
+		Promise::race(&js_sys::Array::of2(&fetch_with_request(&req), &timer))
+	});
+
+	async move {
+		let res = promise::<JsValue>(first?).await.ok()?.dyn_into::<Response>().ok()?;
+		res.ok().then(|| Date::now() - started)
+	}
+	.boxed_local()
+}
+
+/// Downloads media from `url`, returning its content type and bytes. Used to
+/// embed images in exported threads. Media larger than `max_bytes` is
+/// refused without downloading the rest of it.
+pub async fn fetch_media(url: &str, max_bytes: usize) -> Result<(String, Vec<u8>), String> {
+	let req = Request::new_with_str(url).map_err(wasm_error)?;
+	let res: Response = promise(fetch_with_request(&req)).await?;
+	let too_large = || format!("Media is larger than {} bytes", max_bytes);
+
+	let length = res.headers().get("content-length").ok().flatten().and_then(|len| len.parse::<usize>().ok());
+	if !res.ok() || length.is_some_and(|len| len > max_bytes) {
+		if let Some(body) = res.body() {
+			let _ = body.cancel();
+		}
+		return Err(if res.ok() { too_large() } else { format!("Couldn't fetch media ({})", res.status()) });
+	}
+
+	let content_type = res.headers().get("content-type").ok().flatten().unwrap_or_default();
+	match read_capped(&res, max_bytes, None).await {
+		Some((bytes, true)) => Ok((content_type, bytes)),
+		Some((_, false)) => Err(too_large()),
+		None => Err("Couldn't read media".to_string()),
+	}
+}
+
+/// Fetches a page or image from a third-party site for a link preview,
+/// returning its content type and bytes. Redirects aren't followed, and the
+/// request is abandoned after `timeout` milliseconds or when the response is
+/// larger than `max_bytes`.
+pub async fn fetch_preview(url: &str, timeout: i32, max_bytes: usize) -> Option<(String, Vec<u8>)> {
+	let headers = Headers::new().ok()?;
+	headers.set("User-Agent", &format!("web:libreddit:{}", env!("CARGO_PKG_VERSION"))).ok();
+
+	let mut init = RequestInit::new();
+	init.headers(&headers);
+	init.redirect(RequestRedirect::Manual);
+
+	let req = Request::new_with_str_and_init(url, &init).ok()?;
+	let timer = Promise::new(&mut |resolve, _| {
+		set_timeout(&resolve, timeout);
+	});
+	let res = promise::<JsValue>(Promise::race(&js_sys::Array::of2(&fetch_with_request(&req), &timer)))
+		.await
+		.ok()?
+		.dyn_into::<Response>()
+		.ok()?;
+
+	let length = res.headers().get("content-length").ok().flatten().and_then(|len| len.parse::<usize>().ok());
+	if !res.ok() || length.is_some_and(|len| len > max_bytes) {
+		if let Some(body) = res.body() {
+			let _ = body.cancel();
+		}
+		return None;
+	}
+
+	let content_type = res.headers().get("content-type").ok().flatten().unwrap_or_default();
+	let (bytes, _) = read_capped(&res, max_bytes, Some(&timer)).await?;
+
+	Some((content_type, bytes))
+}
+
+// Reads at most `max_bytes` of a response body and cancels the rest, also
+// telling whether the whole body was read. Gives up if `deadline` resolves
+// first, since the length header doesn't bound chunked bodies.
+async fn read_capped(res: &Response, max_bytes: usize, deadline: Option<&Promise>) -> Option<(Vec<u8>, bool)> {
+	let Some(body) = res.body() else {
+		return Some((Vec::new(), true));
+	};
+	let reader = ReadableStreamDefaultReader::new(&body).ok()?;
+
+	let mut bytes = Vec::new();
+	loop {
+		let read = match deadline {
+			Some(deadline) => Promise::race(&js_sys::Array::of2(&reader.read(), deadline)),
+			None => reader.read(),
+		};
+		let chunk = promise::<JsValue>(read).await.ok()?;
+
+		// Timers resolve to `undefined`, chunks to `{ done, value }`
+		if chunk.is_undefined() {
+			let _ = reader.cancel();
+			return None;
+		}
+		if js_sys::Reflect::get(&chunk, &"done".into()).ok()?.is_truthy() {
+			return Some((bytes, true));
+		}
+
+		let value = js_sys::Reflect::get(&chunk, &"value".into()).ok()?;
+		bytes.extend(js_sys::Uint8Array::new(&value).to_vec());
+		if bytes.len() > max_bytes {
+			let _ = reader.cancel();
+			bytes.truncate(max_bytes);
+			return Some((bytes, false));
+		}
+	}
+}
+
+/// How long responses from Reddit are cached, in seconds, by the kind of path
+/// they were fetched from: first while they're fresh, then for how much longer
+/// they may be shown while a fresh copy is fetched. The first rule whose
+/// segments start the path applies, where `*` stands for any one segment and
+/// a `.json` extension is ignored. The last one matches everything else, such
+/// as listings.
+pub(crate) const CACHE_POLICY: [(&str, u32, u32); 7] = [
+	("/r/*/about", 6 * 60 * 60, 24 * 60 * 60),
+	("/user/*/about", 6 * 60 * 60, 24 * 60 * 60),
+	("/api/v1/*/emojis", 6 * 60 * 60, 24 * 60 * 60),
+	("/r/*/wiki", 60 * 60, 6 * 60 * 60),
+	("/r/*/comments", 5 * 60, 10 * 60),
+	("/comments", 5 * 60, 10 * 60),
+	("", 60, 5 * 60),
+];
+
+/// Number of seconds a response for `path` stays fresh.
+pub(crate) fn cache_ttl(path: &str) -> u32 {
+	policy(path).0
+}
+
+/// Number of seconds a response for `path` may still be shown after it stops
+/// being fresh, while it's fetched again.
+pub(crate) fn stale_ttl(path: &str) -> u32 {
+	policy(path).1
+}
+
+fn policy(path: &str) -> (u32, u32) {
+	let path = path.split(['?', '#']).next().unwrap_or_default();
+	let segments: Vec<&str> = path
+		.split('/')
+		.filter(|segment| !segment.is_empty())
+		.map(|segment| segment.trim_end_matches(".json"))
+		.collect();
+
+	CACHE_POLICY
+		.iter()
+		.find(|(pattern, _, _)| {
+			let pattern: Vec<&str> = pattern.split('/').filter(|segment| !segment.is_empty()).collect();
+			pattern.len() <= segments.len()
+				&& pattern
+					.iter()
+					.zip(&segments)
+					.all(|(expected, segment)| *expected == "*" || expected.eq_ignore_ascii_case(segment))
+		})
+		.map_or((0, 0), |&(_, fresh, stale)| (fresh, stale))
+}
+
+/// What the JSON cache did for a lookup, from the most to the least cached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CacheOutcome {
+	Hit,
+	/// A stale response was served while a fresh one is fetched
+	Stale,
+	Miss,
+}
+
+impl CacheOutcome {
+	pub fn as_str(self) -> &'static str {
+		match self {
+			CacheOutcome::Hit => "HIT",
+			CacheOutcome::Stale => "STALE",
+			CacheOutcome::Miss => "MISS",
+		}
+	}
+}
+
+// Report a lookup in the JSON cache for the request being handled, which
+// shows the least cached of its lookups
+fn record_cache(outcome: CacheOutcome) {
+	crate::server::record_stats(|stats| stats.cache = stats.cache.max(Some(outcome)));
+}
+
+// Most responses kept by the JSON cache
+const JSON_CACHE_SIZE: usize = 100;
+
+// Milliseconds after which a stale response is fetched again by another
+// request, in case the host stopped the one fetching it
+const REVALIDATE_TIMEOUT: f64 = 30_000.0;
+
+// A parsed response from Reddit, with the times (in milliseconds since the
+// epoch) at which it stops being fresh and at which it can't be shown anymore
+struct CachedJson {
+	fresh_until: f64,
+	stale_until: f64,
+	/// When a fresh copy started being fetched, if it's being fetched
+	refreshing_since: Option<f64>,
+	value: Value,
+}
+
+// Parsed responses from Reddit by path and quarantine opt-in
+static JSON_CACHE: Lazy<Mutex<HashMap<(String, bool), CachedJson>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+// Make a request to a Reddit API and parse the JSON response, reusing it for
+// as long as the cache policy allows. A stale response is returned right
+// away, and fetched again in the background for the next request.
 pub async fn json(path: String, quarantine: bool) -> Result<Value, String> {
+	let key = (path.clone(), quarantine);
+	let now = Date::now();
+
+	let cached = JSON_CACHE.lock().ok().and_then(|mut cache| {
+		let entry = cache.get_mut(&key).filter(|entry| entry.stale_until > now)?;
+		// Only the first request to find it stale fetches it again
+		let stale = entry.fresh_until <= now;
+		let revalidate = stale && entry.refreshing_since.is_none_or(|since| since + REVALIDATE_TIMEOUT <= now);
+		if revalidate {
+			entry.refreshing_since = Some(now);
+		}
+		Some((entry.value.clone(), stale, revalidate))
+	});
+
+	match cached {
+		Some((value, stale, revalidate)) => {
+			record_cache(if stale { CacheOutcome::Stale } else { CacheOutcome::Hit });
+			if revalidate {
+				crate::server::wait_until(async move {
+					if let Ok(value) = fetch_json(path, quarantine).await {
+						cache_json(key, value);
+					} else if let Ok(mut cache) = JSON_CACHE.lock() {
+						// Let a later request try again
+						if let Some(entry) = cache.get_mut(&key) {
+							entry.refreshing_since = None;
+						}
+					}
+				});
+			}
+			Ok(value)
+		}
+		None => {
+			record_cache(CacheOutcome::Miss);
+			let value = fetch_json(path, quarantine).await?;
+			cache_json(key, value.clone());
+			Ok(value)
+		}
+	}
+}
+
+fn cache_json(key: (String, bool), value: Value) {
+	let now = Date::now();
+	let (fresh, stale) = (cache_ttl(&key.0), stale_ttl(&key.0));
+
+	if let Ok(mut cache) = JSON_CACHE.lock() {
+		cache.retain(|_, entry| entry.stale_until > now);
+		// Make room by dropping the response that can be shown the shortest
+		if cache.len() >= JSON_CACHE_SIZE && !cache.contains_key(&key) {
+			if let Some(oldest) = cache.iter().min_by(|a, b| a.1.stale_until.total_cmp(&b.1.stale_until)).map(|(key, _)| key.clone()) {
+				cache.remove(&oldest);
+			}
+		}
+
+		let fresh_until = now + f64::from(fresh) * 1000.0;
+		cache.insert(
+			key,
+			CachedJson {
+				fresh_until,
+				stale_until: fresh_until + f64::from(stale) * 1000.0,
+				refreshing_since: None,
+				value,
+			},
+		);
+	}
+}
+
+/// Drops the cached responses for `path` and the paths below it, such as
+/// `/r/rust/hot.json?raw_json=1` for `/r/rust`, and returns how many there
+/// were.
+pub fn purge_json(path: &str) -> usize {
+	let path = path.trim_end_matches('/').to_ascii_lowercase();
+	JSON_CACHE.lock().map_or(0, |mut cache| {
+		let before = cache.len();
+		cache.retain(|(cached, _), _| !under_path(&cached.to_ascii_lowercase(), &path));
+		before - cache.len()
+	})
+}
+
+/// Whether a request path is `path` itself, in any format, or below it. Both
+/// are compared in lowercase.
+pub(crate) fn under_path(request: &str, path: &str) -> bool {
+	request.strip_prefix(path).is_some_and(|rest| rest.is_empty() || rest.starts_with(['/', '.', '?']))
+}
+
+async fn fetch_json(path: String, quarantine: bool) -> Result<Value, String> {
 	// Closure to quickly build errors
 	let err = |msg: &str, e: String| -> String {
 		// eprintln!("{} - {}: {}", url, msg, e);
 		format!("{}: {}", msg, e)
 	};
 
-	match reddit_get(path.clone(), quarantine)
-		.await
-		.map_err(|e| err("Couldn't send request to Reddit", e))
-		.and_then(|res| {
-			if res.status() >= 500 {
-				Err("Reddit is having issues, check if there's an outage".to_string())
-			} else {
-				Ok(res)
-			}
-		})?
-		.json()
-	{
+	let res = reddit_get(path.clone(), quarantine).await.map_err(|e| err("Couldn't send request to Reddit", e))?;
+	if res.status() >= 500 {
+		return Err("Reddit is having issues, check if there's an outage".to_string());
+	}
+	if is_block_page(&res) {
+		record_block();
+		return Err(BLOCKED.to_string());
+	}
+
+	match res.json() {
 		Ok(p) => {
 			let json = promise::<JsValue>(p).await?;
 			let json: Value = serde_wasm_bindgen::from_value(json).unwrap_or_default();
@@ -202,3 +898,78 @@ pub async fn json(path: String, quarantine: bool) -> Result<Value, String> {
 		Err(err) => Err(wasm_error(err)),
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::{cache_ttl, oversized, stale_ttl, under_path, ByteRange, Metrics, BREAKER_COOLDOWN, BREAKER_THRESHOLD};
+
+	#[test]
+	fn cache_ttl_works() {
+		assert_eq!(cache_ttl("/r/rust/about.json?raw_json=1"), 6 * 60 * 60);
+		assert_eq!(cache_ttl("/r/rust/wiki/index.json?raw_json=1"), 60 * 60);
+		assert_eq!(cache_ttl("/r/rust/comments/abc.json?raw_json=1"), 5 * 60);
+		assert_eq!(cache_ttl("/r/popular/hot.json?raw_json=1"), 60);
+		assert_eq!(stale_ttl("/r/rust/comments/abc.json?raw_json=1"), 10 * 60);
+		assert_eq!(stale_ttl("/r/popular/hot.json?raw_json=1"), 5 * 60);
+		assert_eq!(cache_ttl("/r/rust/about/rules.json?raw_json=1"), 6 * 60 * 60);
+		assert_eq!(cache_ttl("/user/spez/about.json?raw_json=1"), 6 * 60 * 60);
+		assert_eq!(cache_ttl("/api/v1/rust/emojis/all.json"), 6 * 60 * 60);
+		assert_eq!(cache_ttl("/comments/abc.json?raw_json=1"), 5 * 60);
+
+		// Only whole segments in their place count
+		assert_eq!(cache_ttl("/r/aboutfrogs/hot.json?raw_json=1"), 60);
+		assert_eq!(cache_ttl("/r/rust/comments/abc/about_my_cat.json?raw_json=1"), 5 * 60);
+		assert_eq!(cache_ttl("/r/rust/comments/abc/wiki/def.json?raw_json=1"), 5 * 60);
+		assert_eq!(cache_ttl("/user/wiki/submitted.json?raw_json=1"), 60);
+		assert_eq!(cache_ttl("/r/wiki/hot.json?raw_json=1"), 60);
+		assert_eq!(cache_ttl("/search.json?q=/r/rust/about"), 60);
+	}
+
+	#[test]
+	fn oversized_works() {
+		let mb = |n: u64| Some((n * 1_048_576).to_string());
+		assert_eq!(oversized(None, mb(200), 100.0), Some(200.0));
+		assert_eq!(oversized(None, mb(50), 100.0), None);
+		assert_eq!(oversized(Some(format!("bytes 0-1023/{}", 300 * 1_048_576)), Some("1024".into()), 100.0), Some(300.0));
+		assert_eq!(oversized(Some("bytes 0-1023/*".into()), Some("1024".into()), 100.0), None);
+		assert_eq!(oversized(None, None, 100.0), None);
+	}
+
+	#[test]
+	fn breaker_opens_after_failures() {
+		let mut metrics = Metrics::default();
+		for i in 1..BREAKER_THRESHOLD {
+			metrics.record(f64::from(i), 100.0, true);
+		}
+		assert_eq!(metrics.breaker_open_until, None);
+
+		metrics.record(10.0, 100.0, true);
+		assert_eq!(metrics.breaker_open_until, Some(10.0 + BREAKER_COOLDOWN));
+
+		metrics.record(20.0, 100.0, false);
+		assert_eq!(metrics.breaker_open_until, None);
+	}
+
+	#[test]
+	fn under_path_works() {
+		assert!(under_path("/r/rust/hot.json?raw_json=1", "/r/rust"));
+		assert!(under_path("/r/rust.json?raw_json=1", "/r/rust"));
+		assert!(under_path("/r/rust/comments/abc.json?sort=new", "/r/rust/comments/abc"));
+		assert!(under_path("/r/popular/hot.json", ""));
+		assert!(!under_path("/r/rustjerk/hot.json", "/r/rust"));
+		assert!(!under_path("/user/rust/about.json", "/r/rust"));
+	}
+
+	#[test]
+	fn byte_range_parses() {
+		for range in ["bytes=0-", "bytes=0-1023", "bytes=1048576-2097151", "bytes=-500", " bytes=5-5 "] {
+			assert_eq!(ByteRange::parse(range), ByteRange::Single(range.trim()), "{}", range);
+		}
+		for range in ["bytes=0-1,5-6", "bytes=0-,-500"] {
+			assert_eq!(ByteRange::parse(range), ByteRange::Multiple, "{}", range);
+		}
+		for range in ["", "bytes=", "bytes=-", "bytes=-0", "bytes=5-1", "bytes=a-b", "items=0-1", "bytes 0-1"] {
+			assert_eq!(ByteRange::parse(range), ByteRange::Invalid, "{}", range);
+		}
+	}
+}
@@ -19,6 +19,10 @@ pub(crate) static CONFIG: Lazy<Config> = Lazy::new(Config::load);
 // be the base of a link, to display removed content (on another site).
 pub(crate) const DEFAULT_PUSHSHIFT_FRONTEND: &str = "www.unddit.com";
 
+// The list of public instances maintained by the Libreddit project, in the
+// format of https://github.com/libreddit/libreddit-instances.
+pub(crate) const DEFAULT_INSTANCES_URL: &str = "https://raw.githubusercontent.com/libreddit/libreddit-instances/master/instances.json";
+
 /// Stores the configuration parsed from the environment variables and the
 /// config file. `Config::Default()` contains None for each setting.
 /// When adding more config settings, add it to `Config::load`,
@@ -74,8 +78,119 @@ pub struct Config {
 	#[serde(rename = "LIBREDDIT_ROBOTS_DISABLE_INDEXING")]
 	pub(crate) robots_disable_indexing: Option<String>,
 
+	#[serde(rename = "LIBREDDIT_ROBOTS_TXT")]
+	pub(crate) robots_txt: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_ROBOTS_NOINDEX_USERS")]
+	pub(crate) robots_noindex_users: Option<String>,
+
 	#[serde(rename = "LIBREDDIT_PUSHSHIFT_FRONTEND")]
 	pub(crate) pushshift: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEBUG_HEADERS")]
+	pub(crate) debug_headers: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_LOG_CLIENT_IP")]
+	pub(crate) log_client_ip: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_RESTORE_LINK_KEY")]
+	pub(crate) restore_link_key: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_ARCHIVE_API")]
+	pub(crate) archive_api: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_PROXY_MAX_SIZE")]
+	pub(crate) proxy_max_size: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_INSTANCES_URL")]
+	pub(crate) instances_url: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_PROXY_POLICIES")]
+	pub(crate) proxy_policies: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_TRANSLATE_URL")]
+	pub(crate) translate_url: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_EGRESS", skip_serializing)]
+	pub(crate) egress: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_MINIMAL_COOKIES")]
+	pub(crate) minimal_cookies: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_SEARCH_INDEX_URL")]
+	pub(crate) search_index_url: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_SEARCH_PROVIDER")]
+	pub(crate) search_provider: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_ADMIN_TOKEN", skip_serializing)]
+	pub(crate) admin_token: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_MEDIA_CACHE_TTL")]
+	pub(crate) media_cache_ttl: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_VIDEO_CACHE_TTL")]
+	pub(crate) video_cache_ttl: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_HIDE_INLINE_MEDIA")]
+	pub(crate) default_hide_inline_media: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_SHOW_HIDDEN")]
+	pub(crate) default_show_hidden: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_DATE_FORMAT")]
+	pub(crate) default_date_format: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_RENDER_MATH")]
+	pub(crate) default_render_math: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_DATA_SAVER")]
+	pub(crate) default_data_saver: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_READ_ONLY_BADGES")]
+	pub(crate) default_read_only_badges: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_LIMIT")]
+	pub(crate) default_limit: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_GEO_FILTER")]
+	pub(crate) default_geo_filter: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_BLUR_NSFW_TEXT")]
+	pub(crate) default_blur_nsfw_text: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_APP_HANDOFF")]
+	pub(crate) default_app_handoff: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_LINK_TITLES")]
+	pub(crate) default_link_titles: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_HIDE_SIDEBAR")]
+	pub(crate) default_hide_sidebar: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_COLLAPSE_INFO_PANELS")]
+	pub(crate) default_collapse_info_panels: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_COLLAPSE_SUBSCRIPTIONS")]
+	pub(crate) default_collapse_subscriptions: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_DIM_MEDIA")]
+	pub(crate) default_dim_media: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_FLAIR_ACCENTS")]
+	pub(crate) default_flair_accents: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_PREFERRED_RESOLUTION")]
+	pub(crate) default_preferred_resolution: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_FRONT_PAGE_SORT")]
+	pub(crate) default_front_page_sort: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_USER_SORT")]
+	pub(crate) default_user_sort: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_SEARCH_SORT")]
+	pub(crate) default_search_sort: Option<String>,
 }
 
 impl Config {
@@ -108,7 +223,44 @@ impl Config {
 			default_disable_visit_reddit_confirmation: parse("LIBREDDIT_DEFAULT_DISABLE_VISIT_REDDIT_CONFIRMATION"),
 			banner: parse("LIBREDDIT_BANNER"),
 			robots_disable_indexing: parse("LIBREDDIT_ROBOTS_DISABLE_INDEXING"),
+			robots_txt: parse("LIBREDDIT_ROBOTS_TXT"),
+			robots_noindex_users: parse("LIBREDDIT_ROBOTS_NOINDEX_USERS"),
 			pushshift: parse("LIBREDDIT_PUSHSHIFT_FRONTEND"),
+			debug_headers: parse("LIBREDDIT_DEBUG_HEADERS"),
+			log_client_ip: parse("LIBREDDIT_LOG_CLIENT_IP"),
+			restore_link_key: parse("LIBREDDIT_RESTORE_LINK_KEY"),
+			archive_api: parse("LIBREDDIT_ARCHIVE_API"),
+			proxy_max_size: parse("LIBREDDIT_PROXY_MAX_SIZE"),
+			instances_url: parse("LIBREDDIT_INSTANCES_URL"),
+			proxy_policies: parse("LIBREDDIT_PROXY_POLICIES"),
+			translate_url: parse("LIBREDDIT_TRANSLATE_URL"),
+			egress: parse("LIBREDDIT_EGRESS"),
+			minimal_cookies: parse("LIBREDDIT_MINIMAL_COOKIES"),
+			search_index_url: parse("LIBREDDIT_SEARCH_INDEX_URL"),
+			search_provider: parse("LIBREDDIT_SEARCH_PROVIDER"),
+			admin_token: parse("LIBREDDIT_ADMIN_TOKEN"),
+			media_cache_ttl: parse("LIBREDDIT_MEDIA_CACHE_TTL"),
+			video_cache_ttl: parse("LIBREDDIT_VIDEO_CACHE_TTL"),
+			default_hide_inline_media: parse("LIBREDDIT_DEFAULT_HIDE_INLINE_MEDIA"),
+			default_show_hidden: parse("LIBREDDIT_DEFAULT_SHOW_HIDDEN"),
+			default_date_format: parse("LIBREDDIT_DEFAULT_DATE_FORMAT"),
+			default_render_math: parse("LIBREDDIT_DEFAULT_RENDER_MATH"),
+			default_data_saver: parse("LIBREDDIT_DEFAULT_DATA_SAVER"),
+			default_read_only_badges: parse("LIBREDDIT_DEFAULT_READ_ONLY_BADGES"),
+			default_limit: parse("LIBREDDIT_DEFAULT_LIMIT"),
+			default_geo_filter: parse("LIBREDDIT_DEFAULT_GEO_FILTER"),
+			default_blur_nsfw_text: parse("LIBREDDIT_DEFAULT_BLUR_NSFW_TEXT"),
+			default_app_handoff: parse("LIBREDDIT_DEFAULT_APP_HANDOFF"),
+			default_link_titles: parse("LIBREDDIT_DEFAULT_LINK_TITLES"),
+			default_hide_sidebar: parse("LIBREDDIT_DEFAULT_HIDE_SIDEBAR"),
+			default_collapse_info_panels: parse("LIBREDDIT_DEFAULT_COLLAPSE_INFO_PANELS"),
+			default_collapse_subscriptions: parse("LIBREDDIT_DEFAULT_COLLAPSE_SUBSCRIPTIONS"),
+			default_dim_media: parse("LIBREDDIT_DEFAULT_DIM_MEDIA"),
+			default_flair_accents: parse("LIBREDDIT_DEFAULT_FLAIR_ACCENTS"),
+			default_preferred_resolution: parse("LIBREDDIT_DEFAULT_PREFERRED_RESOLUTION"),
+			default_front_page_sort: parse("LIBREDDIT_DEFAULT_FRONT_PAGE_SORT"),
+			default_user_sort: parse("LIBREDDIT_DEFAULT_USER_SORT"),
+			default_search_sort: parse("LIBREDDIT_DEFAULT_SEARCH_SORT"),
 		}
 	}
 }
@@ -131,7 +283,44 @@ fn get_setting_from_config(name: &str, config: &Config) -> Option<String> {
 		"LIBREDDIT_DEFAULT_DISABLE_VISIT_REDDIT_CONFIRMATION" => config.default_disable_visit_reddit_confirmation.clone(),
 		"LIBREDDIT_BANNER" => config.banner.clone(),
 		"LIBREDDIT_ROBOTS_DISABLE_INDEXING" => config.robots_disable_indexing.clone(),
+		"LIBREDDIT_ROBOTS_TXT" => config.robots_txt.clone(),
+		"LIBREDDIT_ROBOTS_NOINDEX_USERS" => config.robots_noindex_users.clone(),
 		"LIBREDDIT_PUSHSHIFT_FRONTEND" => config.pushshift.clone(),
+		"LIBREDDIT_DEBUG_HEADERS" => config.debug_headers.clone(),
+		"LIBREDDIT_LOG_CLIENT_IP" => config.log_client_ip.clone(),
+		"LIBREDDIT_RESTORE_LINK_KEY" => config.restore_link_key.clone(),
+		"LIBREDDIT_ARCHIVE_API" => config.archive_api.clone(),
+		"LIBREDDIT_PROXY_MAX_SIZE" => config.proxy_max_size.clone(),
+		"LIBREDDIT_INSTANCES_URL" => config.instances_url.clone(),
+		"LIBREDDIT_PROXY_POLICIES" => config.proxy_policies.clone(),
+		"LIBREDDIT_TRANSLATE_URL" => config.translate_url.clone(),
+		"LIBREDDIT_EGRESS" => config.egress.clone(),
+		"LIBREDDIT_MINIMAL_COOKIES" => config.minimal_cookies.clone(),
+		"LIBREDDIT_SEARCH_INDEX_URL" => config.search_index_url.clone(),
+		"LIBREDDIT_SEARCH_PROVIDER" => config.search_provider.clone(),
+		"LIBREDDIT_ADMIN_TOKEN" => config.admin_token.clone(),
+		"LIBREDDIT_MEDIA_CACHE_TTL" => config.media_cache_ttl.clone(),
+		"LIBREDDIT_VIDEO_CACHE_TTL" => config.video_cache_ttl.clone(),
+		"LIBREDDIT_DEFAULT_HIDE_INLINE_MEDIA" => config.default_hide_inline_media.clone(),
+		"LIBREDDIT_DEFAULT_SHOW_HIDDEN" => config.default_show_hidden.clone(),
+		"LIBREDDIT_DEFAULT_DATE_FORMAT" => config.default_date_format.clone(),
+		"LIBREDDIT_DEFAULT_RENDER_MATH" => config.default_render_math.clone(),
+		"LIBREDDIT_DEFAULT_DATA_SAVER" => config.default_data_saver.clone(),
+		"LIBREDDIT_DEFAULT_READ_ONLY_BADGES" => config.default_read_only_badges.clone(),
+		"LIBREDDIT_DEFAULT_LIMIT" => config.default_limit.clone(),
+		"LIBREDDIT_DEFAULT_GEO_FILTER" => config.default_geo_filter.clone(),
+		"LIBREDDIT_DEFAULT_BLUR_NSFW_TEXT" => config.default_blur_nsfw_text.clone(),
+		"LIBREDDIT_DEFAULT_APP_HANDOFF" => config.default_app_handoff.clone(),
+		"LIBREDDIT_DEFAULT_LINK_TITLES" => config.default_link_titles.clone(),
+		"LIBREDDIT_DEFAULT_HIDE_SIDEBAR" => config.default_hide_sidebar.clone(),
+		"LIBREDDIT_DEFAULT_COLLAPSE_INFO_PANELS" => config.default_collapse_info_panels.clone(),
+		"LIBREDDIT_DEFAULT_COLLAPSE_SUBSCRIPTIONS" => config.default_collapse_subscriptions.clone(),
+		"LIBREDDIT_DEFAULT_DIM_MEDIA" => config.default_dim_media.clone(),
+		"LIBREDDIT_DEFAULT_FLAIR_ACCENTS" => config.default_flair_accents.clone(),
+		"LIBREDDIT_DEFAULT_PREFERRED_RESOLUTION" => config.default_preferred_resolution.clone(),
+		"LIBREDDIT_DEFAULT_FRONT_PAGE_SORT" => config.default_front_page_sort.clone(),
+		"LIBREDDIT_DEFAULT_USER_SORT" => config.default_user_sort.clone(),
+		"LIBREDDIT_DEFAULT_SEARCH_SORT" => config.default_search_sort.clone(),
 		_ => None,
 	}
 }
@@ -0,0 +1,127 @@
+// Anonymous usage counters. Only the number of requests per page type is
+// kept, never paths, parameters, addresses or anything else tied to a
+// visitor. Counts are batched in memory and added to totals in KV storage, so
+// the numbers are approximate when several isolates flush at once.
+
+// CRATES
+use crate::{kv, server::PROXY_PREFIXES};
+use build_html::{Html, Table};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Kinds of pages that are counted, as (key, label) pairs.
+pub(crate) const PAGE_TYPES: [(&str, &str); 8] = [
+	("front", "Front page"),
+	("subreddit", "Subreddits"),
+	("post", "Posts"),
+	("user", "Users"),
+	("search", "Search"),
+	("wiki", "Wikis"),
+	("settings", "Settings"),
+	("other", "Other"),
+];
+
+// Counted requests between writes to KV storage
+const FLUSH_EVERY: u64 = 25;
+
+// How long totals are kept in KV storage after the last write, in seconds
+const TOTAL_TTL: u32 = 365 * 24 * 60 * 60;
+
+#[derive(Default)]
+struct Counts {
+	// Requests not yet added to the totals in KV storage
+	pending: HashMap<&'static str, u64>,
+
+	// Requests served since the instance started
+	local: HashMap<&'static str, u64>,
+}
+
+static COUNTS: Lazy<Mutex<Counts>> = Lazy::new(|| Mutex::new(Counts::default()));
+
+/// The kind of page at `path`, or `None` if requests to it aren't counted.
+pub(crate) fn page_type(path: &str) -> Option<&'static str> {
+	let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+	Some(match segments.as_slice() {
+		[""] | ["best" | "hot" | "new" | "top" | "rising" | "controversial"] => "front",
+		// Proxied media and static files aren't pages
+		[first, ..] if PROXY_PREFIXES.contains(first) => return None,
+		[file] if file.contains('.') => return None,
+		["search"] | ["r" | "user", _, "search"] => "search",
+		["comments", ..] | [_, _, "comments", ..] => "post",
+		["wiki" | "w", ..] | ["r", _, "wiki" | "w", ..] => "wiki",
+		["r", ..] => "subreddit",
+		["user" | "u", ..] => "user",
+		["settings" | "subscriptions", ..] => "settings",
+		_ => "other",
+	})
+}
+
+/// Counts a request to `path`. Returns true when the pending counts should
+/// be flushed to KV storage.
+pub(crate) fn record(path: &str) -> bool {
+	let (page, mut counts) = match (page_type(path), COUNTS.lock()) {
+		(Some(page), Ok(counts)) => (page, counts),
+		_ => return false,
+	};
+
+	*counts.local.entry(page).or_default() += 1;
+	*counts.pending.entry(page).or_default() += 1;
+
+	counts.pending.values().sum::<u64>() >= FLUSH_EVERY
+}
+
+/// Adds the pending counts to the totals in KV storage.
+pub(crate) async fn flush() {
+	let pending: Vec<(&str, u64)> = match COUNTS.lock() {
+		Ok(mut counts) => counts.pending.drain().collect(),
+		Err(_) => return,
+	};
+
+	for (page, count) in pending {
+		let key = format!("counter:{page}");
+		let total = kv::get(&key).await.and_then(|total| total.parse::<u64>().ok()).unwrap_or_default();
+		kv::put(&key, &(total + count).to_string(), TOTAL_TTL).await;
+	}
+}
+
+/// An HTML table of requests served per page type. Instances without KV
+/// storage only know about requests since they started.
+pub(crate) async fn table() -> String {
+	flush().await;
+
+	let local = COUNTS.lock().map(|counts| counts.local.clone()).unwrap_or_default();
+
+	let mut rows = Vec::new();
+	let mut totals = (0, 0);
+	for (page, label) in PAGE_TYPES {
+		let since_start = local.get(page).copied().unwrap_or_default();
+		let all_time = kv::get(&format!("counter:{page}")).await.and_then(|total| total.parse::<u64>().ok()).unwrap_or(since_start);
+		totals = (totals.0 + since_start, totals.1 + all_time);
+		rows.push([label.to_string(), since_start.to_string(), all_time.to_string()]);
+	}
+	rows.push(["Total".to_string(), totals.0.to_string(), totals.1.to_string()]);
+
+	Table::from(rows).with_header_row(["Page", "Since restart", "All time"]).to_html_string()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::page_type;
+
+	#[test]
+	fn page_type_works() {
+		assert_eq!(page_type("/"), Some("front"));
+		assert_eq!(page_type("/top"), Some("front"));
+		assert_eq!(page_type("/r/rust"), Some("subreddit"));
+		assert_eq!(page_type("/r/rust/comments/abc/title"), Some("post"));
+		assert_eq!(page_type("/user/spez/comments/abc"), Some("post"));
+		assert_eq!(page_type("/r/rust/search"), Some("search"));
+		assert_eq!(page_type("/r/rust/wiki/index"), Some("wiki"));
+		assert_eq!(page_type("/user/spez"), Some("user"));
+		assert_eq!(page_type("/settings/update"), Some("settings"));
+		assert_eq!(page_type("/img/abc.jpg"), None);
+		assert_eq!(page_type("/style.css"), None);
+	}
+}
@@ -0,0 +1,233 @@
+// Digests of the top posts in a set of subscriptions over the last day or
+// week. Creating one stores the subscriptions in KV storage under a random
+// token, and `/digest/:token` shows the digest without needing cookies, so it
+// can be bookmarked or fetched by an emailing service. Digests are refreshed
+// when they're viewed after their period has passed, and a few at a time by
+// the scheduled task.
+
+// CRATES
+use crate::kv;
+use crate::server::RequestExt;
+use crate::settings::same_origin;
+use crate::utils::{error, format_num, promise, redirect, template, time, wasm_error, Post, Preferences};
+use askama::Template;
+use js_sys::Date;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use web_sys::{FormData, Request, Response};
+
+#[wasm_bindgen]
+extern "C" {
+	#[wasm_bindgen(js_namespace = crypto, js_name = getRandomValues, catch)]
+	fn get_random_values(buf: &mut [u8]) -> Result<JsValue, JsValue>;
+}
+
+// How long a digest is kept after it was last viewed, in seconds, and how
+// often viewing it pushes that back
+const PROFILE_TTL: u32 = 90 * 24 * 60 * 60;
+const TOUCH_INTERVAL: f64 = 24.0 * 60.0 * 60.0;
+
+// Key of the list of digest tokens, walked by the scheduled task
+const INDEX_KEY: &str = "digest_tokens";
+
+// Most digests the instance keeps, and how many the scheduled task refreshes
+// each time it runs, each costing a request to Reddit
+const MAX_DIGESTS: usize = 500;
+const REFRESH_BATCH: usize = 10;
+
+// Most subreddits a digest covers, to keep the listing URL reasonable
+const MAX_SUBREDDITS: usize = 50;
+
+// Posts shown in a digest
+const DIGEST_SIZE: usize = 25;
+
+// What a digest covers, stored under `digest:{token}`
+#[derive(Serialize, Deserialize)]
+struct Profile {
+	subreddits: Vec<String>,
+	/// "day" or "week"
+	period: String,
+	/// UNIX timestamp of when the digest was last kept alive
+	#[serde(default)]
+	touched: f64,
+}
+
+// A generated digest, stored under `digest_posts:{token}`
+#[derive(Serialize, Deserialize)]
+struct Generated {
+	/// UNIX timestamp of the generation
+	generated: f64,
+	posts: Vec<DigestPost>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DigestPost {
+	title: String,
+	permalink: String,
+	subreddit: String,
+	score: String,
+	comments: String,
+}
+
+#[derive(Template)]
+#[template(path = "digest.html")]
+struct DigestTemplate {
+	period: String,
+	generated: String,
+	posts: Vec<DigestPost>,
+	prefs: Preferences,
+	url: String,
+}
+
+impl Profile {
+	// Seconds between refreshes
+	fn period_secs(&self) -> f64 {
+		if self.period == "week" {
+			7.0 * 24.0 * 60.0 * 60.0
+		} else {
+			24.0 * 60.0 * 60.0
+		}
+	}
+}
+
+/// Creates a digest of the visitor's subscribed subreddits and redirects to
+/// it.
+pub async fn create(req: Request) -> Result<Response, String> {
+	if !same_origin(&req) {
+		return Err("Digests can only be created from this instance".to_string());
+	}
+
+	let form = promise::<FormData>(req.form_data().map_err(wasm_error)?).await?;
+	let period = match form.get("period").as_string().as_deref() {
+		Some("week") => "week",
+		_ => "day",
+	};
+
+	let subreddits: Vec<String> = Preferences::new(&req)
+		.subscriptions
+		.into_iter()
+		.filter(|sub| !sub.starts_with("u_"))
+		.take(MAX_SUBREDDITS)
+		.collect();
+	if subreddits.is_empty() {
+		return error(req, "Subscribe to some subreddits to create a digest").await;
+	}
+
+	let mut tokens = get_json::<Vec<String>>(INDEX_KEY).await.unwrap_or_default();
+	if tokens.len() >= MAX_DIGESTS {
+		return error(req, "This instance can't keep any more digests").await;
+	}
+
+	let token = new_token()?;
+	let profile = Profile {
+		subreddits,
+		period: period.to_string(),
+		touched: now(),
+	};
+	put_json(&format!("digest:{token}"), &profile, PROFILE_TTL).await;
+
+	tokens.push(token.clone());
+	put_json(INDEX_KEY, &tokens, PROFILE_TTL).await;
+
+	Ok(redirect(format!("/digest/{token}")))
+}
+
+/// Shows a digest, generating it first if it's missing or out of date.
+pub async fn page(req: Request) -> Result<Response, String> {
+	let token = req.param("token").unwrap_or_default();
+	let mut profile = match get_json::<Profile>(&format!("digest:{token}")).await {
+		Some(profile) => profile,
+		None => return error(req, "This digest doesn't exist or has expired").await,
+	};
+
+	// Viewing a digest keeps it alive
+	if profile.touched + TOUCH_INTERVAL <= now() {
+		profile.touched = now();
+		put_json(&format!("digest:{token}"), &profile, PROFILE_TTL).await;
+	}
+
+	let digest = match get_json::<Generated>(&format!("digest_posts:{token}")).await {
+		Some(digest) if digest.generated + profile.period_secs() > now() => digest,
+		_ => generate(&token, &profile).await?,
+	};
+
+	template(DigestTemplate {
+		period: profile.period,
+		generated: time(digest.generated).0,
+		posts: digest.posts,
+		prefs: Preferences::new(&req),
+		url: req.url(),
+	})
+}
+
+/// Regenerates the next few digests whose period has passed, and forgets
+/// expired ones. Run by the host's scheduler, which gets through every digest
+/// over a few runs.
+pub async fn refresh_batch() {
+	let mut tokens = get_json::<Vec<String>>(INDEX_KEY).await.unwrap_or_default();
+	let batch: Vec<String> = tokens.drain(..REFRESH_BATCH.min(tokens.len())).collect();
+
+	// The batch goes to the back of the list, for the next runs to get to the
+	// others first
+	let mut live = tokens;
+	for token in batch {
+		let profile = match get_json::<Profile>(&format!("digest:{token}")).await {
+			Some(profile) => profile,
+			None => continue,
+		};
+
+		let stale = get_json::<Generated>(&format!("digest_posts:{token}"))
+			.await
+			.is_none_or(|digest| digest.generated + profile.period_secs() <= now());
+		if stale {
+			generate(&token, &profile).await.ok();
+		}
+		live.push(token);
+	}
+
+	put_json(INDEX_KEY, &live, PROFILE_TTL).await;
+}
+
+// Fetch the top posts of the period and store them
+async fn generate(token: &str, profile: &Profile) -> Result<Generated, String> {
+	let path = format!("/r/{}/top.json?t={}&limit={}&raw_json=1", profile.subreddits.join("+"), profile.period, DIGEST_SIZE);
+	let (posts, _) = Post::fetch(&path, false).await?;
+
+	let digest = Generated {
+		generated: now(),
+		posts: posts
+			.into_iter()
+			.map(|post| DigestPost {
+				title: post.title,
+				permalink: post.permalink,
+				subreddit: post.community,
+				score: post.score.0,
+				comments: format_num(post.comments.1.parse().unwrap_or_default()).0,
+			})
+			.collect(),
+	};
+	put_json(&format!("digest_posts:{token}"), &digest, profile.period_secs() as u32 * 2).await;
+
+	Ok(digest)
+}
+
+// An unguessable token for a new digest
+fn new_token() -> Result<String, String> {
+	let mut bytes = [0u8; 16];
+	get_random_values(&mut bytes).map_err(wasm_error)?;
+	Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+fn now() -> f64 {
+	Date::now() / 1000.0
+}
+
+async fn get_json<T: for<'de> Deserialize<'de>>(key: &str) -> Option<T> {
+	serde_json::from_str(&kv::get(key).await?).ok()
+}
+
+async fn put_json(key: &str, value: &impl Serialize, ttl: u32) {
+	if let Ok(json) = serde_json::to_string(value) {
+		kv::put(key, &json, ttl).await;
+	}
+}
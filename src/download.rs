@@ -0,0 +1,130 @@
+// Media downloads. They're proxied like the media shown in posts, but saved
+// by the browser under a name taken from the post's title.
+
+// CRATES
+use crate::client::stream;
+use crate::server::RequestExt;
+use crate::utils::error;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use web_sys::{Request, Response};
+
+// Longest file name given to downloads, in characters, before the extension
+const MAX_NAME_LENGTH: usize = 80;
+
+/// Proxies the media at `/download/:kind/*path`, where `kind` and `path`
+/// name it like the proxy routes do, as an attachment named after
+/// `?title=`.
+pub async fn download(req: Request) -> Result<Response, String> {
+	let kind = req.param("kind").unwrap_or_default();
+	let path = req.param("path").unwrap_or_default();
+
+	let upstream = match upstream(&kind, &path) {
+		Some(upstream) => upstream,
+		None => return error(req, "This media can't be downloaded").await,
+	};
+
+	// Everything else in the query belongs to the media's URL
+	let uri = req.uri();
+	let params = uri.search_params();
+	let title = params.get("title").unwrap_or_default();
+	let force = params.get("force").as_deref() == Some("1");
+	for key in ["title", "force"] {
+		params.delete(key);
+	}
+
+	let res = stream(&format!("{}{}", upstream, uri.search()), &req, force, None).await?;
+	if matches!(res.status(), 200 | 206) {
+		let content_type = res.headers().get("content-type").ok().flatten().unwrap_or_default();
+		res
+			.headers()
+			.set("Content-Disposition", &content_disposition(&filename(&title, extension(&content_type, &path))))
+			.ok();
+	}
+
+	Ok(res)
+}
+
+// The media behind a download path, matching the proxy routes of the same
+// name
+fn upstream(kind: &str, path: &str) -> Option<String> {
+	match kind {
+		"img" => Some(format!("https://i.redd.it/{}", path)),
+		"vid" => path.split_once('/').map(|(id, size)| format!("https://v.redd.it/{}/DASH_{}", id, size)),
+		"preview" => path.split_once('/').map(|(loc, id)| format!("https://{}view.redd.it/{}", loc, id)),
+		_ => None,
+	}
+}
+
+// The extension for media of `content_type`, or else of the file at `path`.
+// Reddit serves GIFs as MP4 videos under their `.gif` names.
+fn extension<'a>(content_type: &str, path: &'a str) -> Option<&'a str> {
+	let known = match content_type.split(';').next().unwrap_or_default().trim() {
+		"video/mp4" => Some("mp4"),
+		"image/jpeg" => Some("jpg"),
+		"image/png" => Some("png"),
+		"image/gif" => Some("gif"),
+		"image/webp" => Some("webp"),
+		_ => None,
+	};
+
+	known.or_else(|| {
+		path
+			.rsplit('/')
+			.next()
+			.and_then(|file| file.rsplit_once('.'))
+			.map(|(_, extension)| extension)
+			.filter(|extension| !extension.is_empty() && extension.len() <= 4 && extension.chars().all(|c| c.is_ascii_alphanumeric()))
+	})
+}
+
+// A file name made of the words of `title`
+fn filename(title: &str, extension: Option<&str>) -> String {
+	let words: Vec<&str> = title.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty()).collect();
+	let mut name: String = words.join("_").chars().take(MAX_NAME_LENGTH).collect();
+	if name.is_empty() {
+		name = "media".to_string();
+	}
+
+	match extension {
+		Some(extension) => format!("{}.{}", name, extension.to_ascii_lowercase()),
+		None => name,
+	}
+}
+
+// Browsers that don't understand `filename*` fall back to an ASCII-only name
+fn content_disposition(filename: &str) -> String {
+	let ascii: String = filename.chars().map(|c| if c.is_ascii() { c } else { '_' }).collect();
+	format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", ascii, utf8_percent_encode(filename, NON_ALPHANUMERIC))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{content_disposition, extension, filename, upstream};
+
+	#[test]
+	fn upstream_works() {
+		assert_eq!(upstream("img", "abc.jpg").as_deref(), Some("https://i.redd.it/abc.jpg"));
+		assert_eq!(upstream("vid", "abc/720.mp4").as_deref(), Some("https://v.redd.it/abc/DASH_720.mp4"));
+		assert_eq!(upstream("preview", "pre/abc.gif").as_deref(), Some("https://preview.redd.it/abc.gif"));
+		assert_eq!(upstream("vid", "abc"), None);
+		assert_eq!(upstream("style", "abc.png"), None);
+	}
+
+	#[test]
+	fn filename_works() {
+		assert_eq!(extension("video/mp4", "pre/abc.gif"), Some("mp4"));
+		assert_eq!(extension("", "abc/720.mp4"), Some("mp4"));
+		assert_eq!(extension("application/octet-stream", "abc/720"), None);
+		assert_eq!(filename("My cat, at 3 a.m.!", Some("mp4")), "My_cat_at_3_a_m.mp4");
+		assert_eq!(filename("", Some("JPG")), "media.jpg");
+		assert_eq!(filename("Café", Some("gif")), "Café.gif");
+		assert_eq!(filename("No extension", None), "No_extension");
+		assert_eq!(filename(&"a".repeat(200), Some("png")).len(), 84);
+	}
+
+	#[test]
+	fn content_disposition_works() {
+		assert_eq!(content_disposition("cat.mp4"), "attachment; filename=\"cat.mp4\"; filename*=UTF-8''cat%2Emp4");
+		assert_eq!(content_disposition("Café.gif"), "attachment; filename=\"Caf_.gif\"; filename*=UTF-8''Caf%C3%A9%2Egif");
+	}
+}
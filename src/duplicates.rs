@@ -50,10 +50,27 @@ struct DuplicatesTemplate {
 	all_posts_filtered: bool,
 }
 
+impl DuplicatesTemplate {
+	// How many more (or fewer) comments a duplicate has than this post
+	fn comment_delta(&self, duplicate: &Post) -> String {
+		let count = |post: &Post| post.comments.1.parse::<i64>().unwrap_or_default();
+		match count(duplicate) - count(&self.post) {
+			0 => "±0".to_string(),
+			delta => format!("{:+}", delta),
+		}
+	}
+}
+
 /// Make the GET request to Reddit. It assumes `req` is the appropriate Reddit
 /// REST endpoint for enumerating post duplicates.
 pub async fn item(req: Request) -> Result<Response, String> {
-	let path: String = format!("{}.json{}&raw_json=1", req.uri().pathname(), req.uri().search());
+	// Reddit only sorts duplicates by comments or date, so sorting by score
+	// is done here, a page at a time
+	let params = req.uri().search_params();
+	if params.get("sort").as_deref() == Some("score") {
+		params.set("sort", "num_comments");
+	}
+	let path: String = format!("{}.json?{}&raw_json=1", req.uri().pathname(), String::from(params.to_string()));
 	let sub = req.param("sub").unwrap_or_default();
 	let quarantined = can_access_quarantine(&req, &sub);
 
@@ -76,7 +93,7 @@ pub async fn item(req: Request) -> Result<Response, String> {
 			}
 
 			let filters = get_filters(&req);
-			let (duplicates, num_posts_filtered, all_posts_filtered) = parse_duplicates(&response[1], &filters).await;
+			let (mut duplicates, num_posts_filtered, all_posts_filtered) = parse_duplicates(&response[1], &filters).await;
 
 			// These are the values for the "before=", "after=", and "sort="
 			// query params, respectively.
@@ -141,7 +158,7 @@ pub async fn item(req: Request) -> Result<Response, String> {
 							"sort" => {
 								let val: &str = kv[1];
 								match val {
-									"new" | "num_comments" => sort = val.to_string(),
+									"new" | "num_comments" | "score" => sort = val.to_string(),
 									_ => {}
 								}
 							}
@@ -177,7 +194,7 @@ pub async fn item(req: Request) -> Result<Response, String> {
 						"{}.json?before=t3_{}&sort={}&limit=1&raw_json=1",
 						req.uri().pathname(),
 						&duplicates[0].id,
-						if sort.is_empty() { "num_comments".to_string() } else { sort.clone() }
+						if sort == "new" { "new" } else { "num_comments" }
 					);
 					match json(new_path, true).await {
 						Ok(response) => {
@@ -197,6 +214,10 @@ pub async fn item(req: Request) -> Result<Response, String> {
 				}
 			}
 
+			if sort == "score" {
+				duplicates.sort_by_key(|post| std::cmp::Reverse(post.score.1.parse::<i64>().unwrap_or_default()));
+			}
+
 			template(DuplicatesTemplate {
 				params: DuplicatesParams { before, after, sort },
 				post,
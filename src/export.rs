@@ -0,0 +1,313 @@
+// Self-contained HTML snapshots of threads, for archiving discussions. The
+// snapshot works offline: styles are inlined and proxied images are embedded
+// as data URIs, up to a size cap. Threads can also be exported as Markdown or
+// plain text, for quoting elsewhere.
+
+// CRATES
+use crate::client::fetch_media;
+use crate::server::RequestExt;
+use crate::utils::wasm_error;
+use once_cell::sync::Lazy;
+use regex::{NoExpand, Regex};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use web_sys::{Request, Response};
+
+// Largest image, in bytes, embedded in a snapshot
+const MAX_IMAGE_SIZE: usize = 512 * 1024;
+
+// Largest total size, in bytes, of the images embedded in a snapshot
+const MAX_TOTAL_SIZE: usize = 8 * 1024 * 1024;
+
+// Most images fetched for a snapshot, whether or not they end up embedded
+const MAX_FETCHES: usize = 50;
+
+// Deepest level of replies in a text export
+const MAX_TEXT_DEPTH: usize = 10;
+
+/// Text formats a thread can be exported in, picked with `?export=`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TextFormat {
+	Markdown,
+	Plain,
+}
+
+impl TextFormat {
+	pub fn from_param(param: &str) -> Option<Self> {
+		match param {
+			"markdown" => Some(Self::Markdown),
+			"text" => Some(Self::Plain),
+			_ => None,
+		}
+	}
+
+	fn extension(self) -> &'static str {
+		match self {
+			Self::Markdown => "md",
+			Self::Plain => "txt",
+		}
+	}
+
+	fn content_type(self) -> &'static str {
+		match self {
+			Self::Markdown => "text/markdown; charset=utf-8",
+			Self::Plain => "text/plain; charset=utf-8",
+		}
+	}
+}
+
+static SCRIPT_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"<script[^>]*></script>").unwrap());
+static STYLESHEET_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"<link rel="stylesheet"[^>]*>"#).unwrap());
+
+// Matches attributes holding a path on this instance, which may be
+// HTML-escaped
+static LOCAL_PATH_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\b(src|href|poster|action)="((?:/|&#x2f;)[^"]*)""#).unwrap());
+
+// Upstream hosts of the media proxy routes, by path prefix
+const MEDIA_ROUTES: [(&str, &str); 5] = [
+	("/img/", "https://i.redd.it/"),
+	("/emoji/", "https://emoji.redditmedia.com/"),
+	("/style/", "https://styles.redditmedia.com/"),
+	("/static/", "https://www.redditstatic.com/"),
+	("/emote/", "https://reddit-econ-prod-assets-permanent.s3.amazonaws.com/"),
+];
+
+/// Turns a rendered page into a downloadable, self-contained snapshot.
+pub async fn snapshot(html: &str, req: &Request, filename: &str) -> Result<Response, String> {
+	let origin = req.uri().origin();
+
+	let html = SCRIPT_REGEX.replace_all(html, "");
+	let html = STYLESHEET_REGEX.replace(&html, NoExpand(&format!("<style>{}</style>", crate::stylesheet())));
+
+	// Fetch each proxied image once, remembering the ones that can't be
+	// embedded so they aren't fetched again
+	let mut embedded: HashMap<String, String> = HashMap::new();
+	let mut rejected: HashSet<String> = HashSet::new();
+	let mut total = 0;
+	let mut fetches = 0;
+	for caps in LOCAL_PATH_REGEX.captures_iter(&html) {
+		if total >= MAX_TOTAL_SIZE || fetches >= MAX_FETCHES {
+			break;
+		}
+		let path = unescape(&caps[2]);
+		if embedded.contains_key(&path) || rejected.contains(&path) {
+			continue;
+		}
+		let url = match upstream_url(&path) {
+			Some(url) => url,
+			None => continue,
+		};
+
+		fetches += 1;
+		match fetch_media(&url, MAX_IMAGE_SIZE.min(MAX_TOTAL_SIZE - total)).await {
+			Ok((content_type, bytes)) if content_type.starts_with("image/") => {
+				total += bytes.len();
+				embedded.insert(path, format!("data:{};base64,{}", content_type, base64(&bytes)));
+			}
+			_ => {
+				rejected.insert(path);
+			}
+		}
+	}
+
+	// Embed the fetched images, and point every other local path back at
+	// this instance
+	let html = LOCAL_PATH_REGEX.replace_all(&html, |caps: &regex::Captures| {
+		let path = unescape(&caps[2]);
+		match embedded.get(&path) {
+			Some(data) => format!("{}=\"{}\"", &caps[1], data),
+			None if !path.starts_with("//") => format!("{}=\"{}{}\"", &caps[1], origin, &caps[2]),
+			None => caps[0].to_string(),
+		}
+	});
+
+	let response = Response::new_with_opt_str(Some(&html)).map_err(wasm_error)?;
+	response.headers().set("content-type", "text/html; charset=utf-8").ok();
+	response.headers().set("content-disposition", &format!("attachment; filename=\"{}\"", filename)).ok();
+	Ok(response)
+}
+
+/// Exports a thread, from Reddit's JSON for the post and its comment listing,
+/// as Markdown or plain text. `depth` is how many levels of comments to
+/// include, as given by `?depth=`, and comments by filtered users are left
+/// out.
+pub fn thread_text(post: &Value, comments: &Value, format: TextFormat, depth: Option<&str>, filters: &HashSet<String>) -> String {
+	let field = |key: &str| post[key].as_str().unwrap_or_default();
+	let mut text = match format {
+		TextFormat::Markdown => format!("# {}\n\n", field("title")),
+		TextFormat::Plain => format!("{}\n\n", field("title")),
+	};
+	text.push_str(&format!(
+		"Posted by u/{} in r/{}{}\nhttps://www.reddit.com{}\n\n",
+		field("author"),
+		field("subreddit"),
+		points(post),
+		field("permalink")
+	));
+	if !field("selftext").is_empty() {
+		text.push_str(&format!("{}\n\n", field("selftext")));
+	} else if !post["is_self"].as_bool().unwrap_or_default() {
+		text.push_str(&format!("{}\n\n", field("url")));
+	}
+	text.push_str("---\n\n");
+
+	let depth = depth.and_then(|depth| depth.parse().ok()).unwrap_or(MAX_TEXT_DEPTH).min(MAX_TEXT_DEPTH);
+	push_comments(&mut text, comments, format, 0, depth, filters);
+	text
+}
+
+/// Serves a text export of a thread, shown in the browser but saved as
+/// `filename` with the format's extension.
+pub fn text_response(text: &str, format: TextFormat, filename: &str) -> Result<Response, String> {
+	let response = Response::new_with_opt_str(Some(text)).map_err(wasm_error)?;
+	response.headers().set("content-type", format.content_type()).ok();
+	response
+		.headers()
+		.set("content-disposition", &format!("inline; filename=\"{}.{}\"", filename, format.extension()))
+		.ok();
+	Ok(response)
+}
+
+// Append the comments of a listing and their replies, quoted (Markdown) or
+// indented (plain text) by how deep they are
+fn push_comments(text: &mut String, listing: &Value, format: TextFormat, level: usize, depth: usize, filters: &HashSet<String>) {
+	if level >= depth {
+		return;
+	}
+
+	let prefix = match format {
+		TextFormat::Markdown => "> ".repeat(level),
+		TextFormat::Plain => "    ".repeat(level),
+	};
+	for comment in listing["data"]["children"].as_array().into_iter().flatten() {
+		let data = &comment["data"];
+		if comment["kind"] == "more" {
+			let count = data["count"].as_i64().unwrap_or_default();
+			if count > 0 {
+				push_lines(text, &prefix, &format!("[{} more {}]\n", count, if count == 1 { "reply" } else { "replies" }));
+			}
+			continue;
+		}
+
+		let author = data["author"].as_str().unwrap_or_default();
+		if filters.contains(&format!("u_{}", author)) {
+			continue;
+		}
+		let header = match format {
+			TextFormat::Markdown => format!("**u/{}**{}", author, points(data)),
+			TextFormat::Plain => format!("u/{}{}:", author, points(data)),
+		};
+		push_lines(text, &prefix, &format!("{}\n\n{}\n", header, data["body"].as_str().unwrap_or_default()));
+		push_comments(text, &data["replies"], format, level + 1, depth, filters);
+	}
+}
+
+// Append each line of `lines` after `prefix`, without trailing whitespace on
+// empty lines
+fn push_lines(text: &mut String, prefix: &str, lines: &str) {
+	for line in lines.split('\n') {
+		if line.is_empty() {
+			text.push_str(prefix.trim_end());
+		} else {
+			text.push_str(prefix);
+			text.push_str(line);
+		}
+		text.push('\n');
+	}
+}
+
+// The score of a post or comment, like " (12 points)", unless it's hidden
+fn points(data: &Value) -> String {
+	if data["score_hidden"].as_bool().unwrap_or_default() {
+		return String::new();
+	}
+	match data["score"].as_i64().unwrap_or_default() {
+		1 => " (1 point)".to_string(),
+		score => format!(" ({} points)", score),
+	}
+}
+
+// Map a media proxy path on this instance to the upstream URL it serves
+fn upstream_url(path: &str) -> Option<String> {
+	if let Some(rest) = path.strip_prefix("/thumb/") {
+		let (point, id) = rest.split_once('/')?;
+		return Some(format!("https://{}.thumbs.redditmedia.com/{}", point, id));
+	}
+
+	// "/preview/pre/..." is served from "preview.redd.it/..."
+	if let Some(rest) = path.strip_prefix("/preview/") {
+		let (loc, id) = rest.split_once('/')?;
+		return Some(format!("https://{}view.redd.it/{}", loc, id));
+	}
+
+	let (prefix, host) = MEDIA_ROUTES.iter().find(|(prefix, _)| path.starts_with(prefix))?;
+	Some(format!("{}{}", host, &path[prefix.len()..]))
+}
+
+fn unescape(text: &str) -> String {
+	text.replace("&#x2f;", "/").replace("&#x27;", "'").replace("&quot;", "\"").replace("&amp;", "&")
+}
+
+fn base64(bytes: &[u8]) -> String {
+	const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+	let mut output = String::with_capacity(bytes.len().div_ceil(3) * 4);
+	for chunk in bytes.chunks(3) {
+		let n = ((chunk[0] as u32) << 16) | ((*chunk.get(1).unwrap_or(&0) as u32) << 8) | (*chunk.get(2).unwrap_or(&0) as u32);
+		for i in 0..4 {
+			if i <= chunk.len() {
+				output.push(ALPHABET[((n >> (18 - 6 * i)) & 63) as usize] as char);
+			} else {
+				output.push('=');
+			}
+		}
+	}
+	output
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{base64, thread_text, upstream_url, TextFormat};
+	use serde_json::json;
+	use std::collections::HashSet;
+
+	#[test]
+	fn base64_works() {
+		assert_eq!(base64(b""), "");
+		assert_eq!(base64(b"f"), "Zg==");
+		assert_eq!(base64(b"fo"), "Zm8=");
+		assert_eq!(base64(b"foo"), "Zm9v");
+		assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+	}
+
+	#[test]
+	fn upstream_url_works() {
+		assert_eq!(upstream_url("/img/abc.jpg").as_deref(), Some("https://i.redd.it/abc.jpg"));
+		assert_eq!(upstream_url("/thumb/a/xyz.jpg").as_deref(), Some("https://a.thumbs.redditmedia.com/xyz.jpg"));
+		assert_eq!(
+			upstream_url("/preview/pre/abc.png?width=640&s=123").as_deref(),
+			Some("https://preview.redd.it/abc.png?width=640&s=123")
+		);
+		assert_eq!(upstream_url("/r/rust"), None);
+	}
+
+	#[test]
+	fn thread_text_works() {
+		let post = json!({ "title": "Title", "author": "op", "subreddit": "rust", "score": 12, "permalink": "/r/rust/comments/abc/title/", "selftext": "Body", "is_self": true });
+		let comments = json!({ "data": { "children": [
+			{ "kind": "t1", "data": { "author": "a", "score": 1, "body": "Top\n\nlevel", "replies": { "data": { "children": [
+				{ "kind": "t1", "data": { "author": "b", "score": 3, "body": "Reply", "replies": "" } },
+				{ "kind": "more", "data": { "count": 2 } },
+			] } } } },
+			{ "kind": "t1", "data": { "author": "filtered", "score": 5, "body": "Hidden", "replies": "" } },
+		] } });
+		let filters = HashSet::from(["u_filtered".to_string()]);
+
+		assert_eq!(
+			thread_text(&post, &comments, TextFormat::Markdown, None, &filters),
+			"# Title\n\nPosted by u/op in r/rust (12 points)\nhttps://www.reddit.com/r/rust/comments/abc/title/\n\nBody\n\n---\n\n\
+			**u/a** (1 point)\n\nTop\n\nlevel\n\n> **u/b** (3 points)\n>\n> Reply\n>\n> [2 more replies]\n>\n"
+		);
+		assert!(thread_text(&post, &comments, TextFormat::Plain, Some("1"), &filters).ends_with("---\n\nu/a (1 point):\n\nTop\n\nlevel\n\n"));
+	}
+}
@@ -0,0 +1,152 @@
+// Alternative representations of pages, for programs rather than browsers.
+// Handlers call these before rendering their HTML template, and fall back to
+// HTML when the client didn't ask for anything else.
+
+// CRATES
+use crate::server::{Format, RequestExt};
+use crate::utils::{wasm_error, Comment, Post, Subreddit};
+use serde_json::{json, Value};
+use time::{macros::format_description, OffsetDateTime};
+use web_sys::{Request, Response};
+
+/// Renders a listing of `posts` as JSON or RSS if the client asked for it.
+/// `after` is the ID to continue the listing from, and `about` the subreddit
+/// the listing belongs to, if any.
+pub fn listing(req: &Request, title: &str, posts: &[Post], after: &str, about: Option<&Subreddit>) -> Option<Result<Response, String>> {
+	match req.format() {
+		Format::Html => None,
+		Format::Json => Some(response(
+			Format::Json,
+			&json!({
+				"title": title,
+				"subreddit": about.map(subreddit_json),
+				"posts": posts.iter().map(post_json).collect::<Vec<Value>>(),
+				"after": after,
+			})
+			.to_string(),
+		)),
+		Format::Rss => Some(response(Format::Rss, &rss(req, title, posts))),
+	}
+}
+
+/// Renders a post and its comments as JSON if the client asked for it.
+pub fn thread(req: &Request, post: &Post, comments: &[Comment]) -> Option<Result<Response, String>> {
+	match req.format() {
+		Format::Json => {
+			let mut body = post_json(post);
+			body["comments"] = comments.iter().map(comment_json).collect();
+			Some(response(Format::Json, &body.to_string()))
+		}
+		_ => None,
+	}
+}
+
+// Like HTML pages, these depend on the visitor's cookies and are served at the
+// same URL when chosen with `Accept`, so shared caches must not keep them
+fn response(format: Format, body: &str) -> Result<Response, String> {
+	let res = Response::new_with_opt_str(Some(body)).map_err(wasm_error)?;
+	res.headers().set("content-type", format.content_type()).ok();
+	res.headers().set("Cache-Control", "private, max-age=30").ok();
+	Ok(res)
+}
+
+fn post_json(post: &Post) -> Value {
+	json!({
+		"id": post.id,
+		"title": post.title,
+		"subreddit": post.community,
+		"author": post.author.name,
+		"permalink": post.permalink,
+		"url": post.media.url,
+		"type": post.post_type.to_str(),
+		"flair": post.flair.text,
+		"body": post.body,
+		"score": post.score.1.parse::<i64>().ok(),
+		"comments": post.comments.1.parse::<i64>().ok(),
+		"created": post.created_ts,
+		"nsfw": post.flags.nsfw,
+		"stickied": post.flags.stickied,
+	})
+}
+
+fn subreddit_json(sub: &Subreddit) -> Value {
+	json!({
+		"name": sub.name,
+		"title": sub.title,
+		"description": sub.description,
+		"icon": sub.icon,
+		"members": sub.members.1.parse::<i64>().ok(),
+		"active": sub.active.1.parse::<i64>().ok(),
+		"wiki": sub.wiki,
+		"nsfw": sub.nsfw,
+	})
+}
+
+fn comment_json(comment: &Comment) -> Value {
+	json!({
+		"id": comment.id,
+		"author": comment.author.name,
+		"body": comment.body,
+		"score": comment.score.1.parse::<i64>().ok(),
+		"replies": comment.replies.iter().map(comment_json).collect::<Vec<Value>>(),
+	})
+}
+
+fn rss(req: &Request, title: &str, posts: &[Post]) -> String {
+	let origin = req.uri().origin();
+	let pathname = req.uri().pathname();
+	let items: String = posts
+		.iter()
+		.map(|post| {
+			let link = format!("{}{}", origin, post.permalink);
+			format!(
+				"<item><title>{}</title><link>{}</link><guid isPermaLink=\"true\">{}</guid><author>u/{}</author><pubDate>{}</pubDate><description>{}</description></item>",
+				escape(&item_title(post)),
+				escape(&link),
+				escape(&link),
+				escape(&post.author.name),
+				rfc2822(post.created_ts),
+				escape(&post.body)
+			)
+		})
+		.collect();
+
+	format!(
+		"<?xml version=\"1.0\" encoding=\"UTF-8\"?><rss version=\"2.0\"><channel><title>{}</title><link>{}{}</link><description>{}</description>{}</channel></rss>",
+		escape(title),
+		escape(&origin),
+		escape(Format::from_path(&pathname).map_or(pathname.as_str(), |(_, path)| path)),
+		escape(title),
+		items
+	)
+}
+
+// Comments in a user's listing have no title of their own
+fn item_title(post: &Post) -> String {
+	if post.title.is_empty() {
+		format!("Comment by u/{} in r/{}", post.author.name, post.community)
+	} else {
+		post.title.clone()
+	}
+}
+
+fn rfc2822(created: f64) -> String {
+	OffsetDateTime::from_unix_timestamp(created.round() as i64)
+		.unwrap_or(OffsetDateTime::UNIX_EPOCH)
+		.format(format_description!("[weekday repr:short], [day] [month repr:short] [year] [hour]:[minute]:[second] +0000"))
+		.unwrap_or_default()
+}
+
+fn escape(text: &str) -> String {
+	text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::rfc2822;
+
+	#[test]
+	fn rfc2822_works() {
+		assert_eq!(rfc2822(1_700_000_000.0), "Tue, 14 Nov 2023 22:13:20 +0000");
+	}
+}
@@ -0,0 +1,88 @@
+// Interstitial offering to open a page in the Reddit app instead, for
+// visitors who followed a link here from another site and turned on the
+// `app_handoff` preference.
+
+// CRATES
+use crate::counters::page_type;
+use crate::server::{BoxResponse, RequestExt};
+use crate::utils::{setting, template, Preferences};
+use askama::Template;
+use futures_lite::{future, FutureExt};
+use web_sys::Request;
+
+// Package name of the official Reddit app on Android
+const ANDROID_PACKAGE: &str = "com.reddit.frontpage";
+
+#[derive(Template)]
+#[template(path = "handoff.html")]
+struct HandoffTemplate {
+	app_url: String,
+	reddit_url: String,
+	prefs: Preferences,
+	url: String,
+}
+
+/// Shows the handoff page in place of pages opened from another site.
+pub fn intercept(req: &Request) -> Option<BoxResponse> {
+	let header = |name: &str| req.headers().get(name).ok().flatten().unwrap_or_default();
+
+	// Links followed within this instance, typed URLs and embedded resources
+	// are left alone
+	if req.method() != "GET" || header("Sec-Fetch-Site") != "cross-site" || header("Sec-Fetch-Dest") != "document" {
+		return None;
+	}
+	if setting(req, "app_handoff") != "on" {
+		return None;
+	}
+
+	let search = Some(req.uri().search()).filter(|search| search != "?_").unwrap_or_default();
+	let url = format!("{}{}", req.uri().pathname(), search);
+	let reddit_url = reddit_url(&url)?;
+
+	// Android opens intent links in the app directly, while iOS hands
+	// Reddit's own URLs to the app when it's installed
+	let app_url = if header("User-Agent").contains("Android") {
+		format!("intent://{}#Intent;scheme=https;package={};end", reddit_url.trim_start_matches("https://"), ANDROID_PACKAGE)
+	} else {
+		reddit_url.clone()
+	};
+
+	let res = template(HandoffTemplate {
+		app_url,
+		reddit_url,
+		prefs: Preferences::new(req),
+		url,
+	})
+	.inspect(|res| {
+		// The same URL serves the page itself once the visitor chooses
+		res.headers().set("Cache-Control", "no-store").ok();
+	});
+
+	Some(future::ready(res).boxed_local())
+}
+
+// The address of the page at `url` on Reddit, if it is a page Reddit has
+fn reddit_url(url: &str) -> Option<String> {
+	let path = url.split('?').next().unwrap_or_default();
+
+	match page_type(path)? {
+		"front" | "subreddit" | "post" | "user" | "search" | "wiki" => Some(format!("https://www.reddit.com{}", url)),
+		_ => None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::reddit_url;
+
+	#[test]
+	fn reddit_url_works() {
+		assert_eq!(
+			reddit_url("/r/rust/comments/abc/title"),
+			Some("https://www.reddit.com/r/rust/comments/abc/title".to_string())
+		);
+		assert_eq!(reddit_url("/search?q=rust"), Some("https://www.reddit.com/search?q=rust".to_string()));
+		assert_eq!(reddit_url("/settings"), None);
+		assert_eq!(reddit_url("/img/abc.jpg"), None);
+	}
+}
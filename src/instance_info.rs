@@ -1,6 +1,7 @@
 use crate::{
 	config::{Config, CONFIG},
-	server::RequestExt,
+	counters,
+	server::{Format, RequestExt},
 	utils::{wasm_error, ErrorTemplate, Preferences},
 };
 use askama::Template;
@@ -34,7 +35,8 @@ pub async fn instance_info(req: Request) -> Result<Response, String> {
 		"yaml" | "yml" => info_yaml(),
 		"txt" => info_txt(),
 		"json" => info_json(),
-		"html" | "" => info_html(req),
+		"" if req.format() == Format::Json => info_json(),
+		"html" | "" => info_html(req).await,
 		_ => {
 			let error = ErrorTemplate {
 				msg: "Error: Invalid info extension".into(),
@@ -71,10 +73,14 @@ fn info_yaml() -> Result<Response, String> {
 fn info_txt() -> Result<Response, String> {
 	response(200, "text/plain", INSTANCE_INFO.to_string(StringType::Raw))
 }
-fn info_html(req: Request) -> Result<Response, String> {
+async fn info_html(req: Request) -> Result<Response, String> {
 	let message = MessageTemplate {
 		title: String::from("Instance information"),
-		body: INSTANCE_INFO.to_string(StringType::Html),
+		body: format!(
+			"{}<br /><h3>Requests served</h3><br />{}",
+			INSTANCE_INFO.to_string(StringType::Html),
+			counters::table().await
+		),
 		prefs: Preferences::new(&req),
 		url: req.url(),
 	}
@@ -126,6 +132,24 @@ impl InstanceInfo {
 				["SFW only", &convert(&self.config.sfw_only)],
 				["Pushshift frontend", &convert(&self.config.pushshift)],
 				//TODO: fallback to crate::config::DEFAULT_PUSHSHIFT_FRONTEND
+				["Debug headers", &convert(&self.config.debug_headers)],
+				["Log client IPs", &convert(&self.config.log_client_ip)],
+				["Signed restore links", &convert(&self.config.restore_link_key.as_ref().map(|_| "Set".to_string()))],
+				["Archive API", &convert(&self.config.archive_api)],
+				["Proxy size limit (MB)", &convert(&self.config.proxy_max_size)],
+				["Instance list URL", &convert(&self.config.instances_url)],
+				["Proxy policies", &convert(&self.config.proxy_policies)],
+				["Translation service", &convert(&self.config.translate_url)],
+				["Egress route", &convert(&self.config.egress.as_ref().map(|_| "Set".to_string()))],
+				["Minimal cookies", &convert(&self.config.minimal_cookies)],
+				["Search index", &convert(&self.config.search_index_url)],
+				["Search provider", &convert(&self.config.search_provider)],
+				["Admin endpoints", &convert(&self.config.admin_token.as_ref().map(|_| "Enabled".to_string()))],
+				["Media cache TTL", &convert(&self.config.media_cache_ttl)],
+				["Video cache TTL", &convert(&self.config.video_cache_ttl)],
+				["Disable indexing", &convert(&self.config.robots_disable_indexing)],
+				["Custom robots.txt", &convert(&self.config.robots_txt.as_ref().map(|_| "Set".to_string()))],
+				["No-index user pages", &convert(&self.config.robots_noindex_users)],
 			])
 			.with_header_row(["Settings"]),
 		);
@@ -144,6 +168,26 @@ impl InstanceInfo {
 				["Use HLS", &convert(&self.config.default_use_hls)],
 				["Hide HLS notification", &convert(&self.config.default_hide_hls_notification)],
 				["Subscriptions", &convert(&self.config.default_subscriptions)],
+				["Hide inline media", &convert(&self.config.default_hide_inline_media)],
+				["Show hidden posts", &convert(&self.config.default_show_hidden)],
+				["Date format", &convert(&self.config.default_date_format)],
+				["Render math", &convert(&self.config.default_render_math)],
+				["Data saver", &convert(&self.config.default_data_saver)],
+				["Read-only badges", &convert(&self.config.default_read_only_badges)],
+				["Posts per page", &convert(&self.config.default_limit)],
+				["Popular posts region", &convert(&self.config.default_geo_filter)],
+				["Blur NSFW text", &convert(&self.config.default_blur_nsfw_text)],
+				["Reddit app handoff", &convert(&self.config.default_app_handoff)],
+				["Link titles", &convert(&self.config.default_link_titles)],
+				["Hide sidebars", &convert(&self.config.default_hide_sidebar)],
+				["Collapse info panels", &convert(&self.config.default_collapse_info_panels)],
+				["Collapse subscription list", &convert(&self.config.default_collapse_subscriptions)],
+				["Dim media", &convert(&self.config.default_dim_media)],
+				["Flair accents", &convert(&self.config.default_flair_accents)],
+				["Preferred resolution", &convert(&self.config.default_preferred_resolution)],
+				["Front page sort", &convert(&self.config.default_front_page_sort)],
+				["User page sort", &convert(&self.config.default_user_sort)],
+				["Search sort", &convert(&self.config.default_search_sort)],
 			])
 			.with_header_row(["Default preferences"]),
 		);
@@ -160,6 +204,24 @@ impl InstanceInfo {
                 Compile mode: {}\n
 				SFW only: {:?}\n
 				Pushshift frontend: {:?}\n
+				Debug headers: {:?}\n
+				Log client IPs: {:?}\n
+				Signed restore links: {:?}\n
+				Archive API: {:?}\n
+				Proxy size limit (MB): {:?}\n
+				Instance list URL: {:?}\n
+				Proxy policies: {:?}\n
+				Translation service: {:?}\n
+				Egress route: {:?}\n
+				Minimal cookies: {:?}\n
+				Search index: {:?}\n
+				Search provider: {:?}\n
+				Admin endpoints: {:?}\n
+				Media cache TTL: {:?}\n
+				Video cache TTL: {:?}\n
+				Disable indexing: {:?}\n
+				Custom robots.txt: {:?}\n
+				No-index user pages: {:?}\n
                 Config:\n
                     Banner: {:?}\n
                     Hide awards: {:?}\n
@@ -173,7 +235,27 @@ impl InstanceInfo {
                     Default blur NSFW: {:?}\n
                     Default use HLS: {:?}\n
                     Default hide HLS notification: {:?}\n
-                    Default subscriptions: {:?}\n",
+                    Default subscriptions: {:?}\n
+                    Default hide inline media: {:?}\n
+                    Default show hidden posts: {:?}\n
+                    Default date format: {:?}\n
+                    Default render math: {:?}\n
+                    Default data saver: {:?}\n
+                    Default read-only badges: {:?}\n
+                    Default posts per page: {:?}\n
+                    Default popular posts region: {:?}\n
+                    Default blur NSFW text: {:?}\n
+                    Default Reddit app handoff: {:?}\n
+                    Default link titles: {:?}\n
+                    Default hide sidebars: {:?}\n
+                    Default collapse info panels: {:?}\n
+                    Default collapse subscription list: {:?}\n
+                    Default dim media: {:?}\n
+                    Default flair accents: {:?}\n
+                    Default preferred resolution: {:?}\n
+                    Default front page sort: {:?}\n
+                    Default user page sort: {:?}\n
+                    Default search sort: {:?}\n",
 					self.crate_version,
 					self.git_commit,
 					self.deploy_date,
@@ -181,6 +263,24 @@ impl InstanceInfo {
 					self.compile_mode,
 					self.config.sfw_only,
 					self.config.pushshift,
+					self.config.debug_headers,
+					self.config.log_client_ip,
+					self.config.restore_link_key.is_some(),
+					self.config.archive_api,
+					self.config.proxy_max_size,
+					self.config.instances_url,
+					self.config.proxy_policies,
+					self.config.translate_url,
+					self.config.egress.is_some(),
+					self.config.minimal_cookies,
+					self.config.search_index_url,
+					self.config.search_provider,
+					self.config.admin_token.is_some(),
+					self.config.media_cache_ttl,
+					self.config.video_cache_ttl,
+					self.config.robots_disable_indexing,
+					self.config.robots_txt,
+					self.config.robots_noindex_users,
 					self.config.banner,
 					self.config.default_hide_awards,
 					self.config.default_theme,
@@ -194,6 +294,26 @@ impl InstanceInfo {
 					self.config.default_use_hls,
 					self.config.default_hide_hls_notification,
 					self.config.default_subscriptions,
+					self.config.default_hide_inline_media,
+					self.config.default_show_hidden,
+					self.config.default_date_format,
+					self.config.default_render_math,
+					self.config.default_data_saver,
+					self.config.default_read_only_badges,
+					self.config.default_limit,
+					self.config.default_geo_filter,
+					self.config.default_blur_nsfw_text,
+					self.config.default_app_handoff,
+					self.config.default_link_titles,
+					self.config.default_hide_sidebar,
+					self.config.default_collapse_info_panels,
+					self.config.default_collapse_subscriptions,
+					self.config.default_dim_media,
+					self.config.default_flair_accents,
+					self.config.default_preferred_resolution,
+					self.config.default_front_page_sort,
+					self.config.default_user_sort,
+					self.config.default_search_sort,
 				)
 			}
 			StringType::Html => self.to_table(),
@@ -206,9 +326,9 @@ enum StringType {
 }
 #[derive(Template)]
 #[template(path = "message.html")]
-struct MessageTemplate {
-	title: String,
-	body: String,
-	prefs: Preferences,
-	url: String,
+pub(crate) struct MessageTemplate {
+	pub title: String,
+	pub body: String,
+	pub prefs: Preferences,
+	pub url: String,
 }
@@ -0,0 +1,144 @@
+// The list of public Libreddit instances, checked for whether they can reach
+// Reddit, so visitors can move to another one when this instance is blocked
+// or rate-limited.
+
+// CRATES
+use crate::client::{external_json, probe};
+use crate::config::{get_setting, DEFAULT_INSTANCES_URL};
+use crate::server::RequestExt;
+use crate::utils::{error, redirect, template, Preferences};
+use askama::Template;
+use cached::proc_macro::cached;
+use js_sys::Math;
+use serde_json::Value;
+use web_sys::{Request, Response};
+
+// Most instances from the list that are checked
+const MAX_CHECKED: usize = 30;
+
+// How long an instance may take to show r/popular, in milliseconds
+const PROBE_TIMEOUT: i32 = 5000;
+
+#[derive(Clone)]
+pub struct Instance {
+	pub url: String,
+	pub country: String,
+	/// Milliseconds it took to show r/popular, or `None` if it failed
+	pub latency: Option<f64>,
+}
+
+#[derive(Template)]
+#[template(path = "instances.html")]
+struct InstancesTemplate {
+	instances: Vec<Instance>,
+	/// Path to keep when switching instances
+	path: String,
+	prefs: Preferences,
+	url: String,
+}
+
+/// Lists public instances, healthy ones first.
+pub async fn page(req: Request) -> Result<Response, String> {
+	let instances = match checked_instances(req.uri().origin()).await {
+		Ok(instances) => instances,
+		Err(msg) => return error(req, msg).await,
+	};
+
+	template(InstancesTemplate {
+		instances,
+		path: local_path(&req),
+		prefs: Preferences::new(&req),
+		url: req.url(),
+	})
+}
+
+/// Redirects to the same page on a random healthy instance.
+pub async fn failover(req: Request) -> Result<Response, String> {
+	let healthy: Vec<Instance> = checked_instances(req.uri().origin()).await?.into_iter().filter(|i| i.latency.is_some()).collect();
+	if healthy.is_empty() {
+		return error(req, "No other instance is reachable right now").await;
+	}
+
+	// Spread visitors across instances rather than sending all of them to
+	// the fastest one
+	let pick = &healthy[(Math::random() * healthy.len() as f64) as usize % healthy.len()];
+	Ok(redirect(format!("{}{}", pick.url, local_path(&req))))
+}
+
+// The `path` parameter, if it's a path on an instance
+fn local_path(req: &Request) -> String {
+	req
+		.uri()
+		.search_params()
+		.get("path")
+		.filter(|path| path.starts_with('/') && !path.starts_with("//"))
+		.unwrap_or_else(|| "/".to_string())
+}
+
+// Instances from the configured list, other than this one, with the result
+// of checking each of them
+#[cached(time = 900, result = true)]
+async fn checked_instances(origin: String) -> Result<Vec<Instance>, String> {
+	let list_url = get_setting("LIBREDDIT_INSTANCES_URL").unwrap_or_else(|| DEFAULT_INSTANCES_URL.to_string());
+	let list = external_json(list_url).await.map_err(|_| "Couldn't load the list of instances".to_string())?;
+
+	let mut instances = parse_instances(&list);
+	instances.retain(|instance| instance.url != origin);
+	instances.truncate(MAX_CHECKED);
+
+	// Start every check before waiting on any of them
+	let checks: Vec<_> = instances.iter().map(|instance| probe(&format!("{}/r/popular", instance.url), PROBE_TIMEOUT)).collect();
+	for (instance, check) in instances.iter_mut().zip(checks) {
+		instance.latency = check.await;
+	}
+
+	instances.sort_by(|a, b| match (a.latency, b.latency) {
+		(Some(a), Some(b)) => a.total_cmp(&b),
+		(Some(_), None) => std::cmp::Ordering::Less,
+		(None, Some(_)) => std::cmp::Ordering::Greater,
+		(None, None) => a.url.cmp(&b.url),
+	});
+
+	Ok(instances)
+}
+
+// Clearnet instances in the libreddit-instances format
+fn parse_instances(list: &Value) -> Vec<Instance> {
+	list["instances"]
+		.as_array()
+		.map(|instances| {
+			instances
+				.iter()
+				.filter_map(|instance| {
+					let url = instance["url"].as_str()?.trim_end_matches('/');
+					url.starts_with("https://").then(|| Instance {
+						url: url.to_string(),
+						country: instance["country"].as_str().unwrap_or_default().to_string(),
+						latency: None,
+					})
+				})
+				.collect()
+		})
+		.unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::parse_instances;
+	use serde_json::json;
+
+	#[test]
+	fn parse_instances_works() {
+		let list = json!({
+			"instances": [
+				{ "url": "https://one.example/", "country": "DE" },
+				{ "onion": "http://example.onion" },
+				{ "url": "http://plain.example" }
+			]
+		});
+		let instances = parse_instances(&list);
+		assert_eq!(instances.len(), 1);
+		assert_eq!(instances[0].url, "https://one.example");
+		assert_eq!(instances[0].country, "DE");
+	}
+}
@@ -0,0 +1,34 @@
+// Persistent key-value storage provided by the host runtime, such as Deno KV
+// or a Cloudflare KV namespace. The host exposes it as two global functions:
+//
+//   __libredditKvGet(key: string): Promise<string | null>
+//   __libredditKvPut(key: string, value: string, ttl: number): Promise<void>
+//
+// where `ttl` is in seconds. Without them, reads miss and writes are dropped.
+
+// CRATES
+use crate::utils::promise;
+use js_sys::Promise;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+extern "C" {
+	#[wasm_bindgen(js_namespace = globalThis, js_name = __libredditKvGet, catch)]
+	fn kv_get(key: &str) -> Result<Promise, JsValue>;
+
+	#[wasm_bindgen(js_namespace = globalThis, js_name = __libredditKvPut, catch)]
+	fn kv_put(key: &str, value: &str, ttl: u32) -> Result<Promise, JsValue>;
+}
+
+/// Reads `key`, or returns `None` if it's missing or there is no storage.
+pub async fn get(key: &str) -> Option<String> {
+	let value = promise::<JsValue>(kv_get(key).ok()?).await.ok()?;
+	value.as_string()
+}
+
+/// Stores `value` under `key` for `ttl` seconds, if there is storage.
+pub async fn put(key: &str, value: &str, ttl: u32) {
+	if let Ok(pending) = kv_put(key, value, ttl) {
+		promise::<JsValue>(pending).await.ok();
+	}
+}
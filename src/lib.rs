@@ -3,13 +3,33 @@
 #![allow(clippy::cmp_owned)]
 
 // Reference local files
+mod admin;
+mod alias;
 mod config;
+mod counters;
+mod digest;
+mod download;
 mod duplicates;
+mod export;
+mod feed;
+mod handoff;
 mod instance_info;
+mod instances;
+mod kv;
+mod link_preview;
+mod math;
+mod media_cache;
+mod media_policy;
 mod post;
+mod resize;
+mod schema;
 mod search;
 mod settings;
+mod status;
 mod subreddit;
+mod subscriptions;
+mod themes;
+mod translate;
 mod user;
 mod utils;
 
@@ -23,7 +43,7 @@ use client::{canonical_path, proxy};
 use js_sys::Uint8Array;
 use once_cell::sync::Lazy;
 use server::{RequestExt, Server};
-use utils::{error, redirect, wasm_error, ThemeAssets};
+use utils::{error, gone, redirect, wasm_error, ThemeAssets};
 use wasm_bindgen::prelude::*;
 use web_sys::{Request, Response};
 
@@ -33,43 +53,98 @@ mod server;
 
 // Required for the manifest to be valid
 async fn pwa_logo() -> Result<Response, String> {
-	resource(include_bytes!("../static/logo.png").as_ref(), "image/png", false).await
+	resource(include_bytes!("../static/logo.png").as_ref(), "image/png").await
 }
 
 // Required for iOS App Icons
 async fn iphone_logo() -> Result<Response, String> {
-	resource(include_bytes!("../static/apple-touch-icon.png").as_ref(), "image/png", false).await
+	resource(include_bytes!("../static/apple-touch-icon.png").as_ref(), "image/png").await
 }
 
 async fn favicon() -> Result<Response, String> {
-	resource(include_bytes!("../static/favicon.ico").as_ref(), "image/vnd.microsoft.icon", true).await
+	resource(include_bytes!("../static/favicon.ico").as_ref(), "image/vnd.microsoft.icon").await
 }
 
 async fn font() -> Result<Response, String> {
-	resource(include_bytes!("../static/Inter.var.woff2").as_ref(), "font/woff2", true).await
+	resource(include_bytes!("../static/Inter.var.woff2").as_ref(), "font/woff2").await
 }
 
-async fn resource(body: impl AsRef<[u8]>, content_type: &str, cache: bool) -> Result<Response, String> {
+async fn resource(body: impl AsRef<[u8]>, content_type: &str) -> Result<Response, String> {
 	let body: Uint8Array = body.as_ref().into();
 	let response = Response::new_with_opt_buffer_source(Some(&body)).map_err(wasm_error)?;
 	response.headers().set("content-type", content_type).ok();
 
-	if cache {
-		response.headers().set("Cache-Control", "public, max-age=1209600, s-maxage=86400").ok();
+	Ok(response)
+}
+
+// Most app shortcuts in the web app manifest; browsers show only a few
+const MAX_SHORTCUTS: usize = 10;
+
+// The web app manifest. The visitor's multireddits and subscriptions become
+// shortcuts of the installed app.
+async fn manifest(req: Request) -> Result<Response, String> {
+	let mut manifest: serde_json::Value = serde_json::from_str(include_str!("../static/manifest.json")).map_err(|e| e.to_string())?;
+
+	let prefs = utils::Preferences::new(&req);
+	let multis = prefs.multis.iter().map(|(name, _)| (format!("m/{}", name), format!("/m/{}", name)));
+	let subscriptions = prefs.subscriptions.iter().map(|sub| match sub.strip_prefix("u_") {
+		Some(user) => (format!("u/{}", user), format!("/user/{}", user)),
+		None => (format!("r/{}", sub), format!("/r/{}", sub)),
+	});
+	let shortcuts: Vec<serde_json::Value> = multis
+		.chain(subscriptions)
+		.take(MAX_SHORTCUTS)
+		.map(|(name, url)| serde_json::json!({ "name": name, "url": url }))
+		.collect();
+	if !shortcuts.is_empty() {
+		manifest["shortcuts"] = shortcuts.into();
 	}
 
+	let response = resource(manifest.to_string(), "application/json").await?;
+	// Shortcuts depend on the visitor's cookies
+	response.headers().set("Cache-Control", "private, no-cache").ok();
 	Ok(response)
 }
 
-async fn style() -> Result<Response, String> {
+// Serve robots.txt. Disabling indexing takes precedence over custom contents.
+async fn robots() -> Result<Response, String> {
+	let body = if config::get_setting("LIBREDDIT_ROBOTS_DISABLE_INDEXING").is_some_and(|val| val == "on") {
+		"User-agent: *\nDisallow: /".to_string()
+	} else if let Some(custom) = config::get_setting("LIBREDDIT_ROBOTS_TXT") {
+		// Environment variables can't easily hold newlines
+		custom.replace("\\n", "\n")
+	} else {
+		"User-agent: *\nDisallow: /u/\nDisallow: /user/".to_string()
+	};
+
+	resource(&body, "text/plain").await
+}
+
+// The stylesheet, including every bundled theme
+pub(crate) fn stylesheet() -> String {
 	let mut res = include_str!("../static/style.css").to_string();
 	for file in ThemeAssets::iter() {
 		res.push('\n');
 		let theme = ThemeAssets::get(file.as_ref()).unwrap();
-		res.push_str(std::str::from_utf8(theme.data.as_ref()).unwrap());
+		let css = std::str::from_utf8(theme.data.as_ref()).unwrap();
+		res.push_str(css);
+		res.push('\n');
+		res.push_str(&themes::brightness_rule(file.trim_end_matches(".css"), css));
 	}
+	res
+}
+
+async fn style() -> Result<Response, String> {
+	resource(&stylesheet(), "text/css").await
+}
 
-	resource(&res, "text/css", true).await
+// The themes added through KV storage. Unlike the bundled stylesheet, this
+// changes without a new version of the instance.
+async fn kv_themes() -> Result<Response, String> {
+	themes::refresh().await;
+	let res = resource(&themes::stylesheet(), "text/css").await?;
+	res.headers().set("Cache-Control", "public, max-age=600").ok();
+	Ok(res)
 }
 
 static SERVER: Lazy<Server> = Lazy::new(|| {
@@ -94,26 +169,14 @@ static SERVER: Lazy<Server> = Lazy::new(|| {
 		"Content-Security-Policy" => "default-src 'none'; font-src 'self'; script-src 'self' blob:; manifest-src 'self'; media-src 'self' data: blob: about:; style-src 'self' 'unsafe-inline'; base-uri 'none'; img-src 'self' data:; form-action 'self'; frame-ancestors 'none'; connect-src 'self'; worker-src blob:;"
 	};
 
+	// Offer the Reddit app to visitors arriving from other sites
+	app.interceptors.push(handoff::intercept);
+
 	// Read static files
 	app.at("/style.css").get(|_| style().boxed_local());
-	app
-		.at("/manifest.json")
-		.get(|_| resource(include_str!("../static/manifest.json"), "application/json", false).boxed_local());
-	app.at("/robots.txt").get(|_| {
-		resource(
-			if match config::get_setting("LIBREDDIT_ROBOTS_DISABLE_INDEXING") {
-				Some(val) => val == "on",
-				None => false,
-			} {
-				"User-agent: *\nDisallow: /"
-			} else {
-				"User-agent: *\nDisallow: /u/\nDisallow: /user/"
-			},
-			"text/plain",
-			true,
-		)
-		.boxed_local()
-	});
+	app.at("/themes.css").get(|_| kv_themes().boxed_local());
+	app.at("/manifest.json").get(|r| manifest(r).boxed_local());
+	app.at("/robots.txt").get(|_| robots().boxed_local());
 	app.at("/favicon.ico").get(|_| favicon().boxed_local());
 	app.at("/logo.png").get(|_| pwa_logo().boxed_local());
 	app.at("/Inter.var.woff2").get(|_| font().boxed_local());
@@ -121,10 +184,37 @@ static SERVER: Lazy<Server> = Lazy::new(|| {
 	app.at("/apple-touch-icon.png").get(|_| iphone_logo().boxed_local());
 	app
 		.at("/playHLSVideo.js")
-		.get(|_| resource(include_str!("../static/playHLSVideo.js"), "text/javascript", false).boxed_local());
+		.get(|_| resource(include_str!("../static/playHLSVideo.js"), "text/javascript").boxed_local());
+	app
+		.at("/collapseMemory.js")
+		.get(|_| resource(include_str!("../static/collapseMemory.js"), "text/javascript").boxed_local());
+	app
+		.at("/moreComments.js")
+		.get(|_| resource(include_str!("../static/moreComments.js"), "text/javascript").boxed_local());
+	app
+		.at("/threadVisits.js")
+		.get(|_| resource(include_str!("../static/threadVisits.js"), "text/javascript").boxed_local());
+	app
+		.at("/themePreview.js")
+		.get(|_| resource(include_str!("../static/themePreview.js"), "text/javascript").boxed_local());
+	app
+		.at("/settingsFilter.js")
+		.get(|_| resource(include_str!("../static/settingsFilter.js"), "text/javascript").boxed_local());
+	app
+		.at("/copyPermalink.js")
+		.get(|_| resource(include_str!("../static/copyPermalink.js"), "text/javascript").boxed_local());
+	app
+		.at("/copyCode.js")
+		.get(|_| resource(include_str!("../static/copyCode.js"), "text/javascript").boxed_local());
+	app
+		.at("/dashAudio.js")
+		.get(|_| resource(include_str!("../static/dashAudio.js"), "text/javascript").boxed_local());
+	app
+		.at("/authorPeek.js")
+		.get(|_| resource(include_str!("../static/authorPeek.js"), "text/javascript").boxed_local());
 	app
 		.at("/hls.min.js")
-		.get(|_| resource(include_str!("../static/hls.min.js"), "text/javascript", false).boxed_local());
+		.get(|_| resource(include_str!("../static/hls.min.js"), "text/javascript").boxed_local());
 
 	// Proxy media through Libreddit
 	app.at("/vid/:id/:size").get(|r| proxy(r, "https://v.redd.it/{id}/DASH_{size}").boxed_local());
@@ -132,12 +222,17 @@ static SERVER: Lazy<Server> = Lazy::new(|| {
 	app.at("/img/*path").get(|r| proxy(r, "https://i.redd.it/{path}").boxed_local());
 	app.at("/thumb/:point/:id").get(|r| proxy(r, "https://{point}.thumbs.redditmedia.com/{id}").boxed_local());
 	app.at("/emoji/:id/:name").get(|r| proxy(r, "https://emoji.redditmedia.com/{id}/{name}").boxed_local());
+	app
+		.at("/emote/*path")
+		.get(|r| proxy(r, "https://reddit-econ-prod-assets-permanent.s3.amazonaws.com/{path}").boxed_local());
 	app
 		.at("/preview/:loc/award_images/:fullname/:id")
 		.get(|r| proxy(r, "https://{loc}view.redd.it/award_images/{fullname}/{id}").boxed_local());
 	app.at("/preview/:loc/:id").get(|r| proxy(r, "https://{loc}view.redd.it/{id}").boxed_local());
 	app.at("/style/*path").get(|r| proxy(r, "https://styles.redditmedia.com/{path}").boxed_local());
 	app.at("/static/*path").get(|r| proxy(r, "https://www.redditstatic.com/{path}").boxed_local());
+	app.at("/favicon/:domain").get(|r| link_preview::favicon(r).boxed_local());
+	app.at("/download/:kind/*path").get(|r| download::download(r).boxed_local());
 
 	// Browse user profile
 	app
@@ -146,17 +241,39 @@ static SERVER: Lazy<Server> = Lazy::new(|| {
 	app.at("/u/:name/comments/:id/:title").get(|r| post::item(r).boxed_local());
 	app.at("/u/:name/comments/:id/:title/:comment_id").get(|r| post::item(r).boxed_local());
 
-	app.at("/user/[deleted]").get(|req| error(req, "User has deleted their account".to_string()).boxed_local());
+	app.at("/user/[deleted]").get(|req| gone(req, "User has deleted their account".to_string()).boxed_local());
 	app.at("/user/:name").get(|r| user::profile(r).boxed_local());
+	app.at("/user/:name/search").get(|r| search::find(r).boxed_local());
+	app.at("/user/:name/peek/:sub").get(|r| user::peek(r).boxed_local());
+	app.at("/user/:name/tag").post(|r| user::tag(r).boxed_local());
 	app.at("/user/:name/:listing").get(|r| user::profile(r).boxed_local());
+	app.at("/user/:name/m/:multi").get(|r| subreddit::custom_feed(r).boxed_local());
+	app.at("/user/:name/m/:multi/:sort").get(|r| subreddit::custom_feed(r).boxed_local());
 	app.at("/user/:name/comments/:id").get(|r| post::item(r).boxed_local());
 	app.at("/user/:name/comments/:id/:title").get(|r| post::item(r).boxed_local());
 	app.at("/user/:name/comments/:id/:title/:comment_id").get(|r| post::item(r).boxed_local());
 
 	// Configure settings
 	app.at("/settings").get(|r| settings::get(r).boxed_local()).post(|r| settings::set(r).boxed_local());
-	app.at("/settings/restore").get(|r| settings::restore(r).boxed_local());
-	app.at("/settings/update").get(|r| settings::update(r).boxed_local());
+	app
+		.at("/settings/restore")
+		.get(|r| settings::restore(r).boxed_local())
+		.post(|r| settings::restore_confirmed(r).boxed_local());
+	app
+		.at("/settings/update")
+		.get(|r| settings::update(r).boxed_local())
+		.post(|r| settings::update_confirmed(r).boxed_local());
+	app.at("/settings/import").post(|r| settings::import(r).boxed_local());
+
+	// Manage subscriptions in bulk
+	app
+		.at("/subscriptions")
+		.get(|r| subscriptions::page(r).boxed_local())
+		.post(|r| subscriptions::update(r).boxed_local());
+
+	// Multireddits saved under a name
+	app.at("/m/:name").get(|r| subscriptions::multi(r).boxed_local());
+	app.at("/m/:name/:sort").get(|r| subscriptions::multi(r).boxed_local());
 
 	// Subreddit services
 	app
@@ -173,15 +290,23 @@ static SERVER: Lazy<Server> = Lazy::new(|| {
 	app.at("/r/:sub/filter").post(|r| subreddit::subscriptions_filters(r).boxed_local());
 	app.at("/r/:sub/unfilter").post(|r| subreddit::subscriptions_filters(r).boxed_local());
 
+	// Hide and unhide posts from listings
+	app.at("/hide/:id").post(|r| subreddit::hide_unhide(r).boxed_local());
+	app.at("/unhide/:id").post(|r| subreddit::hide_unhide(r).boxed_local());
+
 	app.at("/r/:sub/comments/:id").get(|r| post::item(r).boxed_local());
 	app.at("/r/:sub/comments/:id/:title").get(|r| post::item(r).boxed_local());
 	app.at("/r/:sub/comments/:id/:title/:comment_id").get(|r| post::item(r).boxed_local());
 	app.at("/comments/:id").get(|r| post::item(r).boxed_local());
 	app.at("/comments/:id/comments").get(|r| post::item(r).boxed_local());
+	app.at("/comments/:id/more").get(|r| post::more_children(r).boxed_local());
 	app.at("/comments/:id/comments/:comment_id").get(|r| post::item(r).boxed_local());
 	app.at("/comments/:id/:title").get(|r| post::item(r).boxed_local());
 	app.at("/comments/:id/:title/:comment_id").get(|r| post::item(r).boxed_local());
 
+	// Show archived copies of removed comments
+	app.at("/archive/comment/:id").get(|r| post::archived_comment(r).boxed_local());
+
 	app.at("/r/:sub/duplicates/:id").get(|r| duplicates::item(r).boxed_local());
 	app.at("/r/:sub/duplicates/:id/:title").get(|r| duplicates::item(r).boxed_local());
 	app.at("/duplicates/:id").get(|r| duplicates::item(r).boxed_local());
@@ -199,6 +324,9 @@ static SERVER: Lazy<Server> = Lazy::new(|| {
 	app.at("/r/:sub/wiki/*page").get(|r| subreddit::wiki(r).boxed_local());
 
 	app.at("/r/:sub/about/sidebar").get(|r| subreddit::sidebar(r).boxed_local());
+	app.at("/r/:sub/about/activity").get(|r| subreddit::activity(r).boxed_local());
+	app.at("/r/:sub/about/rules").get(|r| subreddit::rules(r).boxed_local());
+	app.at("/r/:sub/media").get(|r| subreddit::media(r).boxed_local());
 
 	app.at("/r/:sub/:sort").get(|r| subreddit::community(r).boxed_local());
 
@@ -219,10 +347,44 @@ static SERVER: Lazy<Server> = Lazy::new(|| {
 	// Handle about pages
 	app.at("/about").get(|req| error(req, "About pages aren't added yet".to_string()).boxed_local());
 
+	// Old Reddit compatible aliases
+	app.at("/.compact").get(|r| alias::compact(r).boxed_local());
+	app.at("/r/:sub/.compact").get(|r| alias::compact(r).boxed_local());
+	app.at("/r/:sub/:sort/.compact").get(|r| alias::compact(r).boxed_local());
+	app.at("/r/:sub/comments/:id/.compact").get(|r| alias::compact(r).boxed_local());
+	app.at("/r/:sub/comments/:id/:title/.compact").get(|r| alias::compact(r).boxed_local());
+	app.at("/r/:sub/comments/:id/:title/:comment_id/.compact").get(|r| alias::compact(r).boxed_local());
+	app.at("/comments/:id/.compact").get(|r| alias::compact(r).boxed_local());
+	app.at("/comments/:id/:title/.compact").get(|r| alias::compact(r).boxed_local());
+	app.at("/user/:name/.compact").get(|r| alias::compact(r).boxed_local());
+	app.at("/user/:name/:listing/.compact").get(|r| alias::compact(r).boxed_local());
+	app.at("/tb/:id").get(|r| alias::post(r).boxed_local());
+	app.at("/gallery/:id").get(|r| alias::post(r).boxed_local());
+	app.at("/i/:id").get(|r| alias::image_or_post(r).boxed_local());
+
+	// Redirect arbitrary Reddit URLs to this instance
+	app.at("/redirect").get(|r| alias::reddit_url(r).boxed_local());
+
+	// Operator endpoints
+	app.at("/admin/purge").get(|r| admin::purge(r).boxed_local()).post(|r| admin::purge(r).boxed_local());
+
 	// Instance info page
 	app.at("/info").get(|r| instance_info::instance_info(r).boxed_local());
 	app.at("/info.:extension").get(|r| instance_info::instance_info(r).boxed_local());
 
+	// Digests of subscribed subreddits
+	app.at("/digest").post(|r| digest::create(r).boxed_local());
+	app.at("/digest/:token").get(|r| digest::page(r).boxed_local());
+
+	// Other public instances
+	app.at("/compare").get(|r| subreddit::compare(r).boxed_local());
+	app.at("/translate/:id").get(|r| translate::page(r).boxed_local());
+	app.at("/instances").get(|r| instances::page(r).boxed_local());
+	app.at("/instances/redirect").get(|r| instances::failover(r).boxed_local());
+
+	// Upstream status page
+	app.at("/status").get(|r| status::status(r).boxed_local());
+
 	app.at("/:id").get(|req: Request| {
 		Box::pin(async move {
 			match req.param("id").as_deref() {
@@ -250,7 +412,31 @@ static SERVER: Lazy<Server> = Lazy::new(|| {
 	app
 });
 
+/// Periodic maintenance, run by the host's scheduler (hourly is enough).
 #[wasm_bindgen]
-pub async fn serve(req: Request) -> Result<Response, String> {
-	SERVER.serve(req).await
+pub async fn scheduled() {
+	digest::refresh_batch().await;
+}
+
+/// Handles a request. `ctx` is whatever the host passes along with it, such
+/// as the execution context of a Cloudflare Worker.
+#[wasm_bindgen]
+pub async fn serve(req: Request, ctx: JsValue) -> Result<Response, String> {
+	let (res, stats) = server::scoped(ctx, SERVER.serve(req)).await;
+	let res = res?;
+	if config::get_setting("LIBREDDIT_DEBUG_HEADERS").is_none_or(|val| val != "on") {
+		return Ok(res);
+	}
+
+	let headers = res.headers();
+	if let Some(cache) = stats
+		.cache
+		.filter(|_| headers.get("content-type").ok().flatten().unwrap_or_default().starts_with("text/html"))
+	{
+		headers.set("X-Libreddit-Cache", cache.as_str()).ok();
+	}
+	headers.set("X-Libreddit-Upstream-Requests", &stats.upstream.0.to_string()).ok();
+	headers.set("Server-Timing", &format!("upstream;dur={:.0}", stats.upstream.1)).ok();
+
+	Ok(res)
 }
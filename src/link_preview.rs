@@ -0,0 +1,120 @@
+// Previews of where link posts go: the destination's favicon and, when the
+// `link_titles` preference is on, the title of the linked page. Both are
+// fetched through this instance so the destination doesn't learn about the
+// visitor before they click, and only from public hosts, with small size and
+// time limits.
+
+// CRATES
+use crate::client::fetch_preview;
+use crate::server::RequestExt;
+use crate::utils::{wasm_error, MediaKind, Post};
+use cached::proc_macro::cached;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use web_sys::{Request, Response, ResponseInit, Url};
+
+// How long the destination may take to respond, in milliseconds
+const TIMEOUT: i32 = 3000;
+
+// Largest favicon served, in bytes
+const MAX_FAVICON_SIZE: usize = 64 * 1024;
+
+// How much of a page is read when looking for its title, in bytes
+const MAX_PAGE_SIZE: usize = 256 * 1024;
+
+// Longest title shown, in characters
+const MAX_TITLE_LENGTH: usize = 200;
+
+static HOST_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^([a-z0-9-]+\.)+[a-z]{2,}$").unwrap());
+static TITLE_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap());
+
+/// Serves the favicon of a link post's destination.
+pub async fn favicon(req: Request) -> Result<Response, String> {
+	let domain = req.param("domain").unwrap_or_default().to_ascii_lowercase();
+	if !is_public_host(&domain) {
+		return Err("Favicons are only fetched from public hosts".to_string());
+	}
+
+	let (content_type, mut icon) = fetch_preview(&format!("https://{}/favicon.ico", domain), TIMEOUT, MAX_FAVICON_SIZE)
+		.await
+		.filter(|(content_type, icon)| content_type.starts_with("image/") && icon.len() < MAX_FAVICON_SIZE)
+		.ok_or_else(|| "Couldn't load the favicon".to_string())?;
+
+	let init = ResponseInit::new();
+	let res = Response::new_with_opt_u8_array_and_init(Some(&mut icon), &init).map_err(wasm_error)?;
+	res.headers().set("content-type", &content_type).ok();
+	res.headers().set("cache-control", "public, max-age=604800").ok();
+	Ok(res)
+}
+
+/// Fills in the title of the page a link post points to.
+pub async fn add_title(post: &mut Post) {
+	if post.post_type == MediaKind::Link {
+		post.link_title = page_title(post.media.url.clone()).await.unwrap_or_default();
+	}
+}
+
+// The title of the page at `url`, if it's an HTML page on a public host
+#[cached(size = 500, time = 3600)]
+async fn page_title(url: String) -> Option<String> {
+	let parsed = Url::new(&url).ok()?;
+	if !matches!(parsed.protocol().as_str(), "http:" | "https:") || !parsed.port().is_empty() || !is_public_host(&parsed.hostname()) {
+		return None;
+	}
+
+	let (content_type, page) = fetch_preview(&url, TIMEOUT, MAX_PAGE_SIZE).await?;
+	if !content_type.starts_with("text/html") {
+		return None;
+	}
+
+	extract_title(&String::from_utf8_lossy(&page))
+}
+
+// Whether `host` is a domain name on the public internet. IP addresses and
+// single-label or local names are refused so the instance can't be used to
+// reach its own network.
+fn is_public_host(host: &str) -> bool {
+	let host = host.to_ascii_lowercase();
+	HOST_REGEX.is_match(&host) && ![".local", ".localhost", ".internal", ".lan", ".home.arpa"].iter().any(|suffix| host.ends_with(suffix))
+}
+
+// The contents of a page's `<title>`, unescaped and with whitespace collapsed
+fn extract_title(html: &str) -> Option<String> {
+	let raw = TITLE_REGEX.captures(html)?.get(1)?.as_str();
+	let title = raw
+		.split_whitespace()
+		.collect::<Vec<_>>()
+		.join(" ")
+		.replace("&lt;", "<")
+		.replace("&gt;", ">")
+		.replace("&quot;", "\"")
+		.replace("&#39;", "'")
+		.replace("&amp;", "&");
+
+	(!title.is_empty()).then(|| title.chars().take(MAX_TITLE_LENGTH).collect())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{extract_title, is_public_host};
+
+	#[test]
+	fn is_public_host_works() {
+		assert!(is_public_host("example.com"));
+		assert!(is_public_host("News.Example.co.uk"));
+		assert!(!is_public_host("localhost"));
+		assert!(!is_public_host("127.0.0.1"));
+		assert!(!is_public_host("printer.local"));
+		assert!(!is_public_host("[::1]"));
+	}
+
+	#[test]
+	fn extract_title_works() {
+		assert_eq!(
+			extract_title("<html><head><TITLE>\n  Rust &amp; WebAssembly </TITLE></head>").as_deref(),
+			Some("Rust & WebAssembly")
+		);
+		assert_eq!(extract_title("<title></title>"), None);
+		assert_eq!(extract_title("<p>No title</p>"), None);
+	}
+}
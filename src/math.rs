@@ -0,0 +1,369 @@
+// Rendering of the LaTeX-style math commonly written in posts and comments on
+// science subreddits into MathML, which browsers display natively.
+
+// CRATES
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+// Matches code blocks and spans, in which math is left untouched
+static CODE_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?s)<pre>.*?</pre>|<code>.*?</code>").unwrap());
+
+// Matches `$$display$$` and `$inline$` math. Inline math may not start or end
+// with whitespace, so that prices like "$5 and $10" are left alone.
+static MATH_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"\$\$([^$<]+?)\$\$|\$([^\s$<](?:[^$<]*?[^\s$<])?)\$").unwrap());
+
+// LaTeX commands that stand for a single symbol, and whether that symbol is an
+// identifier rather than an operator
+const SYMBOLS: &[(&str, &str, bool)] = &[
+	("alpha", "α", true),
+	("beta", "β", true),
+	("gamma", "γ", true),
+	("delta", "δ", true),
+	("epsilon", "ϵ", true),
+	("varepsilon", "ε", true),
+	("zeta", "ζ", true),
+	("eta", "η", true),
+	("theta", "θ", true),
+	("vartheta", "ϑ", true),
+	("iota", "ι", true),
+	("kappa", "κ", true),
+	("lambda", "λ", true),
+	("mu", "μ", true),
+	("nu", "ν", true),
+	("xi", "ξ", true),
+	("pi", "π", true),
+	("rho", "ρ", true),
+	("sigma", "σ", true),
+	("tau", "τ", true),
+	("upsilon", "υ", true),
+	("phi", "ϕ", true),
+	("varphi", "φ", true),
+	("chi", "χ", true),
+	("psi", "ψ", true),
+	("omega", "ω", true),
+	("Gamma", "Γ", true),
+	("Delta", "Δ", true),
+	("Theta", "Θ", true),
+	("Lambda", "Λ", true),
+	("Xi", "Ξ", true),
+	("Pi", "Π", true),
+	("Sigma", "Σ", true),
+	("Upsilon", "Υ", true),
+	("Phi", "Φ", true),
+	("Psi", "Ψ", true),
+	("Omega", "Ω", true),
+	("infty", "∞", true),
+	("partial", "∂", true),
+	("nabla", "∇", true),
+	("hbar", "ℏ", true),
+	("ell", "ℓ", true),
+	("emptyset", "∅", true),
+	("times", "×", false),
+	("cdot", "⋅", false),
+	("pm", "±", false),
+	("mp", "∓", false),
+	("div", "÷", false),
+	("leq", "≤", false),
+	("le", "≤", false),
+	("geq", "≥", false),
+	("ge", "≥", false),
+	("neq", "≠", false),
+	("ne", "≠", false),
+	("approx", "≈", false),
+	("equiv", "≡", false),
+	("sim", "∼", false),
+	("propto", "∝", false),
+	("sum", "∑", false),
+	("prod", "∏", false),
+	("int", "∫", false),
+	("oint", "∮", false),
+	("to", "→", false),
+	("rightarrow", "→", false),
+	("leftarrow", "←", false),
+	("Rightarrow", "⇒", false),
+	("Leftarrow", "⇐", false),
+	("implies", "⟹", false),
+	("iff", "⟺", false),
+	("mapsto", "↦", false),
+	("in", "∈", false),
+	("notin", "∉", false),
+	("subset", "⊂", false),
+	("subseteq", "⊆", false),
+	("supset", "⊃", false),
+	("supseteq", "⊇", false),
+	("cup", "∪", false),
+	("cap", "∩", false),
+	("forall", "∀", false),
+	("exists", "∃", false),
+	("neg", "¬", false),
+	("land", "∧", false),
+	("lor", "∨", false),
+	("circ", "∘", false),
+	("ldots", "…", false),
+	("dots", "…", false),
+	("cdots", "⋯", false),
+	("langle", "⟨", false),
+	("rangle", "⟩", false),
+	("mid", "∣", false),
+	("{", "{", false),
+	("}", "}", false),
+	("|", "‖", false),
+];
+
+// Named functions, set upright like in LaTeX
+const FUNCTIONS: &[&str] = &[
+	"sin", "cos", "tan", "cot", "sec", "csc", "arcsin", "arccos", "arctan", "sinh", "cosh", "tanh", "log", "ln", "exp", "lim", "max", "min", "sup", "inf", "det", "gcd", "deg",
+];
+
+/// Replaces the `$inline$` and `$$display$$` math in a post or comment body
+/// with MathML. Must run before `rewrite_urls`, which strips backslashes.
+pub fn render_math(html: &str) -> String {
+	let mut output = String::with_capacity(html.len());
+	let mut last = 0;
+
+	for code in CODE_REGEX.find_iter(html) {
+		output.push_str(&render_text(&html[last..code.start()]));
+		output.push_str(code.as_str());
+		last = code.end();
+	}
+	output.push_str(&render_text(&html[last..]));
+
+	output
+}
+
+fn render_text(text: &str) -> String {
+	MATH_REGEX
+		.replace_all(text, |caps: &regex::Captures| match (caps.get(1), caps.get(2)) {
+			(Some(display), _) => to_mathml(&unescape(display.as_str()), true),
+			(_, Some(inline)) => to_mathml(&unescape(inline.as_str()), false),
+			_ => caps[0].to_string(),
+		})
+		.to_string()
+}
+
+fn to_mathml(tex: &str, display: bool) -> String {
+	let mut parser = Parser {
+		chars: tex.chars().collect(),
+		pos: 0,
+	};
+	format!("<math display=\"{}\"><mrow>{}</mrow></math>", if display { "block" } else { "inline" }, parser.row(None))
+}
+
+// Undo the HTML escaping of the body, which is redone on output
+fn unescape(text: &str) -> String {
+	text
+		.replace("&lt;", "<")
+		.replace("&gt;", ">")
+		.replace("&quot;", "\"")
+		.replace("&#39;", "'")
+		.replace("&amp;", "&")
+}
+
+fn escape(text: &str) -> String {
+	text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+// Recursive descent parser over a LaTeX expression
+struct Parser {
+	chars: Vec<char>,
+	pos: usize,
+}
+
+impl Parser {
+	fn peek(&self) -> Option<char> {
+		self.chars.get(self.pos).copied()
+	}
+
+	fn skip_whitespace(&mut self) {
+		while self.peek().is_some_and(char::is_whitespace) {
+			self.pos += 1;
+		}
+	}
+
+	// Parse atoms until the end of input or the given closing character
+	fn row(&mut self, end: Option<char>) -> String {
+		let mut output = String::new();
+		loop {
+			self.skip_whitespace();
+			match self.peek() {
+				None => break,
+				Some(c) if Some(c) == end => {
+					self.pos += 1;
+					break;
+				}
+				// Ignore unbalanced closing braces
+				Some('}') => self.pos += 1,
+				_ => output.push_str(&self.scripted()),
+			}
+		}
+		output
+	}
+
+	// Parse an atom along with its subscript and superscript, if any
+	fn scripted(&mut self) -> String {
+		let base = self.atom();
+		let (mut sub, mut sup) = (None, None);
+		loop {
+			self.skip_whitespace();
+			match self.peek() {
+				Some('_') if sub.is_none() => {
+					self.pos += 1;
+					sub = Some(self.atom());
+				}
+				Some('^') if sup.is_none() => {
+					self.pos += 1;
+					sup = Some(self.atom());
+				}
+				_ => break,
+			}
+		}
+
+		match (sub, sup) {
+			(Some(sub), Some(sup)) => format!("<msubsup>{}{}{}</msubsup>", base, sub, sup),
+			(Some(sub), None) => format!("<msub>{}{}</msub>", base, sub),
+			(None, Some(sup)) => format!("<msup>{}{}</msup>", base, sup),
+			(None, None) => base,
+		}
+	}
+
+	fn atom(&mut self) -> String {
+		self.skip_whitespace();
+		let c = match self.peek() {
+			Some(c) => c,
+			None => return "<mrow></mrow>".to_string(),
+		};
+		self.pos += 1;
+
+		match c {
+			'{' => format!("<mrow>{}</mrow>", self.row(Some('}'))),
+			'\\' => self.command(),
+			'0'..='9' => {
+				let mut number = c.to_string();
+				while let Some(digit) = self.peek().filter(|d| d.is_ascii_digit() || *d == '.') {
+					number.push(digit);
+					self.pos += 1;
+				}
+				format!("<mn>{}</mn>", number)
+			}
+			c if c.is_alphabetic() => format!("<mi>{}</mi>", c),
+			c => format!("<mo>{}</mo>", escape(&c.to_string())),
+		}
+	}
+
+	// Read the raw text of a `{...}` group, as used by `\text`
+	fn text_group(&mut self) -> String {
+		self.skip_whitespace();
+		let first = self.peek();
+		self.pos += 1;
+		if first != Some('{') {
+			return first.map(|c| c.to_string()).unwrap_or_default();
+		}
+
+		let (mut depth, mut text) = (1, String::new());
+		while let Some(c) = self.peek() {
+			self.pos += 1;
+			match c {
+				'{' => depth += 1,
+				'}' => {
+					depth -= 1;
+					if depth == 0 {
+						break;
+					}
+				}
+				_ => {}
+			}
+			text.push(c);
+		}
+		text
+	}
+
+	fn command(&mut self) -> String {
+		let mut name = String::new();
+		while let Some(c) = self.peek().filter(char::is_ascii_alphabetic) {
+			name.push(c);
+			self.pos += 1;
+		}
+		// Commands like `\{` and `\,` are a single non-letter character
+		if name.is_empty() {
+			if let Some(c) = self.peek() {
+				name.push(c);
+				self.pos += 1;
+			}
+		}
+
+		match name.as_str() {
+			"frac" | "dfrac" | "tfrac" => format!("<mfrac>{}{}</mfrac>", self.atom(), self.atom()),
+			"binom" => format!("<mrow><mo>(</mo><mfrac linethickness=\"0\">{}{}</mfrac><mo>)</mo></mrow>", self.atom(), self.atom()),
+			"sqrt" => {
+				self.skip_whitespace();
+				if self.peek() == Some('[') {
+					self.pos += 1;
+					let index = self.row(Some(']'));
+					format!("<mroot>{}<mrow>{}</mrow></mroot>", self.atom(), index)
+				} else {
+					format!("<msqrt>{}</msqrt>", self.atom())
+				}
+			}
+			"text" | "textrm" | "mbox" => format!("<mtext>{}</mtext>", escape(&self.text_group())),
+			"mathrm" | "operatorname" => format!("<mi mathvariant=\"normal\">{}</mi>", escape(&self.text_group())),
+			"mathbb" => format!("<mi mathvariant=\"double-struck\">{}</mi>", escape(&self.text_group())),
+			"mathbf" => format!("<mi mathvariant=\"bold\">{}</mi>", escape(&self.text_group())),
+			"mathcal" => format!("<mi mathvariant=\"script\">{}</mi>", escape(&self.text_group())),
+			"mathit" => format!("<mi mathvariant=\"italic\">{}</mi>", escape(&self.text_group())),
+			"overline" | "bar" => format!("<mover>{}<mo>¯</mo></mover>", self.atom()),
+			"hat" => format!("<mover>{}<mo>^</mo></mover>", self.atom()),
+			"vec" => format!("<mover>{}<mo>→</mo></mover>", self.atom()),
+			"dot" => format!("<mover>{}<mo>˙</mo></mover>", self.atom()),
+			// Sizing commands only affect the delimiter that follows
+			"left" | "right" | "big" | "Big" | "bigg" | "Bigg" => match self.atom() {
+				delimiter if delimiter == "<mo>.</mo>" => String::new(),
+				delimiter => delimiter,
+			},
+			"," | ":" | ";" | " " => "<mspace width=\"0.25em\"></mspace>".to_string(),
+			"quad" => "<mspace width=\"1em\"></mspace>".to_string(),
+			"qquad" => "<mspace width=\"2em\"></mspace>".to_string(),
+			"\\" => "<mspace linebreak=\"newline\"></mspace>".to_string(),
+			name if FUNCTIONS.contains(&name) => format!("<mi>{}</mi>", name),
+			name => match SYMBOLS.iter().find(|(symbol, _, _)| *symbol == name) {
+				Some((_, symbol, true)) => format!("<mi>{}</mi>", symbol),
+				Some((_, symbol, false)) => format!("<mo>{}</mo>", symbol),
+				// Show unsupported commands as written
+				None => format!("<mtext>\\{}</mtext>", escape(name)),
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::render_math;
+
+	#[test]
+	fn render_math_works() {
+		assert_eq!(
+			render_math("<p>$x^2$</p>"),
+			"<p><math display=\"inline\"><mrow><msup><mi>x</mi><mn>2</mn></msup></mrow></math></p>"
+		);
+		assert_eq!(
+			render_math(r"$$\frac{a}{b} \leq \sqrt{c}$$"),
+			"<math display=\"block\"><mrow><mfrac><mrow><mi>a</mi></mrow><mrow><mi>b</mi></mrow></mfrac><mo>≤</mo><msqrt><mrow><mi>c</mi></mrow></msqrt></mrow></math>"
+		);
+		assert_eq!(
+			render_math(r"$a_{i} &lt; \alpha$"),
+			"<math display=\"inline\"><mrow><msub><mi>a</mi><mrow><mi>i</mi></mrow></msub><mo>&lt;</mo><mi>α</mi></mrow></math>"
+		);
+	}
+
+	#[test]
+	fn render_math_ignores_prices_and_code() {
+		let cases = [
+			"<p>It costs $5 and $10</p>",
+			"<p>Costs $ 5 $</p>",
+			"<code>$x$</code>",
+			"<pre><code>echo $HOME $PATH</code></pre>",
+		];
+		for input in cases {
+			assert_eq!(render_math(input), input);
+		}
+	}
+}
@@ -0,0 +1,124 @@
+// Proxied media kept in the Cache API of the host runtime, such as
+// Cloudflare's or Deno's, so each location fetches a file from Reddit once.
+// Responses are stored with the time they expire, since not every runtime
+// honors their Cache-Control. Without the Cache API, every request misses.
+
+// CRATES
+use crate::config::get_setting;
+use crate::utils::promise;
+use js_sys::{Date, Promise};
+use wasm_bindgen::prelude::*;
+use web_sys::{Headers, Response, ResponseInit};
+
+// Name of the cache. Deno only has caches opened by name, and Cloudflare's
+// `caches.default` is shared with everything else the zone caches, where
+// media stored by the instance would be mixed with responses it doesn't
+// manage.
+const CACHE_NAME: &str = "libreddit-media";
+
+// Header holding the time (in milliseconds since the epoch) a stored response
+// expires
+const EXPIRES_HEADER: &str = "X-Libreddit-Expires";
+
+// Seconds media is kept when the instance doesn't say
+const DEFAULT_MEDIA_TTL: u32 = 86_400;
+const DEFAULT_VIDEO_TTL: u32 = 3_600;
+
+#[wasm_bindgen]
+extern "C" {
+	type Cache;
+
+	#[wasm_bindgen(js_namespace = caches, js_name = open, catch)]
+	fn open_cache(name: &str) -> Result<Promise, JsValue>;
+
+	#[wasm_bindgen(method, js_name = match)]
+	fn lookup(this: &Cache, key: &str) -> Promise;
+
+	#[wasm_bindgen(method)]
+	fn put(this: &Cache, key: &str, res: &Response) -> Promise;
+
+	#[wasm_bindgen(method)]
+	fn delete(this: &Cache, key: &str) -> Promise;
+}
+
+async fn open() -> Option<Cache> {
+	let cache = promise::<JsValue>(open_cache(CACHE_NAME).ok()?).await.ok()?;
+	(!cache.is_undefined()).then(|| cache.unchecked_into())
+}
+
+// Seconds the media at `path` is kept, or `None` if it isn't
+fn ttl(path: &str) -> Option<u32> {
+	let video = ["/vid/", "/hls/", "/download/vid/"].iter().any(|prefix| path.starts_with(prefix));
+	let (name, default) = if video {
+		("LIBREDDIT_VIDEO_CACHE_TTL", DEFAULT_VIDEO_TTL)
+	} else {
+		("LIBREDDIT_MEDIA_CACHE_TTL", DEFAULT_MEDIA_TTL)
+	};
+
+	let ttl = get_setting(name).and_then(|ttl| ttl.trim().parse().ok()).unwrap_or(default);
+	(ttl > 0).then_some(ttl)
+}
+
+/// The stored response for the media at `url`, whose path is `path`, if
+/// there is one that hasn't expired.
+pub async fn get(url: &str, path: &str) -> Option<Response> {
+	ttl(path)?;
+	let cache = open().await?;
+	let res = promise::<JsValue>(cache.lookup(url)).await.ok()?.dyn_into::<Response>().ok()?;
+
+	let expires = res.headers().get(EXPIRES_HEADER).ok().flatten().and_then(|expires| expires.parse::<f64>().ok());
+	if expires.is_none_or(|expires| expires <= Date::now()) {
+		promise::<JsValue>(cache.delete(url)).await.ok();
+		return None;
+	}
+
+	// The headers of stored responses can't be changed, and the server adds
+	// its own
+	let headers = Headers::new_with_headers(&res.headers()).ok()?;
+	headers.delete(EXPIRES_HEADER).ok();
+
+	let mut init = ResponseInit::new();
+	init.status(res.status());
+	init.headers(&headers);
+	Response::new_with_opt_readable_stream_and_init(res.body().as_ref(), &init).ok()
+}
+
+/// Stores a copy of `res` for the media at `url`, whose path is `path`, while
+/// `res` is sent on. The host is asked to keep running until it's stored.
+pub fn put(url: &str, path: &str, res: Response) -> Response {
+	let (ttl, copy) = match (ttl(path), Response::clone(&res)) {
+		(Some(ttl), Ok(copy)) => (ttl, copy),
+		_ => return res,
+	};
+	let url = url.to_string();
+
+	crate::server::wait_until(async move {
+		let (cache, headers) = match (open().await, Headers::new_with_headers(&copy.headers())) {
+			(Some(cache), Ok(headers)) => (cache, headers),
+			_ => return,
+		};
+		headers.set(EXPIRES_HEADER, &(Date::now() + f64::from(ttl) * 1000.0).to_string()).ok();
+
+		let mut init = ResponseInit::new();
+		init.status(copy.status());
+		init.headers(&headers);
+		if let Ok(stored) = Response::new_with_opt_readable_stream_and_init(copy.body().as_ref(), &init) {
+			promise::<JsValue>(cache.put(&url, &stored)).await.ok();
+		}
+	});
+
+	res
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{ttl, DEFAULT_MEDIA_TTL, DEFAULT_VIDEO_TTL};
+
+	#[test]
+	fn ttl_works() {
+		assert_eq!(ttl("/img/abc.jpg"), Some(DEFAULT_MEDIA_TTL));
+		assert_eq!(ttl("/thumb/a/abc.jpg"), Some(DEFAULT_MEDIA_TTL));
+		assert_eq!(ttl("/vid/abc/720.mp4"), Some(DEFAULT_VIDEO_TTL));
+		assert_eq!(ttl("/hls/abc/HLSPlaylist.m3u8"), Some(DEFAULT_VIDEO_TTL));
+	}
+}
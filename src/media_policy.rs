@@ -0,0 +1,168 @@
+// Rules for media proxied from each upstream host: which content types it
+// may serve, how large its files may be, and whether image metadata is
+// stripped. Reddit's own hosts have built-in policies; other hosts are
+// refused unless an operator adds a policy in `LIBREDDIT_PROXY_POLICIES`.
+
+// CRATES
+use crate::config::get_setting;
+
+/// Headers passed on from upstream media responses. Everything else, like
+/// cookies and CDN or storage metadata, is dropped.
+pub(crate) const ALLOWED_HEADERS: [&str; 7] = [
+	"accept-ranges",
+	"content-length",
+	"content-range",
+	"content-type",
+	"expires",
+	"last-modified",
+	"cache-control",
+];
+
+const IMAGES: &[&str] = &["image/"];
+const VIDEOS: &[&str] = &[
+	"video/",
+	"audio/",
+	"application/vnd.apple.mpegurl",
+	"application/x-mpegurl",
+	"application/dash+xml",
+	"binary/octet-stream",
+];
+const PREVIEWS: &[&str] = &["image/", "video/"];
+const STATIC: &[&str] = &["image/", "font/", "text/css", "application/font-woff", "application/octet-stream"];
+
+// Built-in policies for the hosts the proxy routes point at
+const BUILTIN: [(&str, &[&str]); 10] = [
+	("i.redd.it", IMAGES),
+	("v.redd.it", VIDEOS),
+	("a.thumbs.redditmedia.com", IMAGES),
+	("b.thumbs.redditmedia.com", IMAGES),
+	("emoji.redditmedia.com", IMAGES),
+	("preview.redd.it", PREVIEWS),
+	("external-preview.redd.it", PREVIEWS),
+	("styles.redditmedia.com", IMAGES),
+	("www.redditstatic.com", STATIC),
+	("reddit-econ-prod-assets-permanent.s3.amazonaws.com", IMAGES),
+];
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Policy {
+	/// Prefixes of the content types the host may serve
+	pub content_types: Vec<String>,
+	/// Largest file in megabytes, overriding `LIBREDDIT_PROXY_MAX_SIZE`
+	pub max_size: Option<f64>,
+	/// Whether to remove EXIF and other metadata from JPEG images
+	pub strip_metadata: bool,
+}
+
+impl Policy {
+	/// The policy for `host`, if media may be proxied from it at all.
+	/// Configured policies take precedence over the built-in ones.
+	pub fn for_host(host: &str) -> Option<Self> {
+		let configured = get_setting("LIBREDDIT_PROXY_POLICIES").unwrap_or_default();
+		parse_policies(&configured).into_iter().find(|(h, _)| h == host).map(|(_, policy)| policy).or_else(|| {
+			BUILTIN.iter().find(|(h, _)| *h == host).map(|(_, types)| Self {
+				content_types: types.iter().map(|t| t.to_string()).collect(),
+				max_size: None,
+				strip_metadata: false,
+			})
+		})
+	}
+
+	/// Whether a response of `content_type` may be passed on.
+	pub fn allows(&self, content_type: &str) -> bool {
+		let content_type = content_type.trim().to_ascii_lowercase();
+		self.content_types.iter().any(|allowed| content_type.starts_with(allowed.as_str()))
+	}
+}
+
+// Parse policies written as space-separated `host:types:max_mb:strip`
+// entries, where `types` is a comma-separated list of content type prefixes
+// and the last two fields are optional. For example:
+// `i.imgur.com:image/,video/mp4:20:strip`
+fn parse_policies(config: &str) -> Vec<(String, Policy)> {
+	config
+		.split_whitespace()
+		.filter_map(|entry| {
+			let mut fields = entry.split(':');
+			let host = fields.next().filter(|host| !host.is_empty())?.to_ascii_lowercase();
+			let content_types: Vec<String> = fields.next()?.split(',').filter(|t| !t.is_empty()).map(str::to_ascii_lowercase).collect();
+			let max_size = fields.next().and_then(|mb| mb.parse::<f64>().ok()).filter(|&mb| mb > 0.0);
+			let strip_metadata = fields.next() == Some("strip");
+
+			(!content_types.is_empty()).then_some((
+				host,
+				Policy {
+					content_types,
+					max_size,
+					strip_metadata,
+				},
+			))
+		})
+		.collect()
+}
+
+/// Removes EXIF, XMP and comment segments from a JPEG image. Anything that
+/// isn't a well-formed JPEG is returned unchanged.
+pub(crate) fn strip_jpeg_metadata(image: &[u8]) -> Vec<u8> {
+	if !image.starts_with(&[0xFF, 0xD8]) {
+		return image.to_vec();
+	}
+
+	let mut stripped = vec![0xFF, 0xD8];
+	let mut pos = 2;
+	while pos + 4 <= image.len() && image[pos] == 0xFF {
+		let marker = image[pos + 1];
+
+		// The compressed image data follows the start of scan segment
+		if marker == 0xDA {
+			break;
+		}
+
+		let length = u16::from_be_bytes([image[pos + 2], image[pos + 3]]) as usize;
+		let end = pos + 2 + length;
+		if length < 2 || end > image.len() {
+			return image.to_vec();
+		}
+
+		// APP1 holds EXIF and XMP, APP13 holds IPTC, COM holds comments
+		if !matches!(marker, 0xE1 | 0xED | 0xFE) {
+			stripped.extend_from_slice(&image[pos..end]);
+		}
+		pos = end;
+	}
+
+	stripped.extend_from_slice(&image[pos..]);
+	stripped
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{parse_policies, strip_jpeg_metadata};
+
+	#[test]
+	fn parse_policies_works() {
+		let policies = parse_policies("i.imgur.com:image/,video/mp4:20:strip  media.example:image/ broken");
+		assert_eq!(policies.len(), 2);
+		assert_eq!(policies[0].0, "i.imgur.com");
+		assert_eq!(policies[0].1.content_types, vec!["image/", "video/mp4"]);
+		assert_eq!(policies[0].1.max_size, Some(20.0));
+		assert!(policies[0].1.strip_metadata);
+		assert_eq!(policies[1].1.max_size, None);
+		assert!(!policies[1].1.strip_metadata);
+	}
+
+	#[test]
+	fn strip_jpeg_metadata_works() {
+		let image = [
+			0xFF, 0xD8, // start of image
+			0xFF, 0xE0, 0x00, 0x04, 0x4A, 0x46, // APP0, kept
+			0xFF, 0xE1, 0x00, 0x05, 0x45, 0x78, 0x69, // APP1 (EXIF), removed
+			0xFF, 0xDA, 0x00, 0x02, 0x12, 0x34, 0xFF, 0xD9, // scan data and end
+		];
+		assert_eq!(
+			strip_jpeg_metadata(&image),
+			vec![0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x04, 0x4A, 0x46, 0xFF, 0xDA, 0x00, 0x02, 0x12, 0x34, 0xFF, 0xD9]
+		);
+		assert_eq!(strip_jpeg_metadata(b"not a jpeg"), b"not a jpeg".to_vec());
+	}
+}
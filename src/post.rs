@@ -1,17 +1,24 @@
 // CRATES
-use crate::client::json;
+use crate::client::{external_json, json};
 use crate::config::get_setting;
+use crate::export::{snapshot, text_response, thread_text, TextFormat};
+use crate::feed;
+use crate::link_preview;
+use crate::math::render_math;
 use crate::server::RequestExt;
+use crate::settings::is_valid;
 use crate::subreddit::{can_access_quarantine, quarantine};
 use crate::utils::{
-	error, format_num, get_filters, nsfw_landing, param, parse_post, rewrite_urls, setting, template, time, val, Author, Awards, Comment, Flair, FlairPart, Post, Preferences,
+	error, format_num, format_time, get_filters, gone, nsfw_landing, param, parse_post, removal_reason, render_inline_media, rewrite_urls, setting, subreddit_emojis, template,
+	time, val, wasm_error, Author, Awards, Comment, Flair, FlairPart, Post, Preferences,
 };
-use web_sys::{Request, Response};
+use serde_json::{json, Value};
+use web_sys::{Request, Response, Url};
 
 use askama::Template;
 use once_cell::sync::Lazy;
 use regex::Regex;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 // STRUCTS
 #[derive(Template)]
@@ -22,16 +29,122 @@ struct PostTemplate {
 	sort: String,
 	prefs: Preferences,
 	single_thread: bool,
+	/// How many parent comments are shown above a permalinked comment
+	context: u32,
 	url: String,
 	url_without_query: String,
 	comment_query: String,
+	/// Why the post was removed, as in "removed by Reddit", if it was
+	removal: Option<String>,
 }
 
+impl PostTemplate {
+	// schema.org structured data describing the thread, for search engines and
+	// archival tools
+	fn json_ld(&self) -> String {
+		let origin = Url::new(&self.url).map(|url| url.origin()).unwrap_or_default();
+		let person = |name: &str| json!({ "@type": "Person", "name": name, "url": format!("{}/user/{}", origin, name) });
+		let count = |value: &str| value.parse::<i64>().unwrap_or_default();
+
+		let comments: Vec<Value> = self
+			.comments
+			.iter()
+			.filter(|comment| comment.kind == "t1" && !comment.is_filtered)
+			.map(|comment| {
+				json!({
+					"@type": "Comment",
+					"author": person(&comment.author.name),
+					"text": strip_tags(&comment.body),
+					"url": format!("{}{}{}/", origin, comment.post_link, comment.id),
+					"upvoteCount": count(&comment.score.1),
+				})
+			})
+			.collect();
+
+		json!({
+			"@context": "https://schema.org",
+			"@type": "DiscussionForumPosting",
+			"headline": self.post.title,
+			"url": format!("{}{}", origin, self.post.permalink),
+			"datePublished": format_time(self.post.created_ts, "iso"),
+			"author": person(&self.post.author.name),
+			"text": strip_tags(&self.post.body),
+			"isPartOf": { "@type": "WebPage", "name": format!("r/{}", self.post.community), "url": format!("{}/r/{}", origin, self.post.community) },
+			"interactionStatistic": [
+				{ "@type": "InteractionCounter", "interactionType": "https://schema.org/LikeAction", "userInteractionCount": count(&self.post.score.1) },
+				{ "@type": "InteractionCounter", "interactionType": "https://schema.org/CommentAction", "userInteractionCount": count(&self.post.comments.1) },
+			],
+			"comment": comments,
+		})
+		.to_string()
+		// Keep the JSON from closing the surrounding script element
+		.replace('<', "\\u003c")
+	}
+}
+
+static TAG_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+// Plain text of an HTML post or comment body
+pub(crate) fn strip_tags(html: &str) -> String {
+	TAG_REGEX
+		.replace_all(html, "")
+		.replace("&lt;", "<")
+		.replace("&gt;", ">")
+		.replace("&quot;", "\"")
+		.replace("&#39;", "'")
+		.replace("&amp;", "&")
+		.trim()
+		.to_string()
+}
+
+#[derive(Template)]
+#[template(path = "archived.html")]
+struct ArchivedCommentTemplate {
+	author: String,
+	/// Markdown source of the comment, as archived
+	body: String,
+	created: String,
+	/// Link to the comment on this instance
+	permalink: String,
+	prefs: Preferences,
+	url: String,
+}
+
+#[derive(Template)]
+#[template(path = "more.html")]
+struct MoreCommentsTemplate {
+	comments: Vec<Comment>,
+	/// Link back to the thread
+	post_link: String,
+	prefs: Preferences,
+	url: String,
+}
+
+// Most comments Reddit expands in one "morechildren" request
+const MAX_MORE_CHILDREN: usize = 100;
+
+// Most parent comments Reddit shows above a permalinked comment
+const MAX_CONTEXT: u32 = 8;
+
 static COMMENT_SEARCH_CAPTURE: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\?q=(.*)&type=comment"#).unwrap());
 
 pub async fn item(req: Request) -> Result<Response, String> {
+	let single_thread = req.param("comment_id").is_some();
+	let highlighted_comment = &req.param("comment_id").unwrap_or_default();
+
+	// Forward how many parents of a permalinked comment to show, within
+	// Reddit's limit
+	let params = req.uri().search_params();
+	let context = if single_thread { context_depth(params.get("context").as_deref()) } else { 0 };
+	if context > 0 {
+		params.set("context", &context.to_string());
+	} else {
+		params.delete("context");
+	}
+	let search = String::from(params.to_string());
+
 	// Build Reddit API path
-	let mut path: String = format!("{}.json{}&raw_json=1", req.uri().pathname(), req.uri().search());
+	let mut path: String = format!("{}.json?{}&raw_json=1", req.route_path(), search);
 	let sub = req.param("sub").unwrap_or_default();
 	let quarantined = can_access_quarantine(&req, &sub);
 	let url = req.uri().as_string().unwrap_or_default();
@@ -45,7 +158,7 @@ pub async fn item(req: Request) -> Result<Response, String> {
 		if default_sort.is_empty() {
 			String::new()
 		} else {
-			path = format!("{}.json{}&sort={}&raw_json=1", req.uri().pathname(), req.uri().search(), default_sort);
+			path = format!("{}.json?{}&sort={}&raw_json=1", req.route_path(), search, default_sort);
 			default_sort
 		}
 	});
@@ -54,15 +167,22 @@ pub async fn item(req: Request) -> Result<Response, String> {
 	#[cfg(debug_assertions)]
 	dbg!(req.param("id").unwrap_or_default());
 
-	let single_thread = req.param("comment_id").is_some();
-	let highlighted_comment = &req.param("comment_id").unwrap_or_default();
-
 	// Send a request to the url, receive JSON in response
 	match json(path, quarantined).await {
 		// Otherwise, grab the JSON output from the request
 		Ok(response) => {
 			// Parse the JSON into Post and Comment structs
-			let post = parse_post(&response[0]["data"]["children"][0]).await;
+			let mut post = parse_post(&response[0]["data"]["children"][0]).await;
+
+			// Render math from the original body, before backslashes are stripped from it
+			let post_data = &response[0]["data"]["children"][0];
+			if setting(&req, "render_math") == "on" && val(post_data, "removed_by_category") != "moderator" {
+				post.body = rewrite_urls(&render_math(&val(post_data, "selftext_html")));
+			}
+
+			// Without a sort of its own, Reddit uses the one the thread suggests,
+			// such as Q&A for AMAs, so show that in the selector
+			let sort = if sort.is_empty() { val(post_data, "suggested_sort") } else { sort };
 
 			let req_url = req.uri().as_string().unwrap_or_default();
 			// Return landing page if this post if this Reddit deems this post
@@ -72,27 +192,62 @@ pub async fn item(req: Request) -> Result<Response, String> {
 				return Ok(nsfw_landing(req, req_url).await.unwrap());
 			}
 
+			if let Some(format) = param(&url, "export").and_then(|export| TextFormat::from_param(&export)) {
+				let text = thread_text(&post_data["data"], &response[1], format, param(&url, "depth").as_deref(), &get_filters(&req));
+				return text_response(&text, format, &format!("{}-{}", post.community, post.id));
+			}
+
 			let query = match COMMENT_SEARCH_CAPTURE.captures(&url) {
 				Some(captures) => captures.get(1).unwrap().as_str().replace("%20", " ").replace('+', " "),
 				None => String::new(),
 			};
 
-			let comments = match query.as_str() {
+			let mut comments = match query.as_str() {
 				"" => parse_comments(&response[1], &post.permalink, &post.author.name, highlighted_comment, &get_filters(&req), &req),
 				_ => query_comments(&response[1], &post.permalink, &post.author.name, highlighted_comment, &get_filters(&req), &query, &req),
 			};
 
+			// A removed post without comments has nothing left to show
+			let removal = removal_reason(&val(post_data, "removed_by_category"), &post.community);
+			if let Some(reason) = &removal {
+				if comments.is_empty() && query.is_empty() && !single_thread {
+					return gone(req, format!("This post was {}", reason)).await;
+				}
+			}
+
+			let emojis = subreddit_emojis(&post.community).await;
+			post.apply_emojis(&emojis);
+			apply_comment_emojis(&mut comments, &emojis);
+
+			if setting(&req, "link_titles") == "on" {
+				link_preview::add_title(&mut post).await;
+			}
+
+			if let Some(res) = feed::thread(&req, &post, &comments) {
+				return res;
+			}
+
+			let filename = format!("{}-{}.html", post.community, post.id);
+
 			// Use the Post and Comment structs to generate a website to show users
-			template(PostTemplate {
+			let page = PostTemplate {
 				comments,
 				post,
 				url_without_query: url.clone().trim_end_matches(&format!("?q={query}&type=comment")).to_string(),
 				sort,
 				prefs: Preferences::new(&req),
 				single_thread,
+				context,
 				url: req_url,
 				comment_query: query,
-			})
+				removal,
+			};
+
+			if param(&url, "export").is_some() {
+				return snapshot(&page.render().map_err(|e| e.to_string())?, &req, &filename).await;
+			}
+
+			template(page)
 		}
 		// If the Reddit API returns an error, exit and send error page to user
 		Err(msg) => {
@@ -108,6 +263,153 @@ pub async fn item(req: Request) -> Result<Response, String> {
 
 // COMMENTS
 
+// Base URL of the Pushshift-compatible archive API, if the instance has one
+fn archive_api() -> Option<String> {
+	get_setting("LIBREDDIT_ARCHIVE_API")
+		.filter(|url| !url.is_empty())
+		.map(|url| url.trim_end_matches('/').to_string())
+}
+
+// Show the archived copy of a removed comment, fetched from the archive API
+pub async fn archived_comment(req: Request) -> Result<Response, String> {
+	let api = match archive_api() {
+		Some(api) => api,
+		None => return error(req, "Archived comments are not available on this instance").await,
+	};
+
+	let id = req.param("id").unwrap_or_default();
+	if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+		return error(req, "Invalid comment ID").await;
+	}
+
+	match external_json(format!("{}/reddit/search/comment/?ids={}", api, id)).await {
+		Ok(response) => {
+			let comment = &response["data"][0];
+			if !comment.is_object() {
+				return error(req, "No archived copy of this comment was found").await;
+			}
+
+			// Older archives don't store the permalink, so build it from the post
+			// ID. The archive is a third party, so only links to threads are kept.
+			let permalink = match comment["permalink"]
+				.as_str()
+				.filter(|permalink| permalink.starts_with("/r/") || permalink.starts_with("/comments/"))
+			{
+				Some(permalink) => permalink.to_string(),
+				None => {
+					let link_id = comment["link_id"].as_str().unwrap_or_default().trim_start_matches("t3_");
+					let link_id: String = link_id.chars().filter(char::is_ascii_alphanumeric).collect();
+					format!("/comments/{}/_/{}/", link_id, id)
+				}
+			};
+
+			template(ArchivedCommentTemplate {
+				author: comment["author"].as_str().unwrap_or("[deleted]").to_string(),
+				body: comment["body"].as_str().unwrap_or_default().to_string(),
+				created: format_time(comment["created_utc"].as_f64().unwrap_or_default(), &setting(&req, "date_format")),
+				permalink,
+				prefs: Preferences::new(&req),
+				url: req.uri().pathname(),
+			})
+		}
+		Err(msg) => error(req, msg).await,
+	}
+}
+
+/// Expands a "more comments" stub of a thread through Reddit's
+/// `morechildren` API, at `/comments/:id/more?children=a,b,c`. With
+/// `fragment` set, only the comments are returned, to be inserted in place of
+/// the stub. Children past the first `MAX_MORE_CHILDREN` are left to a new stub.
+pub async fn more_children(req: Request) -> Result<Response, String> {
+	let id = req.param("id").unwrap_or_default();
+	let params = req.uri().search_params();
+	let mut children: Vec<String> = params
+		.get("children")
+		.unwrap_or_default()
+		.split(',')
+		.filter(|child| !child.is_empty() && child.chars().all(|c| c.is_ascii_alphanumeric()))
+		.map(String::from)
+		.collect();
+	let rest = children.split_off(children.len().min(MAX_MORE_CHILDREN));
+	if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) || children.is_empty() {
+		return error(req, "There are no comments to load").await;
+	}
+
+	let path = format!(
+		"/api/morechildren.json?api_type=json&link_id=t3_{}&children={}&sort={}&raw_json=1",
+		id,
+		children.join(","),
+		params
+			.get("sort")
+			.filter(|sort| is_valid("comment_sort", sort))
+			.unwrap_or_else(|| setting(&req, "comment_sort")),
+	);
+	let response = match json(path, false).await {
+		Ok(response) => response,
+		Err(msg) => return error(req, msg).await,
+	};
+	let things = response["json"]["data"]["things"].as_array().map_or(Vec::new(), ToOwned::to_owned);
+
+	// Comments link to themselves, so the thread's link is their permalink
+	// without the last part
+	let post_link = things
+		.iter()
+		.find_map(|thing| thing["data"]["permalink"].as_str())
+		.and_then(|permalink| permalink.trim_end_matches('/').rsplit_once('/'))
+		.map_or_else(|| format!("/comments/{}/", id), |(link, _)| format!("{}/", link));
+
+	// Reddit returns the comments as a flat list; those whose parent isn't in
+	// it hang from the stub
+	let names: HashSet<String> = things.iter().map(|thing| val(thing, "name")).collect();
+	let op = params.get("op").unwrap_or_default();
+	let mut comments = nest_children(&things, None, &names, &post_link, &op, &get_filters(&req), &req);
+
+	// Reddit expands only so many comments at once, the others get a stub of
+	// their own
+	if !rest.is_empty() {
+		let stub = json!({ "kind": "more", "data": { "count": rest.len(), "children": rest, "parent_id": format!("t3_{}", id) } });
+		comments.push(build_comment(&stub, &stub["data"], Vec::new(), &post_link, &op, "", &HashSet::new(), &req));
+	}
+
+	if params.get("fragment").is_some() {
+		let html: String = comments.iter().filter_map(|comment| comment.render().ok()).collect();
+		let res = Response::new_with_opt_str(Some(&html)).map_err(wasm_error)?;
+		res.headers().set("content-type", "text/html").ok();
+		return Ok(res);
+	}
+
+	template(MoreCommentsTemplate {
+		comments,
+		post_link,
+		prefs: Preferences::new(&req),
+		url: req.uri().pathname(),
+	})
+}
+
+// Build the comments among `things` whose parent is `parent`, or, without a
+// parent, those whose parent isn't among `names`
+fn nest_children(things: &[Value], parent: Option<&str>, names: &HashSet<String>, post_link: &str, op: &str, filters: &HashSet<String>, req: &Request) -> Vec<Comment> {
+	things
+		.iter()
+		.filter(|thing| {
+			let parent_id = val(thing, "parent_id");
+			parent.map_or(!names.contains(&parent_id), |parent| parent_id == parent)
+		})
+		.map(|thing| {
+			let replies = nest_children(things, Some(&val(thing, "name")), names, post_link, op, filters, req);
+			build_comment(thing, &thing["data"], replies, post_link, op, "", filters, req)
+		})
+		.collect()
+}
+
+// Show subreddit emoji in the flairs of commenters, throughout the tree
+fn apply_comment_emojis(comments: &mut [Comment], emojis: &HashMap<String, String>) {
+	for comment in comments {
+		comment.author.flair.apply_emojis(emojis);
+		apply_comment_emojis(&mut comment.replies, emojis);
+	}
+}
+
 fn parse_comments(json: &serde_json::Value, post_link: &str, post_author: &str, highlighted_comment: &str, filters: &HashSet<String>, req: &Request) -> Vec<Comment> {
 	// Parse the comment JSON into a Vector of Comments
 	let comments = json["data"]["children"].as_array().map_or(Vec::new(), std::borrow::ToOwned::to_owned);
@@ -166,24 +468,43 @@ fn build_comment(
 	filters: &HashSet<String>,
 	req: &Request,
 ) -> Comment {
+	crate::schema::check(comment);
 	let id = val(comment, "id");
 
 	let body = if (val(comment, "author") == "[deleted]" && val(comment, "body") == "[removed]") || val(comment, "body") == "[ Removed by Reddit ]" {
+		// Link to the archived copy on this instance if an archive API is set up
+		let archived = match archive_api() {
+			Some(_) => format!(" — <a href=\"/archive/comment/{}\" rel=\"nofollow\">show archived copy</a>", id),
+			None => String::new(),
+		};
 		format!(
-			"<div class=\"md\"><p>[removed] — <a href=\"https://{}{}{}\">view removed comment</a></p></div>",
+			"<div class=\"md\"><p>[removed] — <a href=\"https://{}{}{}\">view removed comment</a>{}</p></div>",
 			get_setting("LIBREDDIT_PUSHSHIFT_FRONTEND").unwrap_or(String::from(crate::config::DEFAULT_PUSHSHIFT_FRONTEND)),
 			post_link,
-			id
+			id,
+			archived
 		)
 	} else {
-		rewrite_urls(&val(comment, "body_html"))
+		let mut body_html = val(comment, "body_html");
+		if setting(req, "render_math") == "on" {
+			body_html = render_math(&body_html);
+		}
+		render_inline_media(
+			&rewrite_urls(&body_html),
+			&data["media_metadata"],
+			setting(req, "hide_inline_media") == "on" || setting(req, "data_saver") == "on",
+		)
 	};
 	let kind = comment["kind"].as_str().unwrap_or_default().to_string();
 
+	let date_format = setting(req, "date_format");
 	let unix_time = data["created_utc"].as_f64().unwrap_or_default();
-	let (rel_time, created) = time(unix_time);
+	let (_, created) = time(unix_time);
+	let rel_time = format_time(unix_time, &date_format);
 
-	let edited = data["edited"].as_f64().map_or((String::new(), String::new()), time);
+	let edited = data["edited"]
+		.as_f64()
+		.map_or((String::new(), String::new()), |edited| (format_time(edited, &date_format), time(edited).1));
 
 	let score = data["score"].as_i64().unwrap_or(0);
 
@@ -193,6 +514,10 @@ fn build_comment(
 	// shows how many more (sub-)comments exist in the respective nesting level.
 	// Note that in certain (seemingly random) cases, the count is simply wrong.
 	let more_count = data["count"].as_i64().unwrap_or_default();
+	let more_ids = data["children"]
+		.as_array()
+		.map(|ids| ids.iter().filter_map(|id| id.as_str()).collect::<Vec<_>>().join(","))
+		.unwrap_or_default();
 
 	let awards: Awards = Awards::parse(&data["all_awardings"]);
 
@@ -201,6 +526,9 @@ fn build_comment(
 
 	let highlighted = id == highlighted_comment;
 
+	// Keep the path down to a permalinked comment expanded
+	let highlighted_below = replies.iter().any(|reply| reply.highlighted || reply.highlighted_below);
+
 	let author = Author {
 		name: val(comment, "author"),
 		flair: Flair {
@@ -241,13 +569,35 @@ fn build_comment(
 		},
 		rel_time,
 		created,
+		created_ts: unix_time,
 		edited,
 		replies,
 		highlighted,
+		highlighted_below,
 		awards,
 		collapsed,
 		is_filtered,
 		more_count,
+		more_ids,
 		prefs: Preferences::new(req),
 	}
 }
+
+// The number of parent comments asked for with `?context=`, at most
+// `MAX_CONTEXT`
+fn context_depth(context: Option<&str>) -> u32 {
+	context.and_then(|context| context.parse::<u32>().ok()).unwrap_or_default().min(MAX_CONTEXT)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::context_depth;
+
+	#[test]
+	fn context_depth_works() {
+		assert_eq!(context_depth(Some("3")), 3);
+		assert_eq!(context_depth(Some("9999")), 8);
+		assert_eq!(context_depth(Some("-1")), 0);
+		assert_eq!(context_depth(None), 0);
+	}
+}
@@ -0,0 +1,113 @@
+// Scaling down of proxied images, asked for with `?w=` (largest width in
+// pixels) and `?q=` (JPEG quality), so visitors on phones and metered
+// connections don't download full-size images.
+
+// CRATES
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::imageops::FilterType;
+use image::io::Reader;
+use image::ImageEncoder;
+use std::io::Cursor;
+
+// Narrowest and widest width an image can be scaled to, in pixels
+const MIN_WIDTH: u32 = 16;
+const MAX_WIDTH: u32 = 2048;
+
+// Range of JPEG qualities, and the one used when only a width is given
+const MIN_QUALITY: u8 = 20;
+const MAX_QUALITY: u8 = 95;
+const DEFAULT_QUALITY: u8 = 80;
+
+// Largest image decoded, in pixels, to stay within the memory of a worker. A
+// decoded image takes 4 bytes a pixel, next to the original and the result,
+// and a Cloudflare isolate has 128 MB for everything.
+const MAX_PIXELS: u64 = 4_000_000;
+
+/// How a proxied image should be scaled and re-encoded.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Resize {
+	pub width: Option<u32>,
+	pub quality: u8,
+}
+
+impl Resize {
+	/// The resizing asked for with the `w` and `q` parameters, if any.
+	/// Values out of range are clamped; anything else is ignored.
+	pub fn from_params(width: Option<String>, quality: Option<String>) -> Option<Self> {
+		let width = width.and_then(|w| w.parse::<u32>().ok()).map(|w| w.clamp(MIN_WIDTH, MAX_WIDTH));
+		let quality = quality.and_then(|q| q.parse::<u8>().ok()).map(|q| q.clamp(MIN_QUALITY, MAX_QUALITY));
+		if width.is_none() && quality.is_none() {
+			return None;
+		}
+
+		Some(Self {
+			width,
+			quality: quality.unwrap_or(DEFAULT_QUALITY),
+		})
+	}
+
+	/// Scales down and re-encodes `image`, returning the new image and its
+	/// content type. Images with transparency stay PNGs, the rest become
+	/// JPEGs. Nothing is returned if the image can't be decoded, is too
+	/// large to decode, or wouldn't get smaller.
+	pub fn apply(&self, image: &[u8]) -> Option<(Vec<u8>, &'static str)> {
+		let reader = Reader::new(Cursor::new(image)).with_guessed_format().ok()?;
+		let (width, height) = reader.into_dimensions().ok()?;
+		if u64::from(width) * u64::from(height) > MAX_PIXELS {
+			return None;
+		}
+
+		let decoded = Reader::new(Cursor::new(image)).with_guessed_format().ok()?.decode().ok()?;
+		let scaled = match self.width {
+			Some(target) if target < width => decoded.resize(target, u32::MAX, FilterType::Triangle),
+			_ => decoded,
+		};
+
+		let mut encoded = Vec::new();
+		let content_type = if scaled.color().has_alpha() {
+			let rgba = scaled.to_rgba8();
+			PngEncoder::new(&mut encoded)
+				.write_image(&rgba, rgba.width(), rgba.height(), image::ColorType::Rgba8)
+				.ok()?;
+			"image/png"
+		} else {
+			let rgb = scaled.to_rgb8();
+			JpegEncoder::new_with_quality(&mut encoded, self.quality)
+				.write_image(&rgb, rgb.width(), rgb.height(), image::ColorType::Rgb8)
+				.ok()?;
+			"image/jpeg"
+		};
+
+		(encoded.len() < image.len()).then_some((encoded, content_type))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Resize;
+	use image::{ImageOutputFormat, RgbImage};
+	use std::io::Cursor;
+
+	#[test]
+	fn from_params_works() {
+		assert_eq!(Resize::from_params(None, None), None);
+		assert_eq!(Resize::from_params(Some("640".into()), None), Some(Resize { width: Some(640), quality: 80 }));
+		assert_eq!(Resize::from_params(Some("99999".into()), Some("5".into())), Some(Resize { width: Some(2048), quality: 20 }));
+		assert_eq!(Resize::from_params(Some("wide".into()), Some("60".into())), Some(Resize { width: None, quality: 60 }));
+	}
+
+	#[test]
+	fn apply_scales_down() {
+		let mut png = Vec::new();
+		RgbImage::from_fn(400, 200, |x, y| image::Rgb([(x % 256) as u8, (y % 256) as u8, 128]))
+			.write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+			.unwrap();
+
+		let (resized, content_type) = Resize::from_params(Some("100".into()), None).unwrap().apply(&png).unwrap();
+		assert_eq!(content_type, "image/jpeg");
+		assert_eq!(image::load_from_memory(&resized).unwrap().width(), 100);
+		assert_eq!(image::load_from_memory(&resized).unwrap().height(), 50);
+		assert_eq!(Resize::from_params(Some("100".into()), None).unwrap().apply(b"not an image"), None);
+	}
+}
@@ -0,0 +1,100 @@
+// Checks of Reddit's responses for the fields Libreddit reads from them. A
+// missing field is logged the first time it's seen and counted, and the
+// counts are shown on the status page, so operators notice when Reddit
+// changes its API before visitors report broken pages.
+
+// CRATES
+use once_cell::sync::Lazy;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+// Fields read from each kind of Reddit object, by kind prefix
+const EXPECTED: [(&str, &str, &[&str]); 4] = [
+	("t1", "comment", &["id", "author", "body", "body_html", "created_utc", "score"]),
+	(
+		"t3",
+		"post",
+		&[
+			"id",
+			"title",
+			"subreddit",
+			"author",
+			"permalink",
+			"created_utc",
+			"score",
+			"upvote_ratio",
+			"num_comments",
+			"url",
+			"domain",
+			"over_18",
+		],
+	),
+	(
+		"t5",
+		"subreddit",
+		&["display_name", "title", "public_description", "description_html", "subscribers", "icon_img", "over18"],
+	),
+	("t2", "user", &["name", "created", "total_karma", "subreddit"]),
+];
+
+// Times each field was found missing, by "kind.field"
+static DRIFT: Lazy<Mutex<HashMap<String, u64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Checks a Reddit object (with `kind` and `data`) for the fields Libreddit
+/// reads from objects of its kind, and records the missing ones.
+pub(crate) fn check(thing: &Value) {
+	let missing = missing_fields(thing);
+	if missing.is_empty() {
+		return;
+	}
+
+	if let Ok(mut drift) = DRIFT.lock() {
+		for field in missing {
+			let count = drift.entry(field.clone()).or_default();
+			if *count == 0 {
+				eprintln!("Reddit's response is missing the {} field, pages using it may break", field);
+			}
+			*count += 1;
+		}
+	}
+}
+
+/// Missing fields seen since the instance started, as ("kind.field", times)
+/// pairs, most frequent first.
+pub(crate) fn missing() -> Vec<(String, u64)> {
+	let mut fields: Vec<(String, u64)> = DRIFT
+		.lock()
+		.map(|drift| drift.iter().map(|(field, &count)| (field.clone(), count)).collect())
+		.unwrap_or_default();
+	fields.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+	fields
+}
+
+// The expected fields `thing` lacks, as "kind.field"
+fn missing_fields(thing: &Value) -> Vec<String> {
+	let kind = thing["kind"].as_str().unwrap_or_default();
+	let (name, fields) = match EXPECTED.iter().find(|(prefix, _, _)| *prefix == kind) {
+		Some((_, name, fields)) => (name, fields),
+		None => return Vec::new(),
+	};
+
+	fields
+		.iter()
+		.filter(|field| thing["data"].get(field).is_none())
+		.map(|field| format!("{}.{}", name, field))
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::missing_fields;
+	use serde_json::json;
+
+	#[test]
+	fn missing_fields_works() {
+		let comment = json!({ "kind": "t1", "data": { "id": "a", "author": "b", "body": "c", "created_utc": 1.0, "score": null } });
+		assert_eq!(missing_fields(&comment), vec!["comment.body_html"]);
+		assert!(missing_fields(&json!({ "kind": "more", "data": {} })).is_empty());
+	}
+}
@@ -1,13 +1,18 @@
 // CRATES
-use crate::utils::{self, catch_random, error, filter_posts, format_num, format_url, get_filters, param, redirect, setting, template, val, Post, Preferences};
+use crate::utils::{
+	self, catch_random, error, filter_posts, format_num, format_url, get_filters, param, redirect, remove_hidden_posts, setting, template, val, Post, Preferences,
+};
 use crate::{
-	client::json,
+	client::{external_json, json},
+	config::get_setting,
+	feed,
 	subreddit::{can_access_quarantine, quarantine},
 	RequestExt,
 };
 use askama::Template;
 use once_cell::sync::Lazy;
 use regex::Regex;
+use std::collections::HashMap;
 use web_sys::{Request, Response};
 
 // STRUCTS
@@ -36,7 +41,13 @@ struct SearchTemplate {
 	posts: Vec<Post>,
 	subreddits: Vec<Subreddit>,
 	sub: String,
+	/// The user whose posts are searched, if searching from a profile page.
+	author: String,
 	params: SearchParams,
+	/// Communities the same post was shared in, by the ID of the result shown
+	/// for them, as names and links. Only posts shared more than once are
+	/// included.
+	crossposts: HashMap<String, Vec<(String, String)>>,
 	prefs: Preferences,
 	url: String,
 	/// Whether the subreddit itself is filtered.
@@ -49,6 +60,46 @@ struct SearchTemplate {
 	no_posts: bool,
 }
 
+// Where posts are searched, chosen with `LIBREDDIT_SEARCH_PROVIDER`. An
+// index is any service that answers Reddit's search paths, like
+// `/r/rust/search.json?q=...`, with a listing in Reddit's JSON format.
+enum Provider {
+	Reddit,
+	Index(String),
+	/// Reddit first, then the index when Reddit fails or finds nothing
+	Fallback(String),
+}
+
+impl Provider {
+	fn configured() -> Self {
+		let index = get_setting("LIBREDDIT_SEARCH_INDEX_URL").filter(|url| !url.is_empty());
+		match (get_setting("LIBREDDIT_SEARCH_PROVIDER").as_deref(), index) {
+			(Some("index"), Some(url)) => Self::Index(url),
+			(Some("fallback"), Some(url)) => Self::Fallback(url),
+			_ => Self::Reddit,
+		}
+	}
+
+	// Search posts with a Reddit search `path`
+	async fn search(&self, path: &str, quarantined: bool) -> Result<(Vec<Post>, String), String> {
+		match self {
+			Self::Reddit => Post::fetch(path, quarantined).await,
+			Self::Index(url) => search_index(url, path).await,
+			Self::Fallback(url) => match Post::fetch(path, quarantined).await {
+				Ok((posts, after)) if !posts.is_empty() => Ok((posts, after)),
+				// Access walls are shown rather than worked around
+				Err(msg) if msg == "quarantined" || msg == "gated" => Err(msg),
+				reddit => search_index(url, path).await.or(reddit),
+			},
+		}
+	}
+}
+
+async fn search_index(url: &str, path: &str) -> Result<(Vec<Post>, String), String> {
+	let listing = external_json(format!("{}{}", url.trim_end_matches('/'), path)).await?;
+	Post::from_listing(&listing).await
+}
+
 // Regex matched against search queries to determine if they are reddit urls.
 static REDDIT_URL_MATCH: Lazy<Regex> = Lazy::new(|| Regex::new(r"^https?://([^\./]+\.)*reddit.com/").unwrap());
 
@@ -60,12 +111,30 @@ pub async fn find(req: Request) -> Result<Response, String> {
 	} else {
 		""
 	};
-	let path = format!("{}.json{}{}&raw_json=1", req.uri().pathname(), req.uri().search(), nsfw_results);
+	let sort_query = utils::default_sort_query(&req, "search_sort");
+	let mut path = format!("{}.json{}{}{}&raw_json=1", req.route_path(), utils::listing_query(&req), sort_query, nsfw_results);
 	let mut query = param(&path, "q").unwrap_or_default();
 	query = REDDIT_URL_MATCH.replace(&query, "").to_string();
 
+	// Searches from a profile page are run against Reddit's global search,
+	// restricted to that user with the `author:` operator
+	let author = req.param("name").unwrap_or_default();
+
 	if query.is_empty() {
-		return Ok(redirect("/".to_string()));
+		return Ok(redirect(if author.is_empty() { "/".to_string() } else { format!("/user/{}", author) }));
+	}
+
+	if !author.is_empty() {
+		let params = req.uri().search_params();
+
+		// Without the restriction this is a normal search
+		if params.get("restrict_sr").is_none() {
+			return Ok(redirect(format!("/search?{}", String::from(params.to_string()))));
+		}
+
+		params.set("q", &format!("author:{} {}", author, query));
+		params.set("limit", &utils::listing_limit(&req));
+		path = format!("/search.json?{}{}{}&raw_json=1", String::from(params.to_string()), sort_query, nsfw_results);
 	}
 
 	if query.starts_with("r/") {
@@ -84,8 +153,8 @@ pub async fn find(req: Request) -> Result<Response, String> {
 	let sort = param(&path, "sort").unwrap_or_else(|| "relevance".to_string());
 	let filters = get_filters(&req);
 
-	// If search is not restricted to this subreddit, show other subreddits in search results
-	let subreddits = if param(&path, "restrict_sr").is_none() {
+	// If search is not restricted to this subreddit or user, show other subreddits in search results
+	let subreddits = if param(&path, "restrict_sr").is_none() && author.is_empty() {
 		let mut subreddits = search_subreddits(&query, &typed).await;
 		subreddits.retain(|s| !filters.contains(s.name.as_str()));
 		subreddits
@@ -101,6 +170,7 @@ pub async fn find(req: Request) -> Result<Response, String> {
 			posts: Vec::new(),
 			subreddits,
 			sub,
+			author,
 			params: SearchParams {
 				q: query.replace('"', "&quot;"),
 				sort,
@@ -110,6 +180,7 @@ pub async fn find(req: Request) -> Result<Response, String> {
 				restrict_sr: param(&path, "restrict_sr").unwrap_or_default(),
 				typed,
 			},
+			crossposts: HashMap::new(),
 			prefs: Preferences::new(&req),
 			url,
 			is_filtered: true,
@@ -118,15 +189,22 @@ pub async fn find(req: Request) -> Result<Response, String> {
 			no_posts: false,
 		})
 	} else {
-		match Post::fetch(&path, quarantined).await {
+		match Provider::configured().search(&path, quarantined).await {
 			Ok((mut posts, after)) => {
+				remove_hidden_posts(&mut posts, &req);
 				let (_, all_posts_filtered) = filter_posts(&mut posts, &filters);
+				if let Some(res) = feed::listing(&req, &format!("Search results for {}", query), &posts, &after, None) {
+					return res;
+				}
+
+				let crossposts = collapse_crossposts(&mut posts);
 				let no_posts = posts.is_empty();
 				let all_posts_hidden_nsfw = !no_posts && (posts.iter().all(|p| p.flags.nsfw) && setting(&req, "show_nsfw") != "on");
 				template(SearchTemplate {
 					posts,
 					subreddits,
 					sub,
+					author,
 					params: SearchParams {
 						q: query.replace('"', "&quot;"),
 						sort,
@@ -136,6 +214,7 @@ pub async fn find(req: Request) -> Result<Response, String> {
 						restrict_sr: param(&path, "restrict_sr").unwrap_or_default(),
 						typed,
 					},
+					crossposts,
 					prefs: Preferences::new(&req),
 					url,
 					is_filtered: false,
@@ -156,6 +235,23 @@ pub async fn find(req: Request) -> Result<Response, String> {
 	}
 }
 
+// Keep only the first result for each post, counting its crossposts as the
+// same post, and return the communities each one was shared in
+fn collapse_crossposts(posts: &mut Vec<Post>) -> HashMap<String, Vec<(String, String)>> {
+	let mut shown: HashMap<String, String> = HashMap::new();
+	let mut crossposts: HashMap<String, Vec<(String, String)>> = HashMap::new();
+
+	posts.retain(|post| {
+		let original = post.crosspost.as_ref().map_or(&post.permalink, |parent| &parent.permalink);
+		let id = shown.entry(original.clone()).or_insert_with(|| post.id.clone());
+		crossposts.entry(id.clone()).or_default().push((post.community.clone(), post.permalink.clone()));
+		*id == post.id
+	});
+
+	crossposts.retain(|_, communities| communities.len() > 1);
+	crossposts
+}
+
 async fn search_subreddits(q: &str, typed: &str) -> Vec<Subreddit> {
 	let limit = if typed == "sr_user" { "50" } else { "3" };
 	let subreddit_search_path = format!("/subreddits/search.json?q={}&limit={}", q.replace(' ', "+"), limit);
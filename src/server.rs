@@ -1,15 +1,107 @@
 use cookie::Cookie;
 use futures_lite::{future::BoxedLocal, FutureExt};
-use js_sys::Map;
+use js_sys::{Date, Map};
+use once_cell::sync::Lazy;
 use route_recognizer::{Params, Router};
-use std::{collections::HashMap, result::Result, string::ToString};
+use std::{
+	cell::RefCell,
+	collections::HashMap,
+	future::Future,
+	pin::Pin,
+	result::Result,
+	string::ToString,
+	sync::Mutex,
+	task::{Context, Poll},
+};
 use time::Duration;
-use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen::prelude::*;
 use web_sys::{Headers, Request, Response, ResponseInit, Url};
 
 use crate::utils::wasm_error;
 
-type BoxResponse = BoxedLocal<Result<Response, String>>;
+pub type BoxResponse = BoxedLocal<Result<Response, String>>;
+
+#[wasm_bindgen]
+extern "C" {
+	#[wasm_bindgen(js_namespace = console, js_name = error)]
+	fn log_error(message: &str);
+}
+
+/// Path prefixes of media proxied from Reddit. Reddit names these files
+/// after their contents, so they never change.
+pub(crate) const PROXY_PREFIXES: [&str; 11] = ["vid", "hls", "img", "thumb", "emoji", "emote", "preview", "style", "static", "favicon", "download"];
+
+// How many rendered pages are kept to show while Reddit is unavailable. Each
+// is kept for as long as the cache policy of its path allows its data to be
+// shown.
+const PAGE_CACHE_SIZE: usize = 50;
+
+// A rendered page, with the times (in milliseconds since the epoch) at which
+// it was rendered and at which it can't be shown anymore
+#[derive(Clone)]
+struct KeptPage {
+	rendered: f64,
+	expires: f64,
+	body: String,
+}
+
+// Rendered pages by path, query and cookies
+static PAGE_CACHE: Lazy<Mutex<HashMap<String, KeptPage>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Representations a page can be rendered as, chosen with the `Accept`
+/// header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+	Html,
+	Json,
+	Rss,
+}
+
+impl Format {
+	/// The format an `Accept` header prefers, or `None` if it accepts none of
+	/// them. Clients that don't send the header get HTML.
+	pub fn negotiate(accept: &str) -> Option<Self> {
+		if accept.trim().is_empty() {
+			return Some(Self::Html);
+		}
+
+		let mut best: Option<(Self, f32)> = None;
+		for range in accept.split(',') {
+			let mut parts = range.split(';').map(str::trim);
+			let format = match parts.next().unwrap_or_default().to_ascii_lowercase().as_str() {
+				"text/html" | "application/xhtml+xml" | "text/*" | "*/*" => Self::Html,
+				"application/json" => Self::Json,
+				"application/rss+xml" => Self::Rss,
+				_ => continue,
+			};
+			let quality = parts.find_map(|param| param.strip_prefix("q=")).and_then(|q| q.parse::<f32>().ok()).unwrap_or(1.0);
+
+			// The first of equally preferred formats wins
+			if quality > 0.0 && best.is_none_or(|(_, q)| quality > q) {
+				best = Some((format, quality));
+			}
+		}
+
+		best.map(|(format, _)| format)
+	}
+
+	/// The format a path asks for with a file extension, like `/r/rust.rss`
+	/// or `/comments/abc.json`, and the path without the extension.
+	pub fn from_path(path: &str) -> Option<(Self, &str)> {
+		match path.strip_suffix(".rss") {
+			Some(path) => Some((Self::Rss, path)),
+			None => path.strip_suffix(".json").map(|path| (Self::Json, path)),
+		}
+	}
+
+	pub fn content_type(self) -> &'static str {
+		match self {
+			Self::Html => "text/html",
+			Self::Json => "application/json",
+			Self::Rss => "application/rss+xml",
+		}
+	}
+}
 
 pub struct Route<'a> {
 	router: &'a mut Router<fn(Request) -> BoxResponse>,
@@ -18,6 +110,11 @@ pub struct Route<'a> {
 
 pub struct Server {
 	pub default_headers: HashMap<String, String>,
+
+	/// Checked in order before a matched route runs. The first one to return
+	/// a response handles the request instead of the route.
+	pub interceptors: Vec<fn(&Request) -> Option<BoxResponse>>,
+
 	router: Router<fn(Request) -> BoxResponse>,
 }
 
@@ -41,6 +138,11 @@ pub trait RequestExt {
 	fn set_params(&mut self, params: Params);
 	fn cookies(&self) -> Vec<Cookie>;
 	fn cookie(&self, name: &str) -> Option<Cookie>;
+	fn client_ip(&self) -> Option<String>;
+	fn client_country(&self) -> Option<String>;
+	fn client_ip_for_log(&self) -> String;
+	fn format(&self) -> Format;
+	fn route_path(&self) -> String;
 }
 
 pub trait ResponseExt {
@@ -95,6 +197,64 @@ impl RequestExt for Request {
 	fn cookie(&self, name: &str) -> Option<Cookie> {
 		self.cookies().into_iter().find(|c| c.name() == name)
 	}
+
+	// The address of the client, as reported by Cloudflare or another reverse
+	// proxy in front of the instance. These headers can be forged by clients
+	// when the instance is reachable without going through the proxy.
+	fn client_ip(&self) -> Option<String> {
+		let header = |name: &str| {
+			self
+				.headers()
+				.get(name)
+				.ok()
+				.flatten()
+				.map(|value| value.trim().to_string())
+				.filter(|value| !value.is_empty())
+		};
+
+		header("CF-Connecting-IP")
+			.or_else(|| header("X-Forwarded-For").and_then(|value| value.split(',').next().map(|ip| ip.trim().to_string())))
+			.or_else(|| header("X-Real-IP"))
+	}
+
+	// The two-letter country code of the client, as reported by Cloudflare.
+	// Unknown locations ("XX") and Tor exit nodes ("T1") are ignored.
+	fn client_country(&self) -> Option<String> {
+		self
+			.headers()
+			.get("CF-IPCountry")
+			.ok()
+			.flatten()
+			.map(|country| country.trim().to_uppercase())
+			.filter(|country| country.len() == 2 && country.chars().all(|c| c.is_ascii_alphabetic()) && country != "XX")
+	}
+
+	// The client address to use in log messages. Addresses are only logged if
+	// the operator has opted in with LIBREDDIT_LOG_CLIENT_IP.
+	fn client_ip_for_log(&self) -> String {
+		if crate::config::get_setting("LIBREDDIT_LOG_CLIENT_IP").is_some_and(|val| val == "on") {
+			self.client_ip().unwrap_or_else(|| "-".to_string())
+		} else {
+			"[redacted]".to_string()
+		}
+	}
+
+	// The representation the client asked for, with a file extension or the
+	// `Accept` header. Pages that can't be rendered that way are refused by the
+	// server.
+	fn format(&self) -> Format {
+		match Format::from_path(self.uri().pathname().trim_end_matches('/')) {
+			Some((format, _)) => format,
+			None => Format::negotiate(&self.headers().get("Accept").ok().flatten().unwrap_or_default()).unwrap_or(Format::Html),
+		}
+	}
+
+	// The requested path without the file extension that picked its format,
+	// so `/comments/abc.rss` gives `/comments/abc`
+	fn route_path(&self) -> String {
+		let path = self.uri().pathname();
+		Format::from_path(&path).map(|(_, stripped)| stripped.to_string()).unwrap_or(path)
+	}
 }
 
 impl ResponseExt for Response {
@@ -144,6 +304,7 @@ impl Server {
 	pub fn new() -> Self {
 		Server {
 			default_headers: HashMap::new(),
+			interceptors: Vec::new(),
 			router: Router::new(),
 		}
 	}
@@ -173,25 +334,87 @@ impl Server {
 			path.pop();
 		}
 
+		// Route `/r/rust.rss` like `/r/rust`; the extension only picks the
+		// format. Files served at fixed paths, like `/manifest.json`, keep it.
+		let fixed = self
+			.router
+			.recognize(&format!("/{}{}", req.method().as_str(), path))
+			.is_ok_and(|found| found.params().iter().next().is_none());
+		if let Some((_, stripped)) = Format::from_path(&path).filter(|_| !fixed) {
+			path = stripped.to_string();
+		}
+
 		// Match the visited path with an added route
 		match self.router.recognize(&format!("/{}{}", req.method().as_str(), path)) {
 			// If a route was configured for this path
 			Ok(found) => {
+				if crate::counters::record(&path) {
+					wait_until(crate::counters::flush());
+				}
+
+				let format = req.format();
+				// Cookies may only be set by submitting forms or on the settings pages
+				let cookieless = crate::settings::minimal_cookies() && req.method() != "POST" && !path.starts_with("/settings");
+				// Pages are kept per visitor settings, so everyone gets their own copy
+				let page_key = (req.method() == "GET" && format == Format::Html).then(|| {
+					let cookies = req.headers().get("Cookie").ok().flatten().unwrap_or_default();
+					format!("{}{}\n{}", path, req.uri().search(), cookies)
+				});
+				// `?np=1` only lasts while the page's own links carry it
+				let carry_np = format == Format::Html && req.uri().search_params().get("np").as_deref() == Some("1");
+				// Failed requests are logged with who made them
+				let request_line = format!("{} {} {}", req.client_ip_for_log(), req.method(), path);
 				let mut parammed = req;
 				parammed.set_params(found.params().clone());
 
 				// Run the route's function
-				let func = (found.handler().to_owned().to_owned())(parammed);
+				let func = match self.interceptors.iter().find_map(|intercept| intercept(&parammed)) {
+					Some(func) => func,
+					None => (found.handler().to_owned().to_owned())(parammed),
+				};
 				async move {
+					// Preferences may name a theme from KV storage
+					crate::themes::refresh().await;
+
 					match func.await {
 						Ok(res) => {
+							// Only HTML could be rendered, but the client wanted something else
+							let content_type = res.headers().get("content-type").ok().flatten().unwrap_or_default();
+							if format != Format::Html && content_type.starts_with("text/html") && res.status() == 200 {
+								return new_boilerplate(def_headers, req_headers, 406, format!("This page isn't available as {}", format.content_type()));
+							}
+							let res = if carry_np && content_type.starts_with("text/html") {
+								rewrite_body(res, crate::utils::carry_no_participation).await?
+							} else {
+								res
+							};
+							if !is_proxied_media(&path) {
+								res.headers().append("Vary", "Accept").ok();
+							}
+
 							for (key, value) in def_headers {
 								res.headers().set(&key, &value).ok();
 							}
 
-							Ok(res)
+							if cookieless {
+								res.headers().delete("Set-Cookie").ok();
+							}
+
+							set_cache_control(&res, &path);
+
+							match page_key {
+								Some(key) if res.status() >= 400 => Ok(stale_page(&key).unwrap_or(res)),
+								Some(key) if res.status() == 200 && content_type.starts_with("text/html") && res.headers().has("Set-Cookie") == Ok(false) => keep_page(key, &path, res).await,
+								_ => Ok(res),
+							}
+						}
+						Err(msg) => {
+							log_error(&format!("{}: {}", request_line, msg));
+							match page_key.as_deref().and_then(stale_page) {
+								Some(res) => Ok(res),
+								None => new_boilerplate(def_headers, req_headers, 500, msg),
+							}
 						}
-						Err(msg) => new_boilerplate(def_headers, req_headers, 500, msg),
 					}
 				}
 				.boxed_local()
@@ -202,6 +425,226 @@ impl Server {
 	}
 }
 
+// Rebuild a response with its body changed by `rewrite`
+async fn rewrite_body(res: Response, rewrite: fn(&str) -> String) -> Result<Response, String> {
+	let body = crate::utils::promise::<JsValue>(res.text().map_err(wasm_error)?).await?.as_string().unwrap_or_default();
+
+	let mut init = ResponseInit::new();
+	init.status(res.status());
+	init.headers(&res.headers());
+	Response::new_with_opt_str_and_init(Some(&rewrite(&body)), &init).map_err(wasm_error)
+}
+
+// Remember a rendered page, which means reading its body and building the
+// response again from it
+async fn keep_page(key: String, path: &str, res: Response) -> Result<Response, String> {
+	let body = crate::utils::promise::<JsValue>(res.text().map_err(wasm_error)?).await?.as_string().unwrap_or_default();
+
+	if let Ok(mut cache) = PAGE_CACHE.lock() {
+		let now = Date::now();
+		let ttl = crate::client::cache_ttl(path) + crate::client::stale_ttl(path);
+		cache.retain(|_, page| page.expires > now);
+		// Make room by dropping the page rendered longest ago
+		if cache.len() >= PAGE_CACHE_SIZE {
+			if let Some(oldest) = cache.iter().min_by(|a, b| a.1.rendered.total_cmp(&b.1.rendered)).map(|(key, _)| key.clone()) {
+				cache.remove(&oldest);
+			}
+		}
+		cache.insert(
+			key,
+			KeptPage {
+				rendered: now,
+				expires: now + f64::from(ttl) * 1000.0,
+				body: body.clone(),
+			},
+		);
+	}
+
+	let mut init = ResponseInit::new();
+	init.status(res.status());
+	init.headers(&res.headers());
+	Response::new_with_opt_str_and_init(Some(&body), &init).map_err(wasm_error)
+}
+
+/// Drops the rendered pages at or below `path`, for everyone, and returns how
+/// many there were.
+pub fn purge_pages(path: &str) -> usize {
+	let path = path.trim_end_matches('/').to_ascii_lowercase();
+	PAGE_CACHE.lock().map_or(0, |mut cache| {
+		let before = cache.len();
+		cache.retain(|key, _| {
+			let page = key.split(['?', '\n']).next().unwrap_or_default();
+			!crate::client::under_path(&page.to_ascii_lowercase(), &path)
+		});
+		before - cache.len()
+	})
+}
+
+// The last rendered copy of a page, if Reddit is unavailable and there is one
+fn stale_page(key: &str) -> Option<Response> {
+	if !crate::client::breaker_open() {
+		return None;
+	}
+
+	let now = Date::now();
+	let page = PAGE_CACHE.lock().ok()?.get(key).filter(|page| page.expires > now).cloned()?;
+	let minutes = ((now - page.rendered) / 60_000.0).floor() as u64;
+
+	let headers = Headers::new().ok()?;
+	headers.set("content-type", "text/html").ok()?;
+	headers.set("Cache-Control", "no-store").ok()?;
+
+	let mut init = ResponseInit::new();
+	init.status(200);
+	init.headers(&headers);
+	Response::new_with_opt_str_and_init(Some(&stale_notice(&page.body, minutes)), &init).ok()
+}
+
+/// Adds a banner saying the page was rendered `minutes` ago above its
+/// content.
+fn stale_notice(body: &str, minutes: u64) -> String {
+	let age = match minutes {
+		0 => "less than a minute ago".to_string(),
+		1 => "1 minute ago".to_string(),
+		n => format!("{} minutes ago", n),
+	};
+	let notice = format!(
+		"<div id=\"stale_notice\">Reddit is unavailable right now, so this is a cached copy from {}.</div>\n\t\t<!-- MAIN CONTENT -->",
+		age
+	);
+	body.replacen("<!-- MAIN CONTENT -->", &notice, 1)
+}
+
+fn is_proxied_media(path: &str) -> bool {
+	path
+		.strip_prefix('/')
+		.and_then(|path| path.split_once('/'))
+		.is_some_and(|(prefix, _)| PROXY_PREFIXES.contains(&prefix))
+}
+
+/// Picks how long clients and shared caches may keep a response to `path`.
+fn cache_control(path: &str, status: u16, content_type: &str, sets_cookie: bool) -> &'static str {
+	let media = is_proxied_media(path);
+
+	match status {
+		// HLS playlists are small and may be regenerated by Reddit
+		200 | 304 if media && path.ends_with(".m3u8") => "public, max-age=86400",
+		200 | 304 if media => "public, max-age=31536000, immutable",
+		// Parts of a file are kept only by the browser that asked for them,
+		// since some shared caches serve a stored part for any range
+		206 if media => "private, max-age=31536000, immutable",
+		// Deleted posts and suspended users don't come back
+		410 if !sets_cookie => "private, max-age=86400",
+		_ if status >= 300 || sets_cookie => "no-store",
+		// Pages depend on the visitor's settings, so only their browser may keep
+		// them, whichever format they're rendered in
+		_ if [Format::Html, Format::Json, Format::Rss]
+			.iter()
+			.any(|format| content_type.starts_with(format.content_type())) =>
+		{
+			"private, max-age=30"
+		}
+		_ => "public, max-age=1209600, s-maxage=86400",
+	}
+}
+
+// Handlers may choose their own Cache-Control, except for proxied media,
+// where Reddit's is replaced. Whatever is kept privately depends on cookies.
+fn set_cache_control(res: &Response, path: &str) {
+	let headers = res.headers();
+	let header = |key: &str| headers.get(key).ok().flatten();
+
+	if header("Cache-Control").is_none() || is_proxied_media(path) {
+		let value = cache_control(path, res.status(), &header("content-type").unwrap_or_default(), header("Set-Cookie").is_some());
+		headers.set("Cache-Control", value).ok();
+	}
+
+	if header("Cache-Control").unwrap_or_default().starts_with("private") {
+		headers.append("Vary", "Cookie").ok();
+	}
+}
+
+// What is known about the request being handled to code that runs for it
+// without being handed the request, like background work
+struct Scope {
+	// The context object the host passed along with the request
+	ctx: JsValue,
+	stats: Stats,
+}
+
+/// How the data for a request was fetched, for the debug headers.
+#[derive(Default)]
+pub struct Stats {
+	/// The least cached answer the JSON cache gave, if it was asked at all
+	pub cache: Option<crate::client::CacheOutcome>,
+
+	/// Requests made to Reddit, and their total latency in milliseconds
+	pub upstream: (u64, f64),
+}
+
+thread_local! {
+	static SCOPE: RefCell<Option<Scope>> = const { RefCell::new(None) };
+}
+
+/// A future that handles a request, with its scope set whenever it runs.
+/// It resolves to the response along with the stats of the request.
+pub struct Scoped<T> {
+	scope: Option<Scope>,
+	fut: BoxedLocal<T>,
+}
+
+impl<T> Future for Scoped<T> {
+	type Output = (T, Stats);
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<(T, Stats)> {
+		let this = self.get_mut();
+		let outer = SCOPE.with(|scope| scope.replace(this.scope.take()));
+		let poll = this.fut.as_mut().poll(cx);
+		this.scope = SCOPE.with(|scope| scope.replace(outer));
+
+		poll.map(|res| (res, this.scope.take().map(|scope| scope.stats).unwrap_or_default()))
+	}
+}
+
+/// Runs `fut` for a request that came with `ctx`, the context object of the
+/// host, such as Cloudflare's or Netlify's.
+pub fn scoped<T>(ctx: JsValue, fut: BoxedLocal<T>) -> Scoped<T> {
+	Scoped {
+		scope: Some(Scope { ctx, stats: Stats::default() }),
+		fut,
+	}
+}
+
+/// Adds to the stats of the request being handled, if any.
+pub fn record_stats(f: impl FnOnce(&mut Stats)) {
+	SCOPE.with(|scope| {
+		if let Some(scope) = scope.borrow_mut().as_mut() {
+			f(&mut scope.stats);
+		}
+	});
+}
+
+/// Runs `fut` in the background. Hosts that stop a request once its response
+/// is sent, like Cloudflare, are asked to wait for it with `waitUntil`.
+pub fn wait_until(fut: impl Future<Output = ()> + 'static) {
+	let wait = SCOPE.with(|scope| {
+		let ctx = scope.borrow().as_ref()?.ctx.clone();
+		let func = js_sys::Reflect::get(&ctx, &JsValue::from_str("waitUntil")).ok()?.dyn_into::<js_sys::Function>().ok()?;
+		Some((ctx, func))
+	});
+
+	match wait {
+		Some((ctx, func)) => {
+			let promise = wasm_bindgen_futures::future_to_promise(async move {
+				fut.await;
+				Ok(JsValue::UNDEFINED)
+			});
+			func.call1(&ctx, &promise).ok();
+		}
+		None => wasm_bindgen_futures::spawn_local(fut),
+	}
+}
+
 /// Create a boilerplate Response for error conditions. This response will be
 /// compressed if requested by client.
 fn new_boilerplate(default_headers: HashMap<String, String>, req_headers: Headers, status: u16, body: String) -> Result<Response, String> {
@@ -212,7 +655,55 @@ fn new_boilerplate(default_headers: HashMap<String, String>, req_headers: Header
 	let mut init = ResponseInit::new();
 
 	init.status(status);
+	req_headers.set("Cache-Control", "no-store").ok();
 	init.headers(&req_headers);
 
 	Response::new_with_opt_str_and_init(Some(&body), &init).map_err(wasm_error)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::{cache_control, stale_notice, Format};
+
+	#[test]
+	fn cache_control_works() {
+		assert_eq!(cache_control("/img/abc.jpg", 200, "image/jpeg", false), "public, max-age=31536000, immutable");
+		assert_eq!(
+			cache_control("/hls/abc/HLSPlaylist.m3u8", 200, "application/vnd.apple.mpegurl", false),
+			"public, max-age=86400"
+		);
+		assert_eq!(cache_control("/vid/abc/DASH_720.mp4", 206, "video/mp4", false), "private, max-age=31536000, immutable");
+		assert_eq!(
+			cache_control("/download/vid/abc/DASH_720.mp4", 206, "video/mp4", false),
+			"private, max-age=31536000, immutable"
+		);
+		assert_eq!(cache_control("/img/abc.jpg", 404, "text/html", false), "no-store");
+		assert_eq!(cache_control("/r/rust", 200, "text/html", false), "private, max-age=30");
+		assert_eq!(cache_control("/r/rust", 200, "application/json", false), "private, max-age=30");
+		assert_eq!(cache_control("/r/rust", 200, "application/rss+xml", false), "private, max-age=30");
+		assert_eq!(cache_control("/settings", 302, "text/html", true), "no-store");
+		assert_eq!(cache_control("/user/[deleted]", 410, "text/html", false), "private, max-age=86400");
+		assert_eq!(cache_control("/style.css", 200, "text/css", false), "public, max-age=1209600, s-maxage=86400");
+	}
+
+	#[test]
+	fn format_negotiation_works() {
+		assert_eq!(Format::negotiate(""), Some(Format::Html));
+		assert_eq!(Format::negotiate("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"), Some(Format::Html));
+		assert_eq!(Format::negotiate("application/json"), Some(Format::Json));
+		assert_eq!(Format::negotiate("application/rss+xml, text/html;q=0.5"), Some(Format::Rss));
+		assert_eq!(Format::negotiate("application/json;q=0.5, application/rss+xml"), Some(Format::Rss));
+		assert_eq!(Format::negotiate("image/png"), None);
+		assert_eq!(Format::from_path("/r/rust/top.rss"), Some((Format::Rss, "/r/rust/top")));
+		assert_eq!(Format::from_path("/comments/abc.json"), Some((Format::Json, "/comments/abc")));
+		assert_eq!(Format::from_path("/r/rust"), None);
+	}
+
+	#[test]
+	fn stale_notice_works() {
+		let body = "<main>\n\t\t<!-- MAIN CONTENT -->\n</main>";
+		assert!(stale_notice(body, 0).contains("cached copy from less than a minute ago."));
+		assert!(stale_notice(body, 12).contains("<div id=\"stale_notice\">Reddit is unavailable right now, so this is a cached copy from 12 minutes ago.</div>"));
+		assert_eq!(stale_notice("<p>no marker</p>", 3), "<p>no marker</p>");
+	}
+}
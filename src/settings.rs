@@ -1,44 +1,453 @@
 // CRATES
 use crate::server::{RequestExt, ResponseExt};
-use crate::utils::{promise, redirect, template, wasm_error, Preferences};
+use crate::utils::{is_local_path, promise, redirect, setting, template, wasm_error, Preferences, ThemeAssets, UserTag};
 use askama::Template;
 use cookie::Cookie;
+use once_cell::sync::Lazy;
+use qrcode::{render::svg, QrCode};
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use time::{Duration, OffsetDateTime};
-use web_sys::{FormData, Request, Response};
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{Blob, FormData, Request, Response, UrlSearchParams};
 
 // STRUCTS
 #[derive(Template)]
 #[template(path = "settings.html")]
 struct SettingsTemplate {
 	prefs: Preferences,
+	/// Current value of each preference in `SECTIONS`, by name
+	values: HashMap<&'static str, String>,
+	/// Absolute link that applies the current filters on this instance
+	filters_link: String,
+	/// Names of the cookies the visitor has stored
+	stored: Vec<&'static str>,
 	url: String,
 }
 
+#[derive(Template)]
+#[template(path = "restore.html")]
+struct RestoreTemplate {
+	/// Settings the link would apply, by name
+	values: Vec<(&'static str, String)>,
+	/// Query string of the link
+	query: String,
+	/// Where the confirmation is sent, which tells a restore link, replacing
+	/// all settings, from an update link
+	action: &'static str,
+	prefs: Preferences,
+	url: String,
+}
+
+/// Regions r/popular can be narrowed to, as Reddit's `geo_filter` values.
+/// "auto" picks one from the visitor's language or location.
+pub(crate) const GEO_FILTERS: &[(&str, &str)] = &[
+	("auto", "Automatic"),
+	("GLOBAL", "Everywhere"),
+	("AR", "Argentina"),
+	("AU", "Australia"),
+	("BG", "Bulgaria"),
+	("CA", "Canada"),
+	("CL", "Chile"),
+	("CO", "Colombia"),
+	("HR", "Croatia"),
+	("CZ", "Czech Republic"),
+	("FI", "Finland"),
+	("FR", "France"),
+	("DE", "Germany"),
+	("GR", "Greece"),
+	("HU", "Hungary"),
+	("IS", "Iceland"),
+	("IN", "India"),
+	("IE", "Ireland"),
+	("IT", "Italy"),
+	("JP", "Japan"),
+	("MY", "Malaysia"),
+	("MX", "Mexico"),
+	("NZ", "New Zealand"),
+	("PH", "Philippines"),
+	("PL", "Poland"),
+	("PT", "Portugal"),
+	("PR", "Puerto Rico"),
+	("RO", "Romania"),
+	("RS", "Serbia"),
+	("SG", "Singapore"),
+	("ES", "Spain"),
+	("SE", "Sweden"),
+	("TW", "Taiwan"),
+	("TH", "Thailand"),
+	("TR", "Turkey"),
+	("GB", "United Kingdom"),
+	("US", "United States"),
+];
+
+/// A group of related preferences, shown together on the settings page.
+struct Section {
+	title: &'static str,
+	prefs: &'static [Pref],
+}
+
+/// A user-modifiable preference, stored in a cookie of the same name.
+struct Pref {
+	name: &'static str,
+	label: &'static str,
+	control: Control,
+	/// Value used when the preference isn't set
+	default: &'static str,
+	/// Longer explanation, shown behind a "Why?" toggle
+	help: Option<&'static str>,
+	/// Whether the preference is hidden on SFW-only instances
+	nsfw: bool,
+}
+
+#[derive(PartialEq, Eq)]
+enum Control {
+	/// An on/off checkbox
+	Toggle,
+	/// A choice between fixed values, given with their labels
+	Select(&'static [(&'static str, &'static str)]),
+	/// A choice between the themes bundled with the instance or added to its
+	/// KV storage
+	Theme,
+}
+
+impl Pref {
+	// Whether `value` is allowed for this preference
+	fn accepts(&self, value: &str) -> bool {
+		match self.control {
+			Control::Toggle => value == "on" || value == "off",
+			Control::Select(options) => options.iter().any(|&(option, _)| option == value),
+			Control::Theme => value == "system" || ThemeAssets::get(&format!("{}.css", value)).is_some() || crate::themes::names().iter().any(|name| name == value),
+		}
+	}
+
+	const fn toggle(name: &'static str, label: &'static str) -> Self {
+		Self {
+			name,
+			label,
+			control: Control::Toggle,
+			default: "off",
+			help: None,
+			nsfw: false,
+		}
+	}
+
+	const fn select(name: &'static str, label: &'static str, options: &'static [(&'static str, &'static str)]) -> Self {
+		Self {
+			name,
+			label,
+			control: Control::Select(options),
+			default: options[0].0,
+			help: None,
+			nsfw: false,
+		}
+	}
+
+	const fn with_help(self, help: &'static str) -> Self {
+		Self { help: Some(help), ..self }
+	}
+
+	const fn nsfw(self) -> Self {
+		Self { nsfw: true, ..self }
+	}
+}
+
+impl SettingsTemplate {
+	// Current value of a preference
+	fn value(&self, name: &str) -> String {
+		self.values.get(name).cloned().unwrap_or_default()
+	}
+
+	// Whether `value` is the current choice for a preference
+	fn is_selected(&self, pref: &Pref, value: &str) -> bool {
+		let current = self.value(pref.name);
+		current == value || (current.is_empty() && value == pref.default)
+	}
+
+	// Values and labels to choose from for a select preference
+	fn choices(&self, pref: &Pref) -> Vec<(String, String)> {
+		match pref.control {
+			Control::Select(options) => options.iter().map(|&(value, label)| (value.to_string(), label.to_string())).collect(),
+			Control::Theme => self
+				.prefs
+				.available_themes
+				.iter()
+				.map(|theme| {
+					(
+						theme.clone(),
+						format!("{}{}", theme.get(0..1).unwrap_or_default().to_uppercase(), theme.get(1..).unwrap_or_default()),
+					)
+				})
+				.collect(),
+			Control::Toggle => Vec::new(),
+		}
+	}
+
+	// QR code of the filter sharing link, as inline SVG. None if the link is
+	// too long to encode.
+	fn filters_qr(&self) -> Option<String> {
+		let code = QrCode::new(self.filters_link.as_bytes()).ok()?;
+		let image = code.render::<svg::Color>().min_dimensions(160, 160).build();
+		image.find("<svg").map(|start| image[start..].to_string())
+	}
+
+	// Link that restores the current preferences, subscriptions, filters and
+	// other lists
+	fn restore_link(&self) -> String {
+		let mut params: Vec<String> = names().map(|name| format!("{}={}", name, self.value(name))).collect();
+		params.push(format!("subscriptions={}", self.prefs.subscriptions.join("%2B")));
+		params.push(format!("filters={}", self.prefs.filters.join("%2B")));
+		params.push(format!("hidden_posts={}", self.prefs.hidden_posts.join("%2B")));
+		params.push(format!(
+			"folders={}",
+			self.prefs.folders.iter().map(|(sub, folder)| format!("{}:{}", sub, folder)).collect::<Vec<_>>().join("%2B")
+		));
+		params.push(format!("user_tags={}", self.prefs.user_tags.iter().map(UserTag::to_entry).collect::<Vec<_>>().join("%2B")));
+		params.push(format!(
+			"multis={}",
+			self
+				.prefs
+				.multis
+				.iter()
+				.map(|(name, subs)| format!("{}:{}", name, subs.replace('+', ",")))
+				.collect::<Vec<_>>()
+				.join("%2B")
+		));
+
+		let query = params.join("&");
+		match sign(&query) {
+			Some(signature) => format!("/settings/restore/?{}&sig={}", query, signature),
+			None => format!("/settings/restore/?{}", query),
+		}
+	}
+}
+
 // CONSTANTS
 
-const PREFS: [&str; 13] = [
-	"theme",
-	"front_page",
-	"layout",
-	"wide",
-	"comment_sort",
-	"post_sort",
-	"show_nsfw",
-	"blur_nsfw",
-	"use_hls",
-	"hide_hls_notification",
-	"autoplay_videos",
-	"hide_awards",
-	"disable_visit_reddit_confirmation",
+// Settings holding lists, which are managed outside of the settings form
+const LISTS: [&str; 6] = ["subscriptions", "filters", "hidden_posts", "folders", "user_tags", "multis"];
+
+// Names of subreddits and user profiles (as "u_name")
+static SUBREDDIT_NAME_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[A-Za-z0-9_]{2,21}$").unwrap());
+
+// Links to subreddits in an HTML page
+static SUBREDDIT_LINK_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"href="(?:https?://[a-z.]*reddit\.com)?/r/([A-Za-z0-9_]+)/?""#).unwrap());
+
+const SECTIONS: [Section; 4] = [
+	Section {
+		title: "Appearance",
+		prefs: &[
+			Pref {
+				name: "theme",
+				label: "Theme:",
+				control: Control::Theme,
+				default: "system",
+				help: None,
+				nsfw: false,
+			},
+			Pref::select("layout", "Layout:", &[("card", "Card"), ("clean", "Clean"), ("compact", "Compact")]),
+			Pref::toggle("wide", "Wide UI:"),
+			Pref::toggle("hide_sidebar", "Hide sidebars"),
+			Pref::toggle("collapse_info_panels", "Collapse subreddit and user info"),
+			Pref::toggle("collapse_subscriptions", "Collapse subscriptions in the feed list"),
+			Pref::select(
+				"date_format",
+				"Date format:",
+				&[("relative", "Relative (5mo ago)"), ("iso", "ISO 8601 (2023-05-04T13:07Z)"), ("locale", "Full (May 4, 2023, 13:07 UTC)")],
+			),
+			Pref::toggle("render_math", "Render math"),
+			Pref::toggle("read_only_badges", "Mark scores as read-only").with_help(
+				"Libreddit can't vote, comment or answer polls on your behalf. This labels scores and polls so they don't look like controls you can use.",
+			),
+		],
+	},
+	Section {
+		title: "Content filters",
+		prefs: &[
+			Pref::select(
+				"front_page",
+				"Front page:",
+				&[("default", "Default"), ("popular", "Popular"), ("all", "All"), ("split", "Subscriptions + Popular")],
+			),
+			Pref::select(
+				"front_page_sort",
+				"Default front page sort:",
+				&[("hot", "Hot"), ("new", "New"), ("top", "Top"), ("rising", "Rising"), ("controversial", "Controversial")],
+			),
+			Pref::select(
+				"post_sort",
+				"Default subreddit post sort:",
+				&[("hot", "Hot"), ("new", "New"), ("top", "Top"), ("rising", "Rising"), ("controversial", "Controversial")],
+			),
+			Pref::select("user_sort", "Default user page sort:", &[("new", "New"), ("hot", "Hot"), ("top", "Top"), ("controversial", "Controversial")]),
+			Pref::select(
+				"search_sort",
+				"Default search sort:",
+				&[("relevance", "Relevance"), ("hot", "Hot"), ("top", "Top"), ("new", "New"), ("comments", "Comments")],
+			),
+			Pref::select("geo_filter", "Popular posts from:", GEO_FILTERS)
+				.with_help("Automatic uses the region your browser asks for content in, or else the country you are browsing from."),
+			Pref::select("limit", "Posts per page:", &[("25", "25"), ("50", "50"), ("100", "100")]),
+			Pref::select(
+				"comment_sort",
+				"Default comment sort:",
+				&[
+					("confidence", "Confidence"),
+					("top", "Top"),
+					("new", "New"),
+					("controversial", "Controversial"),
+					("old", "Old"),
+					("qa", "Q&A"),
+					("live", "Live"),
+				],
+			),
+			Pref::toggle("show_nsfw", "Show NSFW posts:").nsfw(),
+			Pref::toggle("blur_nsfw", "Blur NSFW previews:").nsfw(),
+			Pref::select("blur_nsfw_text", "Also blur NSFW text:", &[("off", "No"), ("previews", "Text previews"), ("titles", "Text previews and titles")])
+				.with_help("Applies in post listings when NSFW previews are blurred. Blurred text shows when you hover over or focus the post.")
+				.nsfw(),
+			Pref::toggle("hide_awards", "Hide awards"),
+			Pref::toggle("flair_accents", "Tint posts with their flair color"),
+			Pref::toggle("show_hidden", "Show hidden posts"),
+		],
+	},
+	Section {
+		title: "Media",
+		prefs: &[
+			Pref::toggle("autoplay_videos", "Autoplay videos"),
+			Pref::toggle("use_hls", "Use HLS for videos").with_help(
+				"Reddit videos require JavaScript (via HLS.js) to be enabled to be played with audio. Therefore, this toggle lets you either use Libreddit JS-free or utilize this feature.",
+			),
+			Pref::toggle("hide_hls_notification", "Hide notification about possible HLS usage"),
+			Pref::toggle("hide_inline_media", "Hide emotes and GIFs in comments"),
+			Pref::toggle("dim_media", "Dim images and videos in dark themes"),
+			Pref::select("preferred_resolution", "Image resolution:", &[("source", "Original"), ("medium", "Medium"), ("low", "Low")])
+				.with_help("Shows the smaller copies Reddit makes of images and thumbnails, where it has them, to use less data."),
+			Pref::toggle("data_saver", "Data saver").with_help("Replaces thumbnails, previews, images and video players with plain links, so that media is only downloaded when you open it."),
+			Pref::toggle("link_titles", "Show titles of linked pages").with_help("Fetches the title of the page a link post points to through this instance, so you know where it goes before clicking."),
+		],
+	},
+	Section {
+		title: "Privacy",
+		prefs: &[
+			Pref::toggle("disable_visit_reddit_confirmation", "Do not confirm before visiting content on Reddit"),
+			Pref::toggle("app_handoff", "Offer to open links in the Reddit app").with_help(
+				"When you follow a link to this instance from another site, ask whether to open it in the Reddit app instead.",
+			),
+		],
+	},
 ];
 
+// All preferences on the settings page
+fn prefs() -> impl Iterator<Item = &'static Pref> {
+	SECTIONS.iter().flat_map(|section| section.prefs.iter())
+}
+
+// Names of all preferences on the settings page
+fn names() -> impl Iterator<Item = &'static str> {
+	prefs().map(|pref| pref.name)
+}
+
+// The key restore links are signed with, if the instance signs them
+fn restore_link_key() -> Option<String> {
+	crate::config::get_setting("LIBREDDIT_RESTORE_LINK_KEY").filter(|key| !key.is_empty())
+}
+
+// Sign the query string of a restore link with the instance's restore link
+// key. Returns `None` if the instance doesn't sign restore links.
+fn sign(query: &str) -> Option<String> {
+	let key = restore_link_key()?;
+
+	Some(hmac_sha256(key.as_bytes(), query.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+// Whether the query string of a restore link ends with a valid signature
+fn is_signed(query: &str) -> bool {
+	query
+		.rsplit_once("&sig=")
+		.and_then(|(unsigned, signature)| sign(unsigned).map(|expected| constant_time_eq(expected.as_bytes(), signature.as_bytes())))
+		.unwrap_or_default()
+}
+
+// HMAC-SHA256, as described in RFC 2104
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+	const BLOCK_SIZE: usize = 64;
+
+	let mut block = [0u8; BLOCK_SIZE];
+	if key.len() > BLOCK_SIZE {
+		block[..32].copy_from_slice(&Sha256::digest(key));
+	} else {
+		block[..key.len()].copy_from_slice(key);
+	}
+
+	let pad = |byte: u8| block.map(|b| b ^ byte);
+	let inner = Sha256::new().chain_update(pad(0x36)).chain_update(message).finalize();
+
+	Sha256::new().chain_update(pad(0x5c)).chain_update(inner).finalize().into()
+}
+
+/// Compares two byte strings in time independent of where they differ.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+	a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Whether a request was sent from a page on this instance.
+pub(crate) fn same_origin(req: &Request) -> bool {
+	let header = |name: &str| req.headers().get(name).ok().flatten();
+
+	match header("Sec-Fetch-Site") {
+		Some(site) => site == "same-origin",
+		None => header("Origin").is_none_or(|origin| origin == req.uri().origin()),
+	}
+}
+
+// Whether the visitor followed a link or sent a form on a page of this
+// instance. Unlike `same_origin`, requests that don't say where they came
+// from don't count, as following a link doesn't send an `Origin` header.
+fn from_this_instance(req: &Request) -> bool {
+	let header = |name: &str| req.headers().get(name).ok().flatten();
+
+	match header("Sec-Fetch-Site") {
+		Some(site) => site == "same-origin",
+		None => header("Referer").is_some_and(|referer| referer.starts_with(&format!("{}/", req.uri().origin()))),
+	}
+}
+
+/// Whether `value` may be stored for the setting `name`. Values of the
+/// preferences on the settings page are checked against the values they
+/// allow; other settings, like subscriptions, accept any value.
+pub fn is_valid(name: &str, value: &str) -> bool {
+	prefs().find(|pref| pref.name == name).is_none_or(|pref| pref.accepts(value))
+}
+
+/// Whether the instance only keeps cookies for preferences that differ from
+/// its defaults, and only sets cookies when forms are submitted or on the
+/// settings pages. Enforced for all responses in `server`.
+pub fn minimal_cookies() -> bool {
+	crate::config::get_setting("LIBREDDIT_MINIMAL_COOKIES").as_deref() == Some("on")
+}
+
+// The value a preference has without a cookie: the instance's default, or
+// else the built-in one
+fn default_value(name: &str) -> String {
+	crate::config::get_setting(&format!("LIBREDDIT_DEFAULT_{}", name.to_uppercase()))
+		.filter(|value| is_valid(name, value))
+		.unwrap_or_else(|| prefs().find(|pref| pref.name == name).map_or("", |pref| pref.default).to_string())
+}
+
 // FUNCTIONS
 
 // Retrieve cookies from request "Cookie" header
 pub async fn get(req: Request) -> Result<Response, String> {
 	let url = req.uri().pathname();
+	let prefs = Preferences::new(&req);
+	let filters_link = format!("{}/settings/update?filters={}", req.uri().origin(), prefs.filters.join("%2B"));
 	template(SettingsTemplate {
-		prefs: Preferences::new(&req),
+		values: names().map(|name| (name, setting(&req, name))).collect(),
+		filters_link,
+		stored: names().chain(LISTS).filter(|name| req.cookie(name).is_some()).collect(),
+		prefs,
 		url,
 	})
 }
@@ -58,10 +467,17 @@ pub async fn set(req: Request) -> Result<Response, String> {
 	let form = promise::<FormData>(req.form_data().map_err(wasm_error)?).await?;
 
 	let mut response = redirect("/settings".to_string());
+	let minimal = minimal_cookies();
 
-	for &name in &PREFS {
+	for name in names() {
 		let data = form.get_all(name);
-		match data.get(data.length() - 1).as_string() {
+		match data.get(data.length() - 1).as_string().filter(|value| is_valid(name, value)) {
+			// Defaults apply without a cookie
+			Some(value) if minimal && value == default_value(name) => {
+				if req.cookie(name).is_some() {
+					response.remove_cookie(name.to_string());
+				}
+			}
 			Some(value) => response.insert_cookie(
 				Cookie::build(name.to_owned(), value.clone())
 					.path("/")
@@ -76,7 +492,7 @@ pub async fn set(req: Request) -> Result<Response, String> {
 	Ok(response)
 }
 
-fn set_cookies_method(req: Request, remove_cookies: bool) -> Response {
+fn set_cookies_method(form: &UrlSearchParams, remove_cookies: bool) -> Response {
 	// Split the body into parts
 
 	// Grab existing cookies
@@ -87,17 +503,17 @@ fn set_cookies_method(req: Request, remove_cookies: bool) -> Response {
 	// 	.filter_map(|header| Cookie::parse(header.to_str().unwrap_or_default()).ok())
 	// 	.collect();
 
-	let form = req.uri().search_params();
-
-	let path = match form.get("redirect") {
-		Some(value) => format!("/{}", value.replace("%26", "&").replace("%23", "#")),
-		None => "/".to_string(),
-	};
+	// Only ever redirect within this instance
+	let path = form
+		.get("redirect")
+		.map(|value| format!("/{}", value.replace("%26", "&").replace("%23", "#")))
+		.filter(|path| is_local_path(path))
+		.unwrap_or_else(|| "/".to_string());
 
 	let mut response = redirect(path);
 
-	for name in [PREFS.to_vec(), vec!["subscriptions", "filters"]].concat() {
-		match form.get(name) {
+	for name in names().chain(LISTS) {
+		match form.get(name).filter(|value| is_valid(name, value)) {
 			Some(value) => response.insert_cookie(
 				Cookie::build(name.to_owned(), value.clone())
 					.path("/")
@@ -116,11 +532,208 @@ fn set_cookies_method(req: Request, remove_cookies: bool) -> Response {
 	response
 }
 
-// Set cookies using response "Set-Cookie" header
+// Set cookies using response "Set-Cookie" header. If the instance signs its
+// restore links, links without a valid signature are confirmed first.
 pub async fn restore(req: Request) -> Result<Response, String> {
-	Ok(set_cookies_method(req, true))
+	let form = req.uri().search_params();
+	let query = req.uri().search().trim_start_matches('?').to_string();
+
+	if restore_link_key().is_none() || is_signed(&query) {
+		return Ok(set_cookies_method(&form, true));
+	}
+
+	confirm(&req, &form, query, "/settings/restore")
 }
 
+// Ask the visitor whether to apply the settings in `form`, which came from a
+// link with the query string `query`, by sending it to `action`
+fn confirm(req: &Request, form: &UrlSearchParams, query: String, action: &'static str) -> Result<Response, String> {
+	let names: Vec<&str> = names().chain(LISTS).collect();
+	template(RestoreTemplate {
+		values: names.into_iter().filter_map(|name| form.get(name).map(|value| (name, value))).collect(),
+		query,
+		action,
+		prefs: Preferences::new(req),
+		url: req.uri().pathname(),
+	})
+}
+
+// Apply a restore link after the user confirmed it
+pub async fn restore_confirmed(req: Request) -> Result<Response, String> {
+	if !same_origin(&req) {
+		return Err("Settings can only be restored from this instance".to_string());
+	}
+
+	let form = promise::<FormData>(req.form_data().map_err(wasm_error)?).await?;
+	let query = form.get("query").as_string().unwrap_or_default();
+
+	Ok(set_cookies_method(&UrlSearchParams::new_with_str(&query).map_err(wasm_error)?, true))
+}
+
+// Subscribe to the subreddits listed in an uploaded or pasted Reddit data
+// export (`subscribed_subreddits.csv`) or old.reddit subreddits page
+pub async fn import(req: Request) -> Result<Response, String> {
+	let form = promise::<FormData>(req.form_data().map_err(wasm_error)?).await?;
+
+	// Prefer an uploaded file over pasted text
+	let mut text = form.get("text").as_string().unwrap_or_default();
+	if let Ok(file) = form.get("file").dyn_into::<Blob>() {
+		if file.size() > 0.0 {
+			text = promise::<JsValue>(file.text()).await?.as_string().unwrap_or_default();
+		}
+	}
+
+	let imported = parse_subscriptions(&text);
+	if imported.is_empty() {
+		return Err("No subreddits found to import".to_string());
+	}
+
+	let preferences = Preferences::new(&req);
+	let mut sub_list = preferences.subscriptions;
+	let mut filters = preferences.filters;
+
+	for sub in imported {
+		if !sub_list.iter().any(|s| s.eq_ignore_ascii_case(&sub)) {
+			filters.retain(|s| !s.eq_ignore_ascii_case(&sub));
+			sub_list.push(sub);
+		}
+	}
+	sub_list.sort_by_key(|a| a.to_lowercase());
+
+	let mut response = redirect("/settings".to_string());
+	for (name, list) in [("subscriptions", sub_list), ("filters", filters)] {
+		if list.is_empty() {
+			response.remove_cookie(name.to_string());
+		} else {
+			response.insert_cookie(
+				Cookie::build(name, list.join("+"))
+					.path("/")
+					.http_only(true)
+					.expires(OffsetDateTime::now_utc() + Duration::weeks(52))
+					.finish(),
+			);
+		}
+	}
+
+	Ok(response)
+}
+
+/// Extracts subreddit names from a Reddit data export CSV (or any list with
+/// one subreddit per line), or from the links of an HTML page such as
+/// old.reddit's subreddit list.
+pub fn parse_subscriptions(text: &str) -> Vec<String> {
+	let names: Vec<String> = if text.contains("<a ") {
+		SUBREDDIT_LINK_REGEX.captures_iter(text).map(|caps| caps[1].to_string()).collect()
+	} else {
+		text
+			.lines()
+			.filter_map(|line| line.split(',').next())
+			.map(|name| name.trim().trim_matches('"').trim_start_matches("r/").to_string())
+			.filter(|name| name != "subreddit")
+			.collect()
+	};
+
+	let mut subs: Vec<String> = Vec::new();
+	for name in names {
+		if SUBREDDIT_NAME_REGEX.is_match(&name) && !subs.iter().any(|s| s.eq_ignore_ascii_case(&name)) {
+			subs.push(name);
+		}
+	}
+
+	subs
+}
+
+// Shared filter lists are added to the user's own filters rather than
+// replacing them.
 pub async fn update(req: Request) -> Result<Response, String> {
-	Ok(set_cookies_method(req, false))
+	let form = req.uri().search_params();
+
+	// `?only=name` links change that one preference and ignore everything
+	// else, so they're safe to share. Toggles without a value are flipped.
+	if let Some(name) = form.get("only") {
+		let pref = prefs().find(|pref| pref.name == name).ok_or_else(|| format!("Unknown setting: {}", name))?;
+		let value = match form.get(&name) {
+			Some(value) => value,
+			None if pref.control == Control::Toggle => if setting(&req, &name) == "on" { "off" } else { "on" }.to_string(),
+			None => return Err(format!("No value given for {}", name)),
+		};
+
+		let only = UrlSearchParams::new().map_err(wasm_error)?;
+		only.set(&name, &value);
+		only.set("redirect", &form.get("redirect").unwrap_or_else(|| format!("settings%23{}", name)));
+		return Ok(set_cookies_method(&only, false));
+	}
+
+	// Other links can change any number of settings, so unless they come from
+	// this instance or carry its signature, they're confirmed first
+	let query = req.uri().search().trim_start_matches('?').to_string();
+	if !from_this_instance(&req) && !is_signed(&query) {
+		return confirm(&req, &form, query, "/settings/update");
+	}
+
+	apply_update(&req, form)
+}
+
+// Apply an update link after the user confirmed it
+pub async fn update_confirmed(req: Request) -> Result<Response, String> {
+	if !same_origin(&req) {
+		return Err("Settings can only be changed from this instance".to_string());
+	}
+
+	let form = promise::<FormData>(req.form_data().map_err(wasm_error)?).await?;
+	let query = form.get("query").as_string().unwrap_or_default();
+
+	apply_update(&req, UrlSearchParams::new_with_str(&query).map_err(wasm_error)?)
+}
+
+fn apply_update(req: &Request, form: UrlSearchParams) -> Result<Response, String> {
+	if let Some(shared) = form.get("filters") {
+		let preferences = Preferences::new(req);
+		let mut filters = preferences.filters;
+		for sub in shared.split('+').filter(|sub| !sub.is_empty()) {
+			if !filters.iter().any(|s| s.eq_ignore_ascii_case(sub)) && !preferences.subscriptions.iter().any(|s| s.eq_ignore_ascii_case(sub)) {
+				filters.push(sub.to_string());
+			}
+		}
+		filters.sort_by_key(|a| a.to_lowercase());
+		form.set("filters", &filters.join("+"));
+	}
+
+	Ok(set_cookies_method(&form, false))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{hmac_sha256, is_valid, parse_subscriptions};
+
+	#[test]
+	fn parse_subscriptions_works() {
+		let csv = "subreddit\nrust\n\"linux\"\nRust\nnot a sub\n";
+		assert_eq!(parse_subscriptions(csv), vec!["rust", "linux"]);
+
+		let html = r#"<a href="https://old.reddit.com/r/rust/" class="title">rust</a> <a href="/r/linux">linux</a> <a href="/user/x">x</a>"#;
+		assert_eq!(parse_subscriptions(html), vec!["rust", "linux"]);
+	}
+
+	#[test]
+	fn hmac_sha256_works() {
+		// Test case 2 from RFC 4231
+		let mac: String = hmac_sha256(b"Jefe", b"what do ya want for nothing?").iter().map(|byte| format!("{:02x}", byte)).collect();
+		assert_eq!(mac, "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+	}
+
+	#[test]
+	fn is_valid_works() {
+		assert!(is_valid("wide", "on"));
+		assert!(is_valid("wide", "off"));
+		assert!(!is_valid("wide", "yes"));
+		assert!(is_valid("layout", "compact"));
+		assert!(!is_valid("layout", "<script>"));
+		assert!(is_valid("theme", "system"));
+		assert!(is_valid("theme", "dark"));
+		assert!(!is_valid("theme", "../dark"));
+		assert!(is_valid("subscriptions", "rust+linux"));
+		assert!(is_valid("limit", "100"));
+		assert!(!is_valid("limit", "1000"));
+	}
 }
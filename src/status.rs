@@ -0,0 +1,121 @@
+// Handler for the upstream status page.
+
+use crate::{
+	client::{BLOCKED, METRICS, METRICS_SAMPLES},
+	instance_info::MessageTemplate,
+	server::RequestExt,
+	utils::{wasm_error, Preferences},
+};
+use askama::Template;
+use build_html::{Container, Html, HtmlContainer, Table};
+use js_sys::Date;
+use web_sys::{Request, Response, ResponseInit};
+
+/// Shows latency, error rate and rate-limit budget of the most recent requests
+/// to Reddit, so users can tell whether slowness comes from the instance or
+/// from Reddit itself.
+pub async fn status(req: Request) -> Result<Response, String> {
+	let body = MessageTemplate {
+		title: String::from("Upstream status"),
+		body: status_table(),
+		prefs: Preferences::new(&req),
+		url: req.uri().pathname(),
+	}
+	.render()
+	.map_err(|e| e.to_string())?;
+
+	let res = Response::new_with_opt_str(Some(&body)).map_err(wasm_error)?;
+	res.headers().set("content-type", "text/html").ok();
+	// The metrics change with every request, never serve a stale copy
+	res.headers().set("Cache-Control", "no-store").ok();
+
+	Ok(res)
+}
+
+/// Explains that Reddit is refusing this instance's requests, instead of
+/// failing on the block page as if it were malformed JSON.
+pub fn blocked(req: &Request) -> Result<Response, String> {
+	let body = MessageTemplate {
+		title: String::from(BLOCKED),
+		body: String::from(
+			"<p>Reddit answered with its network security page instead of the content you asked for. \
+			This happens when Reddit blocks the IP address an instance runs on, which is common for datacenter and cloud hosts. \
+			Try <a href=\"/instances\">another instance</a>, or come back later.</p>\
+			<br /><p>If you run this instance:</p>\
+			<ul>\
+			<li>Check the number of blocked responses on the <a href=\"/status\">status page</a> to see whether the block persists.</li>\
+			<li>Blocks usually target address ranges, so moving to another region, host or egress IP tends to help.</li>\
+			<li>Lowering request volume, for example with longer cache lifetimes, makes new blocks less likely.</li>\
+			</ul>",
+		),
+		prefs: Preferences::new(req),
+		url: req.uri().pathname(),
+	}
+	.render()
+	.map_err(|e| e.to_string())?;
+
+	let mut init = ResponseInit::new();
+	init.status(503);
+	let res = Response::new_with_opt_str_and_init(Some(&body), &init).map_err(wasm_error)?;
+	res.headers().set("content-type", "text/html").ok();
+	res.headers().set("Cache-Control", "no-store").ok();
+
+	Ok(res)
+}
+
+fn status_table() -> String {
+	let metrics = match METRICS.lock() {
+		Ok(metrics) => metrics,
+		Err(_) => return String::from("Metrics are currently unavailable."),
+	};
+
+	let unknown = || "<span class=\"unset\"><i>Unknown</i></span>".to_owned();
+	let now = Date::now();
+
+	let mut container = Container::default();
+	container.add_table(
+		Table::from([
+			["Requests sampled", &metrics.count().to_string()],
+			["Mean latency", &metrics.mean_latency().map_or_else(unknown, |l| format!("{:.0} ms", l))],
+			["95th percentile latency", &metrics.p95_latency().map_or_else(unknown, |l| format!("{:.0} ms", l))],
+			["Error rate", &metrics.error_rate().map_or_else(unknown, |r| format!("{:.1}%", r * 100.0))],
+		])
+		.with_header_row(["Recent requests to Reddit"]),
+	);
+	container.add_raw("<br />");
+	container.add_table(
+		Table::from([
+			["Requests remaining", &metrics.rate_limit_remaining.map_or_else(unknown, |r| format!("{:.0}", r))],
+			[
+				"Resets in",
+				&metrics.rate_limit_reset.map_or_else(unknown, |r| format!("{:.0} s", ((r - now) / 1000.0).max(0.0))),
+			],
+		])
+		.with_header_row(["Rate limit"]),
+	);
+	container.add_raw("<br />");
+	container.add_table(
+		Table::from([
+			["Blocked responses", &metrics.blocked.to_string()],
+			[
+				"Last blocked",
+				&metrics.last_blocked.map_or_else(unknown, |t| format!("{:.0} s ago", ((now - t) / 1000.0).max(0.0))),
+			],
+		])
+		.with_header_row(["Blocks by Reddit"]),
+	);
+	container.add_raw("<br />");
+	let mut missing: Vec<[String; 2]> = crate::schema::missing().into_iter().map(|(field, count)| [field, format!("{} times", count)]).collect();
+	if missing.is_empty() {
+		missing.push(["None seen".to_owned(), String::new()]);
+	}
+	container.add_table(Table::from(missing).with_header_row(["Missing fields in Reddit's responses"]));
+	container.add_raw("<br />");
+	container.add_paragraph(format!(
+		"These figures cover the last {} requests this instance made to Reddit and are reset when it restarts. Errors include responses with a 429 or 5xx status. \
+		Missing fields are counted since the instance started; new ones usually mean Reddit changed its API.",
+		METRICS_SAMPLES
+	));
+
+	container.to_html_string().replace("<th>", "<th colspan=\"2\">")
+}
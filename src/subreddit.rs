@@ -1,11 +1,24 @@
 // CRATES
 use crate::utils::{
-	catch_random, error, filter_posts, format_num, format_url, get_filters, nsfw_landing, param, redirect, rewrite_urls, setting, template, val, wasm_error, Post, Preferences,
-	Subreddit,
+	catch_random, error, filter_posts, format_num, format_time, format_url, geo_filter, get_filters, listing_limit, listing_query, nsfw_landing, param, redirect,
+	remove_hidden_posts, rewrite_urls, setting, subreddit_emojis, template, val, wasm_error, MediaKind, Post, Preferences, Subreddit,
+};
+use crate::{
+	client::{json, under_path},
+	feed,
+	post::strip_tags,
+	server::ResponseExt,
+	settings::{parse_subscriptions, GEO_FILTERS},
+	RequestExt,
 };
-use crate::{client::json, server::ResponseExt, RequestExt};
 use askama::Template;
+use cached::{proc_macro::cached, Cached};
 use cookie::Cookie;
+use futures_lite::future;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashSet;
 use time::{Duration, OffsetDateTime};
 use web_sys::{Request, Response, ResponseInit};
 
@@ -28,6 +41,20 @@ struct SubredditTemplate {
 	/// Whether all posts were hidden because they are NSFW (and user has disabled show NSFW)
 	all_posts_hidden_nsfw: bool,
 	no_posts: bool,
+	/// Posts from r/popular, shown next to `posts` on a split front page
+	popular: Option<Vec<Post>>,
+	/// The Reddit custom feed the posts come from, if any
+	custom_feed: Option<CustomFeed>,
+}
+
+// A user's custom feed (multireddit) on Reddit
+struct CustomFeed {
+	owner: String,
+	/// Name of the feed in its URL
+	name: String,
+	title: String,
+	description: String,
+	subs: Vec<String>,
 }
 
 #[derive(Template)]
@@ -36,10 +63,116 @@ struct WikiTemplate {
 	sub: String,
 	wiki: String,
 	page: String,
+	/// When the wiki page was last revised, if known.
+	revised: String,
+	/// Wiki page search query, and the names of the matching pages
+	query: String,
+	results: Vec<String>,
+	/// Posts discussing the wiki page, and the "after" value of the next page
+	discussions: Vec<Post>,
+	discussions_after: String,
+	prefs: Preferences,
+	url: String,
+}
+
+#[derive(Template)]
+#[template(path = "media.html")]
+struct MediaTemplate {
+	sub: String,
+	items: Vec<MediaItem>,
+	/// "after" value of the listing, for the next page
+	after: String,
 	prefs: Preferences,
 	url: String,
 }
 
+#[derive(Template)]
+#[template(path = "rules.html")]
+struct RulesTemplate {
+	sub: String,
+	rules: Vec<Rule>,
+	requirements: PostRequirements,
+	prefs: Preferences,
+	url: String,
+}
+
+// A community rule, as listed on its about page
+struct Rule {
+	name: String,
+	/// HTML explanation of the rule
+	description: String,
+	/// What the rule covers: "link" (posts), "comment" or "all"
+	kind: String,
+}
+
+// What a community requires of new posts, as enforced by Reddit when posting
+struct PostRequirements {
+	flair_required: bool,
+	title_min_length: Option<u64>,
+	title_max_length: Option<u64>,
+	/// Titles must contain at least one of these
+	title_required_strings: Vec<String>,
+	title_blocked_strings: Vec<String>,
+	/// "required", "notAllowed" or "none"
+	body_policy: String,
+	/// "whitelist", "blacklist" or "none", applying to `domains`
+	link_policy: String,
+	domains: Vec<String>,
+	guidelines: String,
+}
+
+impl PostRequirements {
+	fn parse(data: &Value) -> Self {
+		let strings = |key: &str| -> Vec<String> {
+			data[key]
+				.as_array()
+				.map_or(Vec::new(), |list| list.iter().filter_map(Value::as_str).map(ToString::to_string).collect())
+		};
+		let link_policy = data["link_restriction_policy"].as_str().unwrap_or("none").to_string();
+
+		Self {
+			flair_required: data["is_flair_required"].as_bool().unwrap_or_default(),
+			title_min_length: data["title_text_min_length"].as_u64(),
+			title_max_length: data["title_text_max_length"].as_u64(),
+			title_required_strings: strings("title_required_strings"),
+			title_blocked_strings: strings("title_blacklisted_strings"),
+			body_policy: data["body_restriction_policy"].as_str().unwrap_or("none").to_string(),
+			domains: strings(if link_policy == "whitelist" { "link_domain_whitelist" } else { "domain_blacklist" }),
+			link_policy,
+			guidelines: data["guidelines_text"].as_str().unwrap_or_default().to_string(),
+		}
+	}
+
+	// Whether there is anything to show
+	fn is_empty(&self) -> bool {
+		!self.flair_required
+			&& self.title_min_length.is_none()
+			&& self.title_max_length.is_none()
+			&& self.title_required_strings.is_empty()
+			&& self.title_blocked_strings.is_empty()
+			&& self.body_policy == "none"
+			&& self.domains.is_empty()
+			&& self.guidelines.is_empty()
+	}
+}
+
+// Communities whose requirements can't be fetched are taken to have none
+impl Default for PostRequirements {
+	fn default() -> Self {
+		Self::parse(&Value::Null)
+	}
+}
+
+// A single image or video shown in the media grid
+struct MediaItem {
+	/// "image" or "video"
+	kind: String,
+	url: String,
+	thumbnail: String,
+	title: String,
+	permalink: String,
+}
+
 #[derive(Template)]
 #[template(path = "wall.html")]
 struct WallTemplate {
@@ -50,16 +183,83 @@ struct WallTemplate {
 	url: String,
 }
 
+#[derive(Template)]
+#[template(path = "access.html")]
+struct AccessTemplate {
+	sub: String,
+	title: String,
+	/// "private" or "restricted"
+	restriction: String,
+	/// The community's public description, shown to visitors who can't join
+	description: String,
+	/// Links found in the community's descriptions, as (text, url)
+	contacts: Vec<(String, String)>,
+	prefs: Preferences,
+	url: String,
+}
+
+#[derive(Template)]
+#[template(path = "compare.html")]
+struct CompareTemplate {
+	/// The `subs` parameter, to refill the form
+	query: String,
+	communities: Vec<Compared>,
+	prefs: Preferences,
+	url: String,
+}
+
+// A community's figures on the comparison page
+struct Compared {
+	name: String,
+	/// About data, or `None` if the community couldn't be loaded
+	about: Option<Subreddit>,
+	/// Posts per day, estimated from the newest posts
+	posts_per_day: String,
+	/// This week's top posts
+	top: Vec<Post>,
+}
+
+// Most communities compared at once
+const MAX_COMPARED: usize = 4;
+
+// Top posts listed per community when comparing
+const COMPARED_TOP_POSTS: usize = 5;
+
+// A `+`-joined multireddit name without empty parts or subreddits repeated
+// in a different case
+fn canonical_multi(name: &str) -> String {
+	let mut parts: Vec<&str> = Vec::new();
+	for part in name.split('+').map(str::trim).filter(|part| !part.is_empty()) {
+		if !parts.iter().any(|p| p.eq_ignore_ascii_case(part)) {
+			parts.push(part);
+		}
+	}
+
+	parts.join("+")
+}
+
+// Links in a subreddit description
+static LINK_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"<a href="(https?://[^"]+|mailto:[^"]+)"[^>]*>(.*?)</a>"#).unwrap());
+
 // SERVICES
 pub async fn community(req: Request) -> Result<Response, String> {
 	// Build Reddit API path
 	let root = req.uri().pathname() == "/";
 	let subscribed = setting(&req, "subscriptions");
 	let front_page = setting(&req, "front_page");
-	let post_sort = req.cookie("post_sort").map_or_else(|| "hot".to_string(), |c| c.value().to_string());
+	// The front page has its own default sort
+	let post_sort = match setting(&req, if req.param("sub").is_none() { "front_page_sort" } else { "post_sort" }) {
+		sort if sort.is_empty() => "hot".to_string(),
+		sort => sort,
+	};
 	let sort = req.param("sort").unwrap_or_else(|| req.param("id").unwrap_or(post_sort));
 
-	let sub_name = req.param("sub").unwrap_or(if front_page == "default" || front_page.is_empty() {
+	// Show subscriptions and r/popular side by side
+	if front_page == "split" && req.param("sub").is_none() && !subscribed.is_empty() {
+		return split_front_page(req, subscribed, sort).await;
+	}
+
+	let sub_name = req.param("sub").unwrap_or(if front_page == "default" || front_page == "split" || front_page.is_empty() {
 		if subscribed.is_empty() {
 			"popular".to_string()
 		} else {
@@ -75,18 +275,28 @@ pub async fn community(req: Request) -> Result<Response, String> {
 		return Ok(random);
 	}
 
-	if req.param("sub").is_some() && sub_name.starts_with("u_") {
+	// Send multireddits with empty or repeated parts, like `/r/rust++Rust`, to
+	// their canonical path so listings and pagination work on one name
+	if req.param("sub").is_some() && sub_name.contains('+') {
+		let canonical = canonical_multi(&sub_name);
+		if canonical != sub_name {
+			let sort = req.param("sort").map(|sort| format!("/{}", sort)).unwrap_or_default();
+			return Ok(redirect(format!("/r/{}{}{}", canonical, sort, req.uri().search().trim_start_matches("?_"))));
+		}
+	}
+
+	if req.param("sub").is_some() && sub_name.starts_with("u_") && !sub_name.contains('+') {
 		return Ok(redirect(["/user/", &sub_name[2..]].concat()));
 	}
 
 	// Request subreddit metadata
 	let sub = if !sub_name.contains('+') && sub_name != subscribed && sub_name != "popular" && sub_name != "all" {
 		// Regular subreddit
-		subreddit(&sub_name, quarantined).await.unwrap_or_default()
+		subreddit(sub_name.clone(), quarantined).await.unwrap_or_default()
 	} else if sub_name == subscribed {
 		// Subscription feed
 		if req.uri().pathname().starts_with("/r/") {
-			subreddit(&sub_name, quarantined).await.unwrap_or_default()
+			subreddit(sub_name.clone(), quarantined).await.unwrap_or_default()
 		} else {
 			Subreddit::default()
 		}
@@ -105,7 +315,10 @@ pub async fn community(req: Request) -> Result<Response, String> {
 		return Ok(nsfw_landing(req, req_url).await.unwrap());
 	}
 
-	let path = format!("/r/{}/{}.json{}&raw_json=1", sub_name.clone(), sort, req.uri().search());
+	let mut path = format!("/r/{}/{}.json{}&raw_json=1", sub_name.clone(), sort, listing_query(&req));
+	if sub_name == "popular" {
+		path.push_str(&format!("&geo_filter={}", geo_filter(&req)));
+	}
 	let url = format!("{}{}", req.uri().pathname(), req.uri().search());
 	let redirect_url = url[1..].replace('?', "%3F").replace('&', "%26").replace('+', "%2B");
 	let filters = get_filters(&req);
@@ -124,12 +337,26 @@ pub async fn community(req: Request) -> Result<Response, String> {
 			all_posts_filtered: false,
 			all_posts_hidden_nsfw: false,
 			no_posts: false,
+			popular: None,
+			custom_feed: None,
 		})
 	} else {
 		match Post::fetch(&path, quarantined).await {
 			Ok((mut posts, after)) => {
+				remove_hidden_posts(&mut posts, &req);
 				let (_, all_posts_filtered) = filter_posts(&mut posts, &filters);
 				let no_posts = posts.is_empty();
+
+				// Custom emoji belong to a single subreddit
+				if !sub.name.is_empty() && !sub_name.contains('+') && sub_name != "popular" && sub_name != "all" {
+					let emojis = subreddit_emojis(&sub_name).await;
+					posts.iter_mut().for_each(|post| post.apply_emojis(&emojis));
+				}
+
+				if let Some(res) = feed::listing(&req, &format!("r/{}", sub_name), &posts, &after, Some(&sub).filter(|sub| !sub.name.is_empty())) {
+					return res;
+				}
+
 				let all_posts_hidden_nsfw = !no_posts && (posts.iter().all(|p| p.flags.nsfw) && setting(&req, "show_nsfw") != "on");
 				template(SubredditTemplate {
 					sub,
@@ -143,11 +370,13 @@ pub async fn community(req: Request) -> Result<Response, String> {
 					all_posts_filtered,
 					all_posts_hidden_nsfw,
 					no_posts,
+					popular: None,
+					custom_feed: None,
 				})
 			}
 			Err(msg) => match msg.as_str() {
 				"quarantined" | "gated" => quarantine(req, sub_name, msg),
-				"private" => error(req, format!("r/{} is a private community", sub_name)).await,
+				"private" | "restricted" => community_access(req, sub_name, msg).await,
 				"banned" => error(req, format!("r/{} has been banned from Reddit", sub_name)).await,
 				_ => error(req, msg).await,
 			},
@@ -155,6 +384,110 @@ pub async fn community(req: Request) -> Result<Response, String> {
 	}
 }
 
+// Fetch the subscription feed and r/popular at the same time, and show them
+// next to each other
+async fn split_front_page(req: Request, subscribed: String, sort: String) -> Result<Response, String> {
+	let fetch = |sub: &str| {
+		let mut path = format!("/r/{}/{}.json?limit={}&raw_json=1", sub, sort, listing_limit(&req));
+		if sub == "popular" {
+			path.push_str(&format!("&geo_filter={}", geo_filter(&req)));
+		}
+		async move { Post::fetch(&path, true).await }
+	};
+	let (subscriptions, popular) = future::zip(fetch(&subscribed), fetch("popular")).await;
+
+	let filters = get_filters(&req);
+	let visible = |result: Result<(Vec<Post>, String), String>| -> Vec<Post> {
+		let mut posts = result.map(|(posts, _)| posts).unwrap_or_default();
+		remove_hidden_posts(&mut posts, &req);
+		filter_posts(&mut posts, &filters);
+		posts
+	};
+	let (posts, popular) = match (subscriptions, popular) {
+		(Err(msg), Err(_)) => return error(req, msg).await,
+		(subscriptions, popular) => (visible(subscriptions), visible(popular)),
+	};
+
+	let url = format!("{}{}", req.uri().pathname(), req.uri().search());
+	template(SubredditTemplate {
+		sub: Subreddit::default(),
+		no_posts: posts.is_empty(),
+		posts,
+		sort: (sort, String::new()),
+		ends: (String::new(), String::new()),
+		prefs: Preferences::new(&req),
+		redirect_url: url[1..].replace('?', "%3F").replace('&', "%26").replace('+', "%2B"),
+		url,
+		is_filtered: false,
+		all_posts_filtered: false,
+		all_posts_hidden_nsfw: false,
+		popular: Some(popular),
+		custom_feed: None,
+	})
+}
+
+/// Shows a Reddit custom feed, like `/user/spez/m/somefeed`, with its
+/// description and member subreddits above the combined listing.
+pub async fn custom_feed(req: Request) -> Result<Response, String> {
+	let owner = req.param("name").unwrap_or_default();
+	let name = req.param("multi").unwrap_or_default();
+	let post_sort = match setting(&req, "post_sort") {
+		sort if sort.is_empty() => "hot".to_string(),
+		sort => sort,
+	};
+	let sort = req.param("sort").unwrap_or(post_sort);
+
+	let path = format!("/user/{}/m/{}/{}.json{}&raw_json=1", owner, name, sort, listing_query(&req));
+	let (about, listing) = future::zip(json(format!("/api/multi/user/{}/m/{}.json?raw_json=1", owner, name), false), Post::fetch(&path, false)).await;
+
+	// The listing can be shown without the feed's details
+	let about = about.unwrap_or_default();
+	let data = &about["data"];
+	let custom = CustomFeed {
+		title: data["display_name"].as_str().unwrap_or(&name).to_string(),
+		description: data["description_md"].as_str().unwrap_or_default().to_string(),
+		subs: data["subreddits"]
+			.as_array()
+			.map(|subs| subs.iter().filter_map(|sub| sub["name"].as_str()).map(String::from).collect())
+			.unwrap_or_default(),
+		owner,
+		name,
+	};
+
+	let (mut posts, after) = match listing {
+		Ok(listing) => listing,
+		Err(msg) => return error(req, msg).await,
+	};
+	remove_hidden_posts(&mut posts, &req);
+	let (_, all_posts_filtered) = filter_posts(&mut posts, &get_filters(&req));
+
+	if let Some(res) = feed::listing(&req, &format!("u/{}/m/{}", custom.owner, custom.name), &posts, &after, None) {
+		return res;
+	}
+
+	let url = format!("{}{}", req.uri().pathname(), req.uri().search());
+	let no_posts = posts.is_empty();
+	template(SubredditTemplate {
+		// Named after the member subreddits, so the feed can be subscribed to
+		sub: Subreddit {
+			name: custom.subs.join("+"),
+			..Subreddit::default()
+		},
+		all_posts_hidden_nsfw: !no_posts && posts.iter().all(|p| p.flags.nsfw) && setting(&req, "show_nsfw") != "on",
+		no_posts,
+		posts,
+		sort: (sort, param(&path, "t").unwrap_or_default()),
+		ends: (param(&path, "after").unwrap_or_default(), after),
+		prefs: Preferences::new(&req),
+		redirect_url: url[1..].replace('?', "%3F").replace('&', "%26").replace('+', "%2B"),
+		url,
+		is_filtered: false,
+		all_posts_filtered,
+		popular: None,
+		custom_feed: Some(custom),
+	})
+}
+
 pub fn quarantine(req: Request, sub: String, restriction: String) -> Result<Response, String> {
 	let wall = WallTemplate {
 		title: format!("r/{} is {}", sub, restriction),
@@ -175,6 +508,46 @@ pub fn quarantine(req: Request, sub: String, restriction: String) -> Result<Resp
 	Ok(response)
 }
 
+// Explain how to get into a private community, using what Reddit still shares
+// about it, rather than showing a bare error
+async fn community_access(req: Request, sub: String, restriction: String) -> Result<Response, String> {
+	let about = json(format!("/r/{}/about.json?raw_json=1", sub), false).await.unwrap_or_default();
+	let public_description = val(&about, "public_description_html");
+
+	let access = AccessTemplate {
+		title: val(&about, "title"),
+		description: rewrite_urls(&public_description),
+		contacts: contact_links(&[public_description, val(&about, "description_html")].concat()),
+		url: req.uri().pathname(),
+		prefs: Preferences::new(&req),
+		sub,
+		restriction,
+	};
+
+	let mut init = ResponseInit::new();
+	init.status(403);
+
+	let response = Response::new_with_opt_str_and_init(access.render().ok().as_deref(), &init).map_err(wasm_error)?;
+	response.headers().set("content-type", "text/html").ok();
+	Ok(response)
+}
+
+// External links and email addresses mentioned in a community's description,
+// which are usually how to reach its moderators without an account
+fn contact_links(html: &str) -> Vec<(String, String)> {
+	let mut seen = HashSet::new();
+	LINK_REGEX
+		.captures_iter(html)
+		.filter(|caps| !caps[1].contains("reddit.com/") && !caps[1].contains("redd.it/"))
+		.filter(|caps| seen.insert(caps[1].to_string()))
+		.map(|caps| {
+			let url = caps[1].replace("&amp;", "&");
+			let text = strip_tags(&caps[2]);
+			(if text.is_empty() { url.clone() } else { text }, url)
+		})
+		.collect()
+}
+
 pub async fn add_quarantine_exception(req: Request) -> Result<Response, String> {
 	let subreddit = req.param("sub").ok_or("Invalid URL")?;
 	let redir = param(&req.uri().search(), "redir").ok_or("Invalid URL")?;
@@ -244,7 +617,7 @@ pub async fn subscriptions_filters(req: Request) -> Result<Response, String> {
 		};
 
 		// Modify sub list based on action
-		if action.contains(&"subscribe".to_string()) && !sub_list.contains(&part.to_owned()) {
+		if action.contains(&"subscribe".to_string()) && !sub_list.iter().any(|s| s.eq_ignore_ascii_case(part)) {
 			// Add each sub name to the subscribed list
 			sub_list.push(part.to_owned());
 			filters.retain(|s| s.to_lowercase() != part.to_lowercase());
@@ -254,7 +627,7 @@ pub async fn subscriptions_filters(req: Request) -> Result<Response, String> {
 		} else if action.contains(&"unsubscribe".to_string()) {
 			// Remove sub name from subscribed list
 			sub_list.retain(|s| s.to_lowercase() != part.to_lowercase());
-		} else if action.contains(&"filter".to_string()) && !filters.contains(&part.to_owned()) {
+		} else if action.contains(&"filter".to_string()) && !filters.iter().any(|s| s.eq_ignore_ascii_case(part)) {
 			// Add each sub name to the filtered list
 			filters.push(part.to_owned());
 			sub_list.retain(|s| s.to_lowercase() != part.to_lowercase());
@@ -305,6 +678,48 @@ pub async fn subscriptions_filters(req: Request) -> Result<Response, String> {
 	Ok(response)
 }
 
+// Most hidden posts kept in the cookie; the oldest are dropped beyond this
+const MAX_HIDDEN_POSTS: usize = 300;
+
+/// Hides a post from listings, or unhides it, storing the post IDs in the
+/// `hidden_posts` cookie.
+pub async fn hide_unhide(req: Request) -> Result<Response, String> {
+	let id = req.param("id").unwrap_or_default();
+	let hide = req.uri().pathname().starts_with("/hide/");
+	let mut hidden = Preferences::new(&req).hidden_posts;
+
+	if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+		return Err("Invalid post ID".to_string());
+	}
+
+	hidden.retain(|p| p != &id);
+	if hide {
+		hidden.push(id);
+		let excess = hidden.len().saturating_sub(MAX_HIDDEN_POSTS);
+		hidden.drain(..excess);
+	}
+
+	// Redirect back to the listing the request came from
+	let query = req.uri().search();
+	let path = param(&query, "redirect").map_or_else(|| "/".to_string(), |redirect_path| format!("/{}", redirect_path));
+
+	let mut response = redirect(path);
+
+	if hidden.is_empty() {
+		response.remove_cookie("hidden_posts".to_string());
+	} else {
+		response.insert_cookie(
+			Cookie::build("hidden_posts", hidden.join("+"))
+				.path("/")
+				.http_only(true)
+				.expires(OffsetDateTime::now_utc() + Duration::weeks(52))
+				.finish(),
+		);
+	}
+
+	Ok(response)
+}
+
 pub async fn wiki(req: Request) -> Result<Response, String> {
 	let sub = req.param("sub").unwrap_or_else(|| "reddit.com".to_string());
 	let quarantined = can_access_quarantine(&req, &sub);
@@ -314,14 +729,57 @@ pub async fn wiki(req: Request) -> Result<Response, String> {
 	}
 
 	let page = req.param("page").unwrap_or_else(|| "index".to_string());
-	let path: String = format!("/r/{}/wiki/{}.json?raw_json=1", sub, page);
 	let url = req.uri().pathname();
 
-	match json(path, quarantined).await {
+	// Search the names of the subreddit's wiki pages, as Reddit doesn't offer
+	// wiki search through its API
+	let query = req.uri().search_params().get("q").unwrap_or_default().trim().to_string();
+	if !query.is_empty() {
+		return match json(format!("/r/{}/wiki/pages.json", sub), quarantined).await {
+			Ok(response) => template(WikiTemplate {
+				results: search_wiki_pages(
+					response["data"]
+						.as_array()
+						.map(|pages| pages.iter().filter_map(|page| page.as_str()).collect())
+						.unwrap_or_default(),
+					&query,
+				),
+				sub,
+				wiki: String::new(),
+				page: "Search".to_string(),
+				revised: String::new(),
+				query,
+				discussions: Vec::new(),
+				discussions_after: String::new(),
+				prefs: Preferences::new(&req),
+				url,
+			}),
+			Err(msg) => error(req, msg).await,
+		};
+	}
+
+	let path: String = format!("/r/{}/wiki/{}.json?raw_json=1", sub, page);
+
+	// Posts linking to the wiki page, a page at a time. A page without
+	// discussions, or a failure to list them, just leaves the section out.
+	let after = req.uri().search_params().get("after").unwrap_or_default();
+	let discussions_path = format!("/r/{}/wiki/discussions/{}.json?limit=10&after={}&raw_json=1", sub, page, after);
+	let (response, discussions) = future::zip(json(path, quarantined), Post::fetch(&discussions_path, quarantined)).await;
+	let (discussions, discussions_after) = discussions.unwrap_or_default();
+
+	match response {
 		Ok(response) => template(WikiTemplate {
 			sub,
 			wiki: rewrite_urls(response["data"]["content_html"].as_str().unwrap_or("<h3>Wiki not found</h3>")),
 			page,
+			revised: response["data"]["revision_date"]
+				.as_f64()
+				.map(|date| format_time(date, &setting(&req, "date_format")))
+				.unwrap_or_default(),
+			query: String::new(),
+			results: Vec::new(),
+			discussions,
+			discussions_after,
 			prefs: Preferences::new(&req),
 			url,
 		}),
@@ -344,15 +802,12 @@ pub async fn sidebar(req: Request) -> Result<Response, String> {
 		return Ok(random);
 	}
 
-	// Build the Reddit JSON API url
-	let path: String = format!("/r/{}/about.json?raw_json=1", sub);
 	let url = req.uri().pathname();
 
-	// Send a request to the url
-	match json(path, quarantined).await {
-		// If success, receive JSON in response
-		Ok(response) => template(WikiTemplate {
-			wiki: rewrite_urls(&val(&response, "description_html")),
+	// The sidebar is part of the subreddit's about data, which is cached
+	match subreddit(sub.clone(), quarantined).await {
+		Ok(about) => template(WikiTemplate {
+			wiki: about.info,
 			// wiki: format!(
 			// 	"{}<hr><h1>Moderators</h1><br><ul>{}</ul>",
 			// 	rewrite_urls(&val(&response, "description_html"),
@@ -360,6 +815,11 @@ pub async fn sidebar(req: Request) -> Result<Response, String> {
 			// ),
 			sub,
 			page: "Sidebar".to_string(),
+			revised: String::new(),
+			query: String::new(),
+			results: Vec::new(),
+			discussions: Vec::new(),
+			discussions_after: String::new(),
 			prefs: Preferences::new(&req),
 			url,
 		}),
@@ -373,6 +833,255 @@ pub async fn sidebar(req: Request) -> Result<Response, String> {
 	}
 }
 
+pub async fn activity(req: Request) -> Result<Response, String> {
+	let sub = req.param("sub").unwrap_or_else(|| "reddit.com".to_string());
+	let quarantined = can_access_quarantine(&req, &sub);
+
+	// Handle random subreddits
+	if let Ok(random) = catch_random(&sub, "/about/activity").await {
+		return Ok(random);
+	}
+
+	let url = req.uri().pathname();
+
+	// Sample the newest posts and this month's top posts
+	let mut seen = HashSet::new();
+	let mut timestamps = Vec::new();
+	for path in [
+		format!("/r/{}/new.json?limit=100&raw_json=1", sub),
+		format!("/r/{}/top.json?t=month&limit=100&raw_json=1", sub),
+	] {
+		match json(path, quarantined).await {
+			Ok(response) => {
+				for post in response["data"]["children"].as_array().unwrap_or(&Vec::new()) {
+					if seen.insert(val(post, "id")) {
+						timestamps.push(post["data"]["created_utc"].as_f64().unwrap_or_default().round() as i64);
+					}
+				}
+			}
+			Err(msg) => {
+				return if msg == "quarantined" || msg == "gated" {
+					quarantine(req, sub, msg)
+				} else {
+					error(req, msg).await
+				};
+			}
+		}
+	}
+
+	template(WikiTemplate {
+		wiki: format!(
+			"<h1>Posting activity</h1><p>Number of posts by weekday and hour (UTC), sampled from the {} newest and top posts of this month.</p>{}",
+			timestamps.len(),
+			activity_table(&timestamps)
+		),
+		sub,
+		page: "Activity".to_string(),
+		revised: String::new(),
+		query: String::new(),
+		results: Vec::new(),
+		discussions: Vec::new(),
+		discussions_after: String::new(),
+		prefs: Preferences::new(&req),
+		url,
+	})
+}
+
+/// Compares the size, activity and top posts of several communities side by
+/// side, from `/compare?subs=a,b,c`.
+pub async fn compare(req: Request) -> Result<Response, String> {
+	let query = req.uri().search_params().get("subs").unwrap_or_default();
+	let names: Vec<String> = parse_subscriptions(&query.replace([',', '+', ' '], "\n")).into_iter().take(MAX_COMPARED).collect();
+
+	let mut communities = Vec::new();
+	for name in names {
+		// Quarantined and NSFW communities are left out rather than gated
+		let about = subreddit(name.clone(), false).await.ok().filter(|about| !about.nsfw || setting(&req, "show_nsfw") == "on");
+		let (newest, top) = match about {
+			Some(_) => {
+				future::zip(
+					Post::fetch(&format!("/r/{}/new.json?limit=100&raw_json=1", name), false),
+					Post::fetch(&format!("/r/{}/top.json?t=week&limit={}&raw_json=1", name, COMPARED_TOP_POSTS), false),
+				)
+				.await
+			}
+			None => (Err(String::new()), Err(String::new())),
+		};
+
+		let timestamps: Vec<f64> = newest.map(|(posts, _)| posts.iter().map(|post| post.created_ts).collect()).unwrap_or_default();
+		communities.push(Compared {
+			name,
+			about,
+			posts_per_day: posts_per_day(&timestamps).map_or_else(|| "-".to_string(), |rate| format!("{:.1}", rate)),
+			top: top.map(|(posts, _)| posts).unwrap_or_default(),
+		});
+	}
+
+	template(CompareTemplate {
+		query,
+		communities,
+		prefs: Preferences::new(&req),
+		url: req.url(),
+	})
+}
+
+// Average posts per day over the span of the given post times
+fn posts_per_day(timestamps: &[f64]) -> Option<f64> {
+	let newest = timestamps.iter().copied().reduce(f64::max)?;
+	let oldest = timestamps.iter().copied().reduce(f64::min)?;
+	let days = (newest - oldest) / 86_400.0;
+
+	(days > 0.0).then(|| timestamps.len() as f64 / days)
+}
+
+// Show a community's rules along with the requirements Reddit enforces on
+// new posts
+pub async fn rules(req: Request) -> Result<Response, String> {
+	let sub = req.param("sub").unwrap_or_else(|| "reddit.com".to_string());
+	let quarantined = can_access_quarantine(&req, &sub);
+
+	// Handle random subreddits
+	if let Ok(random) = catch_random(&sub, "/about/rules").await {
+		return Ok(random);
+	}
+
+	let (rules, requirements) = future::zip(
+		json(format!("/r/{}/about/rules.json?raw_json=1", sub), quarantined),
+		json(format!("/api/v1/{}/post_requirements.json", sub), quarantined),
+	)
+	.await;
+
+	match rules {
+		Ok(rules) => template(RulesTemplate {
+			rules: rules["rules"]
+				.as_array()
+				.unwrap_or(&Vec::new())
+				.iter()
+				.map(|rule| Rule {
+					name: rule["short_name"].as_str().unwrap_or_default().to_string(),
+					description: rewrite_urls(rule["description_html"].as_str().unwrap_or_default()),
+					kind: rule["kind"].as_str().unwrap_or("all").to_string(),
+				})
+				.collect(),
+			// Requirements are extra, so the rules are shown even without them
+			requirements: requirements.map(|data| PostRequirements::parse(&data)).unwrap_or_default(),
+			prefs: Preferences::new(&req),
+			url: req.uri().pathname(),
+			sub,
+		}),
+		Err(msg) => {
+			if msg == "quarantined" || msg == "gated" {
+				quarantine(req, sub, msg)
+			} else {
+				error(req, msg).await
+			}
+		}
+	}
+}
+
+// Browse only the images and videos of a subreddit
+pub async fn media(req: Request) -> Result<Response, String> {
+	let sub = req.param("sub").unwrap_or_else(|| "popular".to_string());
+	let quarantined = can_access_quarantine(&req, &sub);
+
+	// Handle random subreddits
+	if let Ok(random) = catch_random(&sub, "/media").await {
+		return Ok(random);
+	}
+
+	let after = req.uri().search_params().get("after").unwrap_or_default();
+	let path = format!("/r/{}/hot.json?limit=100&after={}&raw_json=1", sub, after);
+	let url = req.uri().pathname();
+
+	match Post::fetch(&path, quarantined).await {
+		Ok((mut posts, after)) => {
+			remove_hidden_posts(&mut posts, &req);
+			filter_posts(&mut posts, &get_filters(&req));
+			if setting(&req, "show_nsfw") != "on" || crate::utils::sfw_only() {
+				posts.retain(|post| !post.nsfw);
+			}
+
+			let mut items = Vec::new();
+			for post in posts {
+				let item = |kind: &str, url: &str, thumbnail: &str| MediaItem {
+					kind: kind.to_string(),
+					url: url.to_string(),
+					thumbnail: thumbnail.to_string(),
+					title: post.title.clone(),
+					permalink: post.permalink.clone(),
+				};
+				match post.post_type {
+					MediaKind::Image => items.push(item("image", &post.media.url, &post.media.url)),
+					MediaKind::Video | MediaKind::Gif => items.push(item("video", &post.media.url, &post.media.poster)),
+					MediaKind::Gallery => items.extend(post.gallery.iter().map(|image| item("image", &image.url, &image.url))),
+					MediaKind::Audio | MediaKind::SelfPost | MediaKind::Link => {}
+				}
+			}
+
+			template(MediaTemplate {
+				sub,
+				items,
+				after,
+				prefs: Preferences::new(&req),
+				url,
+			})
+		}
+		Err(msg) => match msg.as_str() {
+			"quarantined" | "gated" => quarantine(req, sub, msg),
+			_ => error(req, msg).await,
+		},
+	}
+}
+
+// Wiki pages whose names contain every word of the query, ignoring case and
+// treating the separators in page names as spaces
+fn search_wiki_pages(pages: Vec<&str>, query: &str) -> Vec<String> {
+	let words: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+	let mut results: Vec<String> = pages
+		.into_iter()
+		.filter(|page| {
+			let name = page.to_lowercase().replace(['_', '-', '/'], " ");
+			words.iter().all(|word| name.contains(word.as_str()))
+		})
+		.map(str::to_string)
+		.collect();
+	results.sort();
+	results
+}
+
+// Render a heatmap table of post counts by UTC weekday and hour
+fn activity_table(timestamps: &[i64]) -> String {
+	let mut counts = [[0u32; 24]; 7];
+	for time in timestamps.iter().filter_map(|&t| OffsetDateTime::from_unix_timestamp(t).ok()) {
+		counts[time.weekday().number_days_from_monday() as usize][time.hour() as usize] += 1;
+	}
+	let max = counts.iter().flatten().copied().max().unwrap_or_default().max(1);
+
+	let mut table = String::from("<table id=\"activity\"><thead><tr><th></th>");
+	for hour in 0..24 {
+		table.push_str(&format!("<th>{:02}</th>", hour));
+	}
+	table.push_str("</tr></thead><tbody>");
+
+	for (day, row) in ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"].iter().zip(counts.iter()) {
+		table.push_str(&format!("<tr><th>{}</th>", day));
+		for (hour, count) in row.iter().enumerate() {
+			table.push_str(&format!(
+				"<td title=\"{} {:02}:00: {} posts\" style=\"background: color-mix(in srgb, var(--accent) {}%, transparent)\">{}</td>",
+				day,
+				hour,
+				count,
+				count * 100 / max,
+				count
+			));
+		}
+		table.push_str("</tr>");
+	}
+	table.push_str("</tbody></table>");
+
+	table
+}
+
 // pub async fn moderators(sub: &str, quarantined: bool) -> Result<Vec<String>, String> {
 // 	// Retrieve and format the html for the moderators list
 // 	Ok(
@@ -408,12 +1117,16 @@ pub async fn sidebar(req: Request) -> Result<Response, String> {
 // }
 
 // SUBREDDIT
-async fn subreddit(sub: &str, quarantined: bool) -> Result<Subreddit, String> {
+// About data changes rarely, so it's kept much longer than listings, sparing a
+// request to Reddit on every page of the same subreddit
+#[cached(size = 200, time = 3600, result = true)]
+async fn subreddit(sub: String, quarantined: bool) -> Result<Subreddit, String> {
 	// Build the Reddit JSON API url
 	let path: String = format!("/r/{}/about.json?raw_json=1", sub);
 
 	// Send a request to the url
 	let res = json(path, quarantined).await?;
+	crate::schema::check(&res);
 
 	// Metadata regarding the subreddit
 	let members: i64 = res["data"]["subscribers"].as_u64().unwrap_or_default() as i64;
@@ -436,3 +1149,60 @@ async fn subreddit(sub: &str, quarantined: bool) -> Result<Subreddit, String> {
 		nsfw: res["data"]["over18"].as_bool().unwrap_or_default(),
 	})
 }
+
+/// Drops the cached about data of the subreddits at or below `path`, like
+/// `/r/rust`, and returns how many there were.
+pub async fn purge_about(path: &str) -> usize {
+	let path = path.trim_end_matches('/').to_ascii_lowercase();
+	let mut cache = SUBREDDIT.lock().await;
+	let purged: Vec<(String, bool)> = cache
+		.key_order()
+		.filter(|(sub, _)| under_path(&format!("/r/{}", sub.to_ascii_lowercase()), &path))
+		.cloned()
+		.collect();
+
+	for key in &purged {
+		cache.cache_remove(key);
+	}
+	purged.len()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{canonical_multi, contact_links, posts_per_day, PostRequirements};
+	use serde_json::json;
+
+	#[test]
+	fn canonical_multi_works() {
+		assert_eq!(canonical_multi("rust+cpp+golang"), "rust+cpp+golang");
+		assert_eq!(canonical_multi("+rust++cpp+Rust+"), "rust+cpp");
+	}
+
+	#[test]
+	fn contact_links_works() {
+		let html = r#"<p>Apply on <a href="https://discord.gg/abc">our <strong>Discord</strong></a>, see <a href="https://www.reddit.com/r/rust/wiki">the wiki</a>
+			or mail <a href="mailto:mods@example.com">mailto:mods@example.com</a>. <a href="https://discord.gg/abc">Discord</a></p>"#;
+		assert_eq!(
+			contact_links(html),
+			vec![
+				("our Discord".to_string(), "https://discord.gg/abc".to_string()),
+				("mailto:mods@example.com".to_string(), "mailto:mods@example.com".to_string()),
+			]
+		);
+	}
+
+	#[test]
+	fn posts_per_day_works() {
+		assert_eq!(posts_per_day(&[0.0, 43_200.0, 86_400.0, 172_800.0]), Some(2.0));
+		assert_eq!(posts_per_day(&[100.0]), None);
+		assert_eq!(posts_per_day(&[]), None);
+	}
+
+	#[test]
+	fn post_requirements_is_empty_works() {
+		assert!(PostRequirements::default().is_empty());
+		assert!(PostRequirements::parse(&json!({ "body_restriction_policy": "none", "link_restriction_policy": "none" })).is_empty());
+		assert!(!PostRequirements::parse(&json!({ "body_restriction_policy": "required" })).is_empty());
+		assert!(!PostRequirements::parse(&json!({ "is_flair_required": true })).is_empty());
+	}
+}
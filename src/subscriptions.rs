@@ -0,0 +1,151 @@
+// CRATES
+use crate::server::{RequestExt, ResponseExt};
+use crate::settings::parse_subscriptions;
+use crate::utils::{error, promise, redirect, template, wasm_error, Preferences};
+use askama::Template;
+use cookie::Cookie;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use route_recognizer::Params;
+use time::{Duration, OffsetDateTime};
+use web_sys::{FormData, Request, Response};
+
+// STRUCTS
+#[derive(Template)]
+#[template(path = "subscriptions.html")]
+struct SubscriptionsTemplate {
+	/// Subscriptions and their folders, in display order
+	rows: Vec<(String, String)>,
+	/// "name" or "folder"
+	sort: String,
+	prefs: Preferences,
+	url: String,
+}
+
+// Most multireddits a visitor can save
+const MAX_MULTIS: usize = 20;
+
+// Names of folders subscriptions can be moved to, and of multireddits
+static FOLDER_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[A-Za-z0-9_-]{1,20}$").unwrap());
+
+// SERVICES
+pub async fn page(req: Request) -> Result<Response, String> {
+	let prefs = Preferences::new(&req);
+	let sort = req.uri().search_params().get("sort").filter(|sort| sort == "folder").unwrap_or_else(|| "name".to_string());
+
+	let mut rows: Vec<(String, String)> = prefs.subscriptions.iter().map(|sub| (sub.clone(), prefs.folder(sub).to_string())).collect();
+	if sort == "folder" {
+		rows.sort_by_key(|(sub, folder)| (folder.is_empty(), folder.to_lowercase(), sub.to_lowercase()));
+	} else {
+		rows.sort_by_key(|(sub, _)| sub.to_lowercase());
+	}
+
+	template(SubscriptionsTemplate {
+		rows,
+		sort,
+		prefs,
+		url: req.uri().pathname(),
+	})
+}
+
+// Apply a bulk action to the selected subscriptions, or add subscriptions
+// from a pasted list
+pub async fn update(req: Request) -> Result<Response, String> {
+	let form = promise::<FormData>(req.form_data().map_err(wasm_error)?).await?;
+	let field = |name: &str| form.get(name).as_string().unwrap_or_default();
+	let selected: Vec<String> = form.get_all("selected").iter().filter_map(|value| value.as_string()).collect();
+
+	let preferences = Preferences::new(&req);
+	let mut sub_list = preferences.subscriptions;
+	let mut filters = preferences.filters;
+	let mut folders = preferences.folders;
+	let mut multis = preferences.multis;
+
+	match field("action").as_str() {
+		"add" => {
+			for sub in parse_subscriptions(&field("add")) {
+				if !sub_list.iter().any(|s| s.eq_ignore_ascii_case(&sub)) {
+					filters.retain(|s| !s.eq_ignore_ascii_case(&sub));
+					sub_list.push(sub);
+				}
+			}
+			sub_list.sort_by_key(|a| a.to_lowercase());
+		}
+		"unsubscribe" => {
+			sub_list.retain(|s| !selected.contains(s));
+			folders.retain(|(s, _)| !selected.contains(s));
+		}
+		"move" => {
+			let folder = field("folder").trim().to_string();
+			if !folder.is_empty() && !FOLDER_REGEX.is_match(&folder) {
+				return Err("Folder names may only contain letters, numbers, dashes and underscores".to_string());
+			}
+
+			folders.retain(|(s, _)| !selected.contains(s));
+			if !folder.is_empty() {
+				folders.extend(selected.iter().filter(|s| sub_list.contains(s)).map(|s| (s.clone(), folder.clone())));
+			}
+		}
+		"save_multi" => {
+			let name = field("multi").trim().to_string();
+			if !FOLDER_REGEX.is_match(&name) {
+				return Err("Multireddit names may only contain letters, numbers, dashes and underscores".to_string());
+			}
+			let subs = parse_subscriptions(&field("subs").replace([',', '+', ' '], "\n"));
+			if subs.is_empty() {
+				return Err("A multireddit needs at least one subreddit".to_string());
+			}
+
+			multis.retain(|(n, _)| !n.eq_ignore_ascii_case(&name));
+			if multis.len() >= MAX_MULTIS {
+				return Err(format!("You can save at most {} multireddits", MAX_MULTIS));
+			}
+			multis.push((name, subs.join("+")));
+			multis.sort_by_key(|(name, _)| name.to_lowercase());
+		}
+		"delete_multi" => {
+			let name = field("multi");
+			multis.retain(|(n, _)| *n != name);
+		}
+		_ => return Err("Unknown action".to_string()),
+	}
+
+	let mut response = redirect(format!("/subscriptions{}", req.uri().search().trim_start_matches("?_")));
+
+	let folders: Vec<String> = folders.iter().map(|(sub, folder)| format!("{}:{}", sub, folder)).collect();
+	let multis: Vec<String> = multis.iter().map(|(name, subs)| format!("{}:{}", name, subs.replace('+', ","))).collect();
+	for (name, list) in [("subscriptions", sub_list), ("filters", filters), ("folders", folders), ("multis", multis)] {
+		if list.is_empty() {
+			response.remove_cookie(name.to_string());
+		} else {
+			response.insert_cookie(
+				Cookie::build(name, list.join("+"))
+					.path("/")
+					.http_only(true)
+					.expires(OffsetDateTime::now_utc() + Duration::weeks(52))
+					.finish(),
+			);
+		}
+	}
+
+	Ok(response)
+}
+
+/// Shows the combined listing of a multireddit saved under a name, at
+/// `/m/:name` and `/m/:name/:sort`.
+pub async fn multi(mut req: Request) -> Result<Response, String> {
+	let name = req.param("name").unwrap_or_default();
+	let subs = match Preferences::new(&req).multi(&name) {
+		Some(subs) => subs.to_string(),
+		None => return error(req, format!("You haven't saved a multireddit named \"{}\"", name)).await,
+	};
+
+	let mut params = Params::new();
+	params.insert("sub".to_string(), subs);
+	if let Some(sort) = req.param("sort") {
+		params.insert("sort".to_string(), sort);
+	}
+	req.set_params(params);
+
+	crate::subreddit::community(req).await
+}
@@ -0,0 +1,112 @@
+// Themes added at runtime through KV storage, next to the ones built into
+// the binary from `static/themes/`. The key `themes` lists their names, one
+// per line, and `theme:<name>` holds the CSS of each, written like the
+// bundled files: `.<name> { --accent: ...; }`. They're loaded again every few
+// minutes, so themes can be added without rebuilding the instance.
+
+// CRATES
+use crate::kv;
+use crate::utils::ThemeAssets;
+use js_sys::Date;
+use once_cell::sync::Lazy;
+use std::sync::Mutex;
+
+// How often the themes are loaded again, in milliseconds
+const REFRESH_INTERVAL: f64 = 10.0 * 60.0 * 1000.0;
+
+// Most themes loaded from KV storage
+const MAX_THEMES: usize = 50;
+
+#[derive(Default)]
+struct Loaded {
+	/// When the themes were last loaded, in milliseconds since the epoch
+	checked: f64,
+	/// Names and CSS of the themes
+	themes: Vec<(String, String)>,
+}
+
+static LOADED: Lazy<Mutex<Loaded>> = Lazy::new(|| Mutex::new(Loaded::default()));
+
+/// Loads the themes from KV storage again, if they haven't been in a while.
+pub(crate) async fn refresh() {
+	let now = Date::now();
+	match LOADED.lock() {
+		// Later requests use the themes already loaded in the meantime
+		Ok(mut loaded) if loaded.checked + REFRESH_INTERVAL <= now => loaded.checked = now,
+		_ => return,
+	}
+
+	let mut themes = Vec::new();
+	for name in parse_names(&kv::get("themes").await.unwrap_or_default()) {
+		if let Some(css) = kv::get(&format!("theme:{}", name)).await.filter(|css| !css.trim().is_empty()) {
+			themes.push((name, css));
+		}
+	}
+
+	if let Ok(mut loaded) = LOADED.lock() {
+		loaded.themes = themes;
+	}
+}
+
+/// Names of the themes loaded from KV storage.
+pub(crate) fn names() -> Vec<String> {
+	LOADED.lock().map(|loaded| loaded.themes.iter().map(|(name, _)| name.clone()).collect()).unwrap_or_default()
+}
+
+/// The CSS of every theme loaded from KV storage.
+pub(crate) fn stylesheet() -> String {
+	let loaded = match LOADED.lock() {
+		Ok(loaded) => loaded,
+		Err(_) => return String::new(),
+	};
+
+	let mut res = String::new();
+	for (name, css) in &loaded.themes {
+		res.push_str(css);
+		res.push('\n');
+		res.push_str(&brightness_rule(name, css));
+		res.push('\n');
+	}
+	res
+}
+
+/// How bright media is in the theme `name`, for the `dim_media` preference.
+/// Media is dimmed in dark themes.
+pub(crate) fn brightness_rule(name: &str, css: &str) -> String {
+	let brightness = if css.contains("color-scheme: light") { "1" } else { "0.85" };
+	format!(".{} {{ --media-brightness: {}; }}", name, brightness)
+}
+
+// The usable theme names in a list of them. Names become CSS classes, and
+// can't replace the themes built into the instance.
+fn parse_names(list: &str) -> Vec<String> {
+	let mut names: Vec<String> = Vec::new();
+	for name in list.lines().map(str::trim) {
+		let usable = !name.is_empty()
+			&& name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+			&& name.starts_with(|c: char| c.is_ascii_lowercase())
+			&& name != "system"
+			&& ThemeAssets::get(&format!("{}.css", name)).is_none();
+		if usable && !names.iter().any(|known| known == name) && names.len() < MAX_THEMES {
+			names.push(name.to_string());
+		}
+	}
+	names
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{brightness_rule, parse_names};
+
+	#[test]
+	fn parse_names_works() {
+		assert_eq!(parse_names("winter\n  pumpkin-spice \n\nwinter"), vec!["winter", "pumpkin-spice"]);
+		assert_eq!(parse_names("dark\nsystem\nBad Name\n1st\n.x{}"), Vec::<String>::new());
+	}
+
+	#[test]
+	fn brightness_rule_works() {
+		assert_eq!(brightness_rule("winter", ".winter { color-scheme: light; }"), ".winter { --media-brightness: 1; }");
+		assert_eq!(brightness_rule("night", ".night { --text: white; }"), ".night { --media-brightness: 0.85; }");
+	}
+}
@@ -0,0 +1,107 @@
+// Translation of posts and comments through a LibreTranslate-compatible
+// service set in `LIBREDDIT_TRANSLATE_URL`. Requests to the service are made
+// by the instance, so visitors' browsers never contact it.
+
+// CRATES
+use crate::client::{external_post_json, json};
+use crate::config::get_setting;
+use crate::server::RequestExt;
+use crate::utils::{error, template, Preferences};
+use askama::Template;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::json;
+use web_sys::{Request, Response};
+
+#[derive(Template)]
+#[template(path = "translate.html")]
+struct TranslateTemplate {
+	/// Link back to the post or comment
+	permalink: String,
+	/// Language the text was translated to
+	target: String,
+	/// Original and translated title, empty for comments
+	title: (String, String),
+	/// Original and translated text
+	body: (String, String),
+	prefs: Preferences,
+	url: String,
+}
+
+// Reddit fullnames of posts (t3) and comments (t1)
+static FULLNAME_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^t[13]_[a-z0-9]+$").unwrap());
+
+/// Whether the instance has a translation service.
+pub fn enabled() -> bool {
+	get_setting("LIBREDDIT_TRANSLATE_URL").is_some_and(|url| !url.is_empty())
+}
+
+/// Shows a post or comment next to its translation into `?target=` or the
+/// visitor's preferred language.
+pub async fn page(req: Request) -> Result<Response, String> {
+	let service = match get_setting("LIBREDDIT_TRANSLATE_URL").filter(|url| !url.is_empty()) {
+		Some(url) => url,
+		None => return error(req, "This instance doesn't offer translations").await,
+	};
+
+	let id = req.param("id").unwrap_or_default();
+	if !FULLNAME_REGEX.is_match(&id) {
+		return error(req, "Only posts and comments can be translated").await;
+	}
+
+	let info = json(format!("/api/info.json?id={}&raw_json=1", id), false).await?;
+	let data = &info["data"]["children"][0]["data"];
+	let text = |key: &str| data[key].as_str().unwrap_or_default().to_string();
+	let (title, body) = if id.starts_with("t3_") {
+		(text("title"), text("selftext"))
+	} else {
+		(String::new(), text("body"))
+	};
+	if title.is_empty() && body.is_empty() {
+		return error(req, "There is nothing to translate").await;
+	}
+
+	let target = target_language(
+		req.uri().search_params().get("target").as_deref(),
+		&req.headers().get("Accept-Language").ok().flatten().unwrap_or_default(),
+	);
+	let translated = external_post_json(
+		&format!("{}/translate", service.trim_end_matches('/')),
+		&json!({ "q": [title, body], "source": "auto", "target": target, "format": "text" }),
+	)
+	.await?;
+	let translation = |i: usize| translated["translatedText"][i].as_str().unwrap_or_default().to_string();
+
+	template(TranslateTemplate {
+		permalink: text("permalink"),
+		target,
+		title: (title, translation(0)),
+		body: (body, translation(1)),
+		prefs: Preferences::new(&req),
+		url: req.url(),
+	})
+}
+
+// The language to translate into: the `target` parameter if it looks like a
+// language code, or else the first language the browser accepts
+fn target_language(param: Option<&str>, accept_language: &str) -> String {
+	let is_code = |code: &&str| (2..=3).contains(&code.len()) && code.chars().all(|c| c.is_ascii_alphabetic());
+
+	param
+		.filter(is_code)
+		.or_else(|| accept_language.split([',', ';', '-']).map(str::trim).find(is_code))
+		.unwrap_or("en")
+		.to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::target_language;
+
+	#[test]
+	fn target_language_works() {
+		assert_eq!(target_language(Some("de"), "fr-FR,fr;q=0.9"), "de");
+		assert_eq!(target_language(Some("../x"), "fr-FR,fr;q=0.9"), "fr");
+		assert_eq!(target_language(None, ""), "en");
+	}
+}
@@ -1,10 +1,15 @@
 // CRATES
 use crate::client::json;
-use crate::server::RequestExt;
-use crate::utils::{error, filter_posts, format_url, get_filters, nsfw_landing, param, setting, template, Post, Preferences, User};
+use crate::feed;
+use crate::server::{RequestExt, ResponseExt};
+use crate::utils::{
+	default_sort_query, error, filter_posts, format_date, format_url, get_filters, gone, listing_query, nsfw_landing, param, promise, redirect, remove_hidden_posts, setting,
+	template, wasm_error, Post, Preferences, User, UserTag, TAG_COLORS,
+};
 use askama::Template;
-use time::{macros::format_description, OffsetDateTime};
-use web_sys::{Request, Response};
+use cookie::Cookie;
+use time::{Duration, OffsetDateTime};
+use web_sys::{FormData, Request, Response};
 
 // STRUCTS
 #[derive(Template)]
@@ -29,16 +34,34 @@ struct UserTemplate {
 	no_posts: bool,
 }
 
+#[derive(Template)]
+#[template(path = "peek.html")]
+struct PeekTemplate {
+	name: String,
+	sub: String,
+	comments: Vec<Post>,
+}
+
+// Most comments shown in a quick-peek panel
+const PEEK_LIMIT: usize = 5;
+
+// Most users that can be tagged, to keep the cookie small
+const MAX_TAGS: usize = 50;
+
+// Longest label a user can be tagged with
+const MAX_TAG_LENGTH: usize = 24;
+
 // FUNCTIONS
 pub async fn profile(req: Request) -> Result<Response, String> {
 	let listing = req.param("listing").unwrap_or_else(|| "overview".to_string());
 
 	// Build the Reddit JSON API path
 	let path = format!(
-		"/user/{}/{}.json{}&raw_json=1",
+		"/user/{}/{}.json{}{}&raw_json=1",
 		req.param("name").unwrap_or_else(|| "reddit".to_string()),
 		listing,
-		req.uri().search(),
+		listing_query(&req),
+		default_sort_query(&req, "user_sort"),
 	);
 	let url = format!("{}{}", req.uri().pathname(), req.uri().search());
 	let redirect_url = url[1..].replace('?', "%3F").replace('&', "%26");
@@ -48,7 +71,10 @@ pub async fn profile(req: Request) -> Result<Response, String> {
 	let username = req.param("name").unwrap_or_default();
 
 	// Retrieve info from user about page.
-	let user = user(&username).await.unwrap_or_default();
+	let user = user(&username, &setting(&req, "date_format")).await.unwrap_or_default();
+	if user.suspended {
+		return gone(req, format!("u/{} has been suspended", user.name)).await;
+	}
 
 	let req_url = req.uri().as_string().unwrap_or_default();
 	// Return landing page if this post if this Reddit deems this user NSFW,
@@ -78,7 +104,12 @@ pub async fn profile(req: Request) -> Result<Response, String> {
 		// Request user posts/comments from Reddit
 		match Post::fetch(&path, false).await {
 			Ok((mut posts, after)) => {
+				remove_hidden_posts(&mut posts, &req);
 				let (_, all_posts_filtered) = filter_posts(&mut posts, &filters);
+				if let Some(res) = feed::listing(&req, &format!("u/{}", username), &posts, &after, None) {
+					return res;
+				}
+
 				let no_posts = posts.is_empty();
 				let all_posts_hidden_nsfw = !no_posts && (posts.iter().all(|p| p.flags.nsfw) && setting(&req, "show_nsfw") != "on");
 				template(UserTemplate {
@@ -102,16 +133,83 @@ pub async fn profile(req: Request) -> Result<Response, String> {
 	}
 }
 
+/// Renders an author's recent comments in a subreddit as an HTML fragment,
+/// loaded into the quick-peek panel next to their comments.
+pub async fn peek(req: Request) -> Result<Response, String> {
+	let name = req.param("name").unwrap_or_default();
+	let sub = req.param("sub").unwrap_or_default();
+
+	let path = format!("/user/{}/comments.json?limit=100&raw_json=1", name);
+	let mut comments = match Post::fetch(&path, false).await {
+		Ok((comments, _)) => comments,
+		Err(msg) => return Err(msg),
+	};
+	comments.retain(|comment| comment.community.eq_ignore_ascii_case(&sub));
+	comments.truncate(PEEK_LIMIT);
+
+	template(PeekTemplate { name, sub, comments })
+}
+
+/// Tags a user with a label and color, or removes their tag when the label
+/// is empty.
+pub async fn tag(req: Request) -> Result<Response, String> {
+	let name = req.param("name").unwrap_or_default();
+	let form = promise::<FormData>(req.form_data().map_err(wasm_error)?).await?;
+	let label: String = form
+		.get("label")
+		.as_string()
+		.unwrap_or_default()
+		.trim()
+		.chars()
+		.filter(|c| c.is_alphanumeric() || matches!(c, ' ' | '-'))
+		.take(MAX_TAG_LENGTH)
+		.collect();
+	let color = form
+		.get("color")
+		.as_string()
+		.filter(|color| TAG_COLORS.contains(&color.as_str()))
+		.unwrap_or_else(|| TAG_COLORS[0].to_string());
+
+	let mut tags = Preferences::new(&req).user_tags;
+	tags.retain(|tag| !tag.name.eq_ignore_ascii_case(&name));
+	if !label.is_empty() {
+		if tags.len() >= MAX_TAGS {
+			return error(req, format!("You can tag at most {} users", MAX_TAGS)).await;
+		}
+		tags.push(UserTag { name: name.clone(), color, label });
+	}
+
+	let mut response = redirect(format!("/user/{}", name));
+	if tags.is_empty() {
+		response.remove_cookie("user_tags".to_string());
+	} else {
+		response.insert_cookie(
+			Cookie::build("user_tags", tags.iter().map(UserTag::to_entry).collect::<Vec<_>>().join("+"))
+				.path("/")
+				.http_only(true)
+				.expires(OffsetDateTime::now_utc() + Duration::weeks(52))
+				.finish(),
+		);
+	}
+
+	Ok(response)
+}
+
 // USER
-async fn user(name: &str) -> Result<User, String> {
+async fn user(name: &str, date_format: &str) -> Result<User, String> {
 	// Build the Reddit JSON API path
 	let path: String = format!("/user/{}/about.json?raw_json=1", name);
 
 	// Send a request to the url
 	json(path, false).await.map(|res| {
+		// Suspended accounts only come with a name
+		let suspended = res["data"]["is_suspended"].as_bool().unwrap_or_default();
+		if !suspended {
+			crate::schema::check(&res);
+		}
+
 		// Grab creation date as unix timestamp
-		let created_unix = res["data"]["created"].as_f64().unwrap_or(0.0).round() as i64;
-		let created = OffsetDateTime::from_unix_timestamp(created_unix).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+		let created_unix = res["data"]["created"].as_f64().unwrap_or(0.0);
 
 		// Closure used to parse JSON from Reddit APIs
 		let about = |item| res["data"]["subreddit"][item].as_str().unwrap_or_default().to_string();
@@ -122,10 +220,11 @@ async fn user(name: &str) -> Result<User, String> {
 			title: about("title"),
 			icon: format_url(&about("icon_img")),
 			karma: res["data"]["total_karma"].as_i64().unwrap_or(0),
-			created: created.format(format_description!("[month repr:short] [day] '[year repr:last_two]")).unwrap_or_default(),
+			created: format_date(created_unix, date_format),
 			banner: about("banner_img"),
 			description: about("public_description"),
 			nsfw: res["data"]["subreddit"]["over_18"].as_bool().unwrap_or_default(),
+			suspended,
 		}
 	})
 }
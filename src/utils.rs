@@ -4,13 +4,12 @@ use crate::config::get_setting;
 //
 use crate::{client::json, server::RequestExt};
 use askama::Template;
-use cookie::Cookie;
 use js_sys::Promise;
 use once_cell::sync::Lazy;
 use regex::Regex;
 use rust_embed::RustEmbed;
 use serde_json::Value;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::str::FromStr;
 use time::{macros::format_description, Duration, OffsetDateTime};
@@ -50,6 +49,27 @@ pub struct Flair {
 	pub foreground_color: String,
 }
 
+impl Flair {
+	/// Replaces `:name:` placeholders in the text of the flair with the
+	/// matching subreddit emoji.
+	pub fn apply_emojis(&mut self, emojis: &HashMap<String, String>) {
+		self.flair_parts = std::mem::take(&mut self.flair_parts)
+			.into_iter()
+			.flat_map(|part| match emoji_parts(&part.value, emojis) {
+				parts if part.flair_part_type == "text" && !parts.is_empty() => parts,
+				_ => vec![part],
+			})
+			.collect();
+	}
+
+	/// The flair's background color, if it has a visible one. Used to tint
+	/// posts when the `flair_accents` preference is on.
+	pub fn accent_color(&self) -> Option<&str> {
+		let color = self.background_color.trim();
+		(!self.text.is_empty() && !color.is_empty() && color != "transparent").then_some(color)
+	}
+}
+
 // Part of flair, either emoji or text
 #[derive(Clone)]
 pub struct FlairPart {
@@ -124,6 +144,12 @@ impl Poll {
 	pub fn most_votes(&self) -> u64 {
 		self.poll_options.iter().filter_map(|o| o.vote_count).max().unwrap_or(0)
 	}
+
+	/// The rounded share of all votes an option got, once results are public.
+	pub fn percentage(&self, option: &PollOption) -> Option<u64> {
+		let votes = option.vote_count?;
+		(self.total_vote_count > 0).then(|| (votes * 100 + self.total_vote_count / 2) / self.total_vote_count)
+	}
 }
 
 pub struct PollOption {
@@ -167,85 +193,240 @@ pub struct Media {
 	pub width: i64,
 	pub height: i64,
 	pub poster: String,
+	/// Estimated download size in bytes, or 0 if unknown
+	pub size: u64,
+	/// Low-resolution copy of a video, used to preview frames while scrubbing
+	pub scrubber_url: String,
+	/// Where the sound of a Reddit video may be, most likely first. Reddit
+	/// keeps it apart from the video and has named it in several ways.
+	pub audio_sources: Vec<String>,
+	/// Smaller copies of an image that Reddit made, as widths and URLs
+	pub resolutions: Vec<(i64, String)>,
 }
 
-impl Media {
-	pub async fn parse(data: &Value) -> (String, Self, Vec<GalleryMedia>) {
-		let mut gallery = Vec::new();
+/// What a post links to or contains, which decides how it is displayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaKind {
+	Video,
+	Gif,
+	Image,
+	Gallery,
+	Audio,
+	SelfPost,
+	Link,
+}
 
-		// Define the various known places that Reddit might put video URLs.
-		let data_preview = &data["preview"]["reddit_video_preview"];
-		let secure_media = &data["secure_media"]["reddit_video"];
-		let crosspost_parent_media = &data["crosspost_parent_list"][0]["secure_media"]["reddit_video"];
+impl MediaKind {
+	/// Classifies a post from its JSON data. Reddit-hosted video wins over
+	/// everything else, since Reddit also attaches image previews to videos.
+	pub fn classify(data: &Value) -> Self {
+		if let Some(video) = reddit_video(data) {
+			return if video["is_gif"].as_bool().unwrap_or_default() { Self::Gif } else { Self::Video };
+		}
 
-		// If post is a video, return the video
-		let (post_type, url_val, alt_url_val) = if data_preview["fallback_url"].is_string() {
-			(
-				if data_preview["is_gif"].as_bool().unwrap_or(false) { "gif" } else { "video" },
-				&data_preview["fallback_url"],
-				Some(&data_preview["hls_url"]),
-			)
-		} else if secure_media["fallback_url"].is_string() {
-			(
-				if secure_media["is_gif"].as_bool().unwrap_or(false) { "gif" } else { "video" },
-				&secure_media["fallback_url"],
-				Some(&secure_media["hls_url"]),
-			)
-		} else if crosspost_parent_media["fallback_url"].is_string() {
-			(
-				if crosspost_parent_media["is_gif"].as_bool().unwrap_or(false) { "gif" } else { "video" },
-				&crosspost_parent_media["fallback_url"],
-				Some(&crosspost_parent_media["hls_url"]),
-			)
-		} else if data["post_hint"].as_str().unwrap_or("") == "image" {
-			// Handle images, whether GIFs or pics
-			let preview = &data["preview"]["images"][0];
-			let mp4 = &preview["variants"]["mp4"];
-
-			if mp4.is_object() {
-				// Return the mp4 if the media is a gif
-				("gif", &mp4["source"]["url"], None)
+		if data["post_hint"].as_str() == Some("image") {
+			// Reddit converts GIFs to MP4, and lists the conversion as a variant
+			return if data["preview"]["images"][0]["variants"]["mp4"].is_object() {
+				Self::Gif
 			} else {
-				// Return the picture if the media is an image
-				if data["domain"] == "i.redd.it" {
-					("image", &data["url"], None)
-				} else {
-					("image", &preview["source"]["url"], None)
-				}
-			}
-		} else if data["is_self"].as_bool().unwrap_or_default() {
-			// If type is self, return permalink
-			("self", &data["permalink"], None)
-		} else if data["is_gallery"].as_bool().unwrap_or_default() {
-			// If this post contains a gallery of images
-			gallery = GalleryMedia::parse(&data["gallery_data"]["items"], &data["media_metadata"]);
+				Self::Image
+			};
+		}
 
-			("gallery", &data["url"], None)
+		if data["is_self"].as_bool().unwrap_or_default() {
+			Self::SelfPost
+		} else if data["is_gallery"].as_bool().unwrap_or_default() {
+			Self::Gallery
+		} else if is_audio_url(data["url"].as_str().unwrap_or_default()) {
+			Self::Audio
 		} else {
-			// If type can't be determined, return url
-			("link", &data["url"], None)
+			Self::Link
+		}
+	}
+
+	/// Name of the kind, as used by the templates.
+	pub fn to_str(self) -> &'static str {
+		match self {
+			Self::Video => "video",
+			Self::Gif => "gif",
+			Self::Image => "image",
+			Self::Gallery => "gallery",
+			Self::Audio => "audio",
+			Self::SelfPost => "self",
+			Self::Link => "link",
+		}
+	}
+}
+
+// The Reddit-hosted video of a post, from any of the places Reddit puts it
+fn reddit_video(data: &Value) -> Option<&Value> {
+	[
+		&data["preview"]["reddit_video_preview"],
+		&data["secure_media"]["reddit_video"],
+		&data["crosspost_parent_list"][0]["secure_media"]["reddit_video"],
+	]
+	.into_iter()
+	.find(|video| video["fallback_url"].is_string())
+}
+
+// Widest image shown in listings, in pixels
+const LISTING_IMAGE_WIDTH: i64 = 1080;
+
+// Widest copy of an image shown at each `preferred_resolution`, in pixels
+const LOW_RESOLUTION_WIDTH: i64 = 320;
+const MEDIUM_RESOLUTION_WIDTH: i64 = 960;
+
+// The smaller copies Reddit made of a preview image, as widths and proxied URLs
+fn resolutions(preview: &Value) -> Vec<(i64, String)> {
+	preview["resolutions"]
+		.as_array()
+		.map(|resolutions| {
+			resolutions
+				.iter()
+				.filter_map(|resolution| Some((resolution["width"].as_i64()?, format_url(resolution["url"].as_str()?))))
+				.filter(|(_, url)| !url.is_empty())
+				.collect()
+		})
+		.unwrap_or_default()
+}
+
+// The copies of a preview image narrow enough to replace a thumbnail
+fn thumbnail_resolutions(data: &Value) -> Vec<(i64, String)> {
+	let width = data["thumbnail_width"].as_i64().unwrap_or_default();
+	resolutions(&data["preview"]["images"][0])
+		.into_iter()
+		.filter(|(resolution, _)| *resolution < width)
+		.collect()
+}
+
+// The proxied audio tracks that may go with a Reddit video, unless it's known
+// to be silent
+fn audio_sources(video: &Value) -> Vec<String> {
+	if video["is_gif"].as_bool().unwrap_or_default() || video["has_audio"] == false {
+		return Vec::new();
+	}
+
+	match video["fallback_url"].as_str().and_then(|url| url.rsplit_once("/DASH_")) {
+		Some((base, _)) => ["DASH_AUDIO_128.mp4", "DASH_AUDIO_64.mp4", "DASH_audio.mp4"]
+			.iter()
+			.map(|track| format_url(&format!("{}/{}", base, track)))
+			.collect(),
+		None => Vec::new(),
+	}
+}
+
+impl Media {
+	pub async fn parse(data: &Value) -> (MediaKind, Self, Vec<GalleryMedia>) {
+		let mut gallery = Vec::new();
+
+		// Crossposts show the media of the post they share
+		let data = data["crosspost_parent_list"].get(0).filter(|parent| parent.is_object()).unwrap_or(data);
+
+		let kind = MediaKind::classify(data);
+		let video = reddit_video(data);
+		let preview = &data["preview"]["images"][0];
+
+		let (url_val, alt_url_val) = match (kind, video) {
+			(MediaKind::Video | MediaKind::Gif, Some(video)) => (&video["fallback_url"], Some(&video["hls_url"])),
+			(MediaKind::Gif, None) => (&preview["variants"]["mp4"]["source"]["url"], None),
+			(MediaKind::Image, _) if data["domain"] == "i.redd.it" => (&data["url"], None),
+			(MediaKind::Image, _) => (&preview["source"]["url"], None),
+			(MediaKind::SelfPost, _) => (&data["permalink"], None),
+			(MediaKind::Gallery, _) => {
+				gallery = GalleryMedia::parse(&data["gallery_data"]["items"], &data["media_metadata"]);
+				(&data["url"], None)
+			}
+			(MediaKind::Video | MediaKind::Audio | MediaKind::Link, _) => (&data["url"], None),
 		};
 
-		let source = &data["preview"]["images"][0]["source"];
+		let source = &preview["source"];
 
 		let alt_url = alt_url_val.map_or(String::new(), |val| format_url(val.as_str().unwrap_or_default()));
 
+		let width = source["width"].as_i64().unwrap_or_default();
+		let height = source["height"].as_i64().unwrap_or_default();
+
+		// Estimate the download size from the metadata Reddit gives us
+		let size = match (kind, video) {
+			(MediaKind::Video | MediaKind::Gif, Some(video)) => video["bitrate_kbps"].as_u64().unwrap_or_default() * video["duration"].as_u64().unwrap_or_default() * 1000 / 8,
+			(MediaKind::Image, _) => estimate_image_size(width, height),
+			_ => 0,
+		};
+
 		(
-			post_type.to_string(),
+			kind,
 			Self {
 				url: format_url(url_val.as_str().unwrap_or_default()),
 				alt_url,
-				width: source["width"].as_i64().unwrap_or_default(),
-				height: source["height"].as_i64().unwrap_or_default(),
+				width,
+				height,
 				poster: format_url(source["url"].as_str().unwrap_or_default()),
+				size,
+				scrubber_url: video.map_or(String::new(), |video| format_url(video["scrubber_media_url"].as_str().unwrap_or_default())),
+				audio_sources: video.filter(|_| kind == MediaKind::Video).map_or(Vec::new(), audio_sources),
+				resolutions: if kind == MediaKind::Image { resolutions(preview) } else { Vec::new() },
 			},
 			gallery,
 		)
 	}
+
+	/// The image to show in a listing card: a copy at the preferred
+	/// resolution, or else the image scaled down by the proxy to the width of
+	/// the card, if it's an i.redd.it image wider than that.
+	pub fn listing_url(&self, resolution: &str) -> String {
+		if resolution != "source" {
+			self.preferred_url(resolution)
+		} else if self.url.starts_with("/img/") && !self.url.contains('?') && self.width > LISTING_IMAGE_WIDTH {
+			format!("{}?w={}", self.url, LISTING_IMAGE_WIDTH)
+		} else {
+			self.url.clone()
+		}
+	}
+
+	/// The widest copy of the image allowed by the `preferred_resolution`
+	/// preference, or the image itself if Reddit made none that small.
+	pub fn preferred_url(&self, resolution: &str) -> String {
+		let max_width = match resolution {
+			"low" => LOW_RESOLUTION_WIDTH,
+			"medium" => MEDIUM_RESOLUTION_WIDTH,
+			_ => return self.url.clone(),
+		};
+
+		self
+			.resolutions
+			.iter()
+			.filter(|(width, _)| *width <= max_width)
+			.max_by_key(|(width, _)| *width)
+			.map_or_else(|| self.url.clone(), |(_, url)| url.clone())
+	}
+
+	/// Approximate download size, such as "~1.2 MB", or an empty string if
+	/// unknown.
+	pub fn display_size(&self) -> String {
+		format_bytes(self.size)
+	}
+
+	/// A link that saves the media as a file named after `title`, if it's an
+	/// image or video from Reddit.
+	pub fn download_url(&self, title: &str) -> Option<String> {
+		["/img/", "/vid/", "/preview/"].iter().find(|prefix| self.url.starts_with(*prefix))?;
+		let separator = if self.url.contains('?') { '&' } else { '?' };
+		Some(format!(
+			"/download{}{}title={}",
+			self.url,
+			separator,
+			percent_encoding::utf8_percent_encode(title, percent_encoding::NON_ALPHANUMERIC)
+		))
+	}
 }
 
+// Widest gallery rendition shown in listings, in pixels
+const GALLERY_PREVIEW_WIDTH: i64 = 640;
+
 pub struct GalleryMedia {
 	pub url: String,
+	/// A smaller rendition for listings, or the full image if Reddit has none
+	pub preview_url: String,
 	pub width: i64,
 	pub height: i64,
 	pub caption: String,
@@ -253,31 +434,52 @@ pub struct GalleryMedia {
 }
 
 impl GalleryMedia {
+	/// Approximate download size, such as "~1.2 MB", or an empty string if
+	/// unknown.
+	pub fn display_size(&self) -> String {
+		format_bytes(estimate_image_size(self.width, self.height))
+	}
+
 	fn parse(items: &Value, metadata: &Value) -> Vec<Self> {
 		items
 			.as_array()
 			.unwrap_or(&Vec::new())
 			.iter()
-			.map(|item| {
-				// For each image in gallery
+			.filter_map(|item| {
+				// For each image in gallery, skipping those Reddit failed to process
 				let media_id = item["media_id"].as_str().unwrap_or_default();
-				let image = &metadata[media_id]["s"];
-				let image_type = &metadata[media_id]["m"];
+				let meta = &metadata[media_id];
+				if meta["status"].as_str().is_some_and(|status| status != "valid") {
+					return None;
+				}
+				let image = &meta["s"];
 
-				let url = if image_type == "image/gif" {
+				let url = if meta["m"] == "image/gif" {
 					image["gif"].as_str().unwrap_or_default()
 				} else {
 					image["u"].as_str().unwrap_or_default()
 				};
+				if url.is_empty() {
+					return None;
+				}
+
+				// The largest resized rendition that fits a listing card
+				let preview = meta["p"]
+					.as_array()
+					.and_then(|previews| previews.iter().rfind(|p| p["x"].as_i64().unwrap_or_default() <= GALLERY_PREVIEW_WIDTH))
+					.and_then(|p| p["u"].as_str())
+					.filter(|_| meta["m"] != "image/gif")
+					.unwrap_or(url);
 
 				// Construct gallery items
-				Self {
+				Some(Self {
 					url: format_url(url),
+					preview_url: format_url(preview),
 					width: image["x"].as_i64().unwrap_or_default(),
 					height: image["y"].as_i64().unwrap_or_default(),
 					caption: item["caption"].as_str().unwrap_or_default().to_string(),
 					outbound_url: item["outbound_url"].as_str().unwrap_or_default().to_string(),
-				}
+				})
 			})
 			.collect::<Vec<Self>>()
 	}
@@ -287,6 +489,8 @@ impl GalleryMedia {
 pub struct Post {
 	pub id: String,
 	pub title: String,
+	/// The title split into text and subreddit emoji, if it has any emoji
+	pub title_parts: Vec<FlairPart>,
 	pub community: String,
 	pub body: String,
 	pub author: Author,
@@ -294,22 +498,67 @@ pub struct Post {
 	pub poll: Option<Poll>,
 	pub score: (String, String),
 	pub upvote_ratio: i64,
-	pub post_type: String,
+	pub post_type: MediaKind,
 	pub flair: Flair,
 	pub flags: Flags,
 	pub thumbnail: Media,
 	pub media: Media,
 	pub domain: String,
+	/// Title of the linked page, filled in when the `link_titles` preference
+	/// is on
+	pub link_title: String,
 	pub rel_time: String,
 	pub created: String,
+	pub created_ts: f64,
 	pub num_duplicates: u64,
 	pub comments: (String, String),
 	pub gallery: Vec<GalleryMedia>,
 	pub awards: Awards,
 	pub nsfw: bool,
+	/// The post this one shares, if it's a crosspost
+	pub crosspost: Option<Crosspost>,
+}
+
+/// The original of a crossposted post, whose media and text the crosspost
+/// shows.
+pub struct Crosspost {
+	pub community: String,
+	pub author: String,
+	pub title: String,
+	/// Link to the original post on this instance
+	pub permalink: String,
+	pub body: String,
+}
+
+impl Crosspost {
+	pub fn parse(data: &Value) -> Option<Self> {
+		let parent = data["crosspost_parent_list"].get(0).filter(|parent| parent.is_object())?;
+		let field = |key: &str| parent[key].as_str().unwrap_or_default().to_string();
+
+		Some(Self {
+			community: field("subreddit"),
+			author: field("author"),
+			title: field("title"),
+			permalink: field("permalink"),
+			body: rewrite_urls(&field("selftext_html")),
+		})
+	}
 }
 
 impl Post {
+	/// Shows the subreddit's custom emoji in the title and flairs of the post.
+	pub fn apply_emojis(&mut self, emojis: &HashMap<String, String>) {
+		self.title_parts = emoji_parts(&self.title, emojis);
+		self.flair.apply_emojis(emojis);
+		self.author.flair.apply_emojis(emojis);
+	}
+
+	/// The post's creation time, formatted according to the `date_format`
+	/// preference.
+	pub fn display_time(&self, date_format: &str) -> String {
+		format_time(self.created_ts, date_format)
+	}
+
 	// Fetch posts of a user or subreddit and return a vector of posts and the "after" value
 	pub async fn fetch(path: &str, quarantine: bool) -> Result<(Vec<Self>, String), String> {
 		// Send a request to the url
@@ -320,6 +569,12 @@ impl Post {
 			Err(msg) => return Err(msg),
 		};
 
+		Self::from_listing(&res).await
+	}
+
+	/// Parses a listing in Reddit's JSON format, from Reddit or a compatible
+	/// source, into posts and the "after" value
+	pub async fn from_listing(res: &serde_json::Value) -> Result<(Vec<Self>, String), String> {
 		// Fetch the list of posts from the JSON response
 		let post_list = match res["data"]["children"].as_array() {
 			Some(list) => list,
@@ -330,9 +585,11 @@ impl Post {
 
 		// For each post from posts list
 		for post in post_list {
+			crate::schema::check(post);
 			let data = &post["data"];
 
-			let (rel_time, created) = time(data["created_utc"].as_f64().unwrap_or_default());
+			let created_ts = data["created_utc"].as_f64().unwrap_or_default();
+			let (rel_time, created) = time(created_ts);
 			let score = data["score"].as_i64().unwrap_or_default();
 			let ratio: f64 = data["upvote_ratio"].as_f64().unwrap_or(1.0) * 100.0;
 			let title = val(post, "title");
@@ -350,6 +607,7 @@ impl Post {
 			posts.push(Self {
 				id: val(post, "id"),
 				title,
+				title_parts: Vec::new(),
 				community: val(post, "subreddit"),
 				body,
 				author: Author {
@@ -379,9 +637,14 @@ impl Post {
 					width: data["thumbnail_width"].as_i64().unwrap_or_default(),
 					height: data["thumbnail_height"].as_i64().unwrap_or_default(),
 					poster: "".to_string(),
+					size: 0,
+					scrubber_url: String::new(),
+					audio_sources: Vec::new(),
+					resolutions: thumbnail_resolutions(data),
 				},
 				media,
 				domain: val(post, "domain"),
+				link_title: String::new(),
 				flair: Flair {
 					flair_parts: FlairPart::parse(
 						data["link_flair_type"].as_str().unwrap_or_default(),
@@ -404,11 +667,13 @@ impl Post {
 				poll: Poll::parse(&data["poll_data"]),
 				rel_time,
 				created,
+				created_ts,
 				num_duplicates: post["data"]["num_duplicates"].as_u64().unwrap_or(0),
 				comments: format_num(data["num_comments"].as_i64().unwrap_or_default()),
 				gallery,
 				awards,
 				nsfw: post["data"]["over_18"].as_bool().unwrap_or_default(),
+				crosspost: Crosspost::parse(data),
 			});
 		}
 
@@ -431,16 +696,34 @@ pub struct Comment {
 	pub score: (String, String),
 	pub rel_time: String,
 	pub created: String,
+	/// Unix timestamp of when the comment was posted
+	pub created_ts: f64,
 	pub edited: (String, String),
 	pub replies: Vec<Comment>,
 	pub highlighted: bool,
+	/// Whether a reply, at any depth, is the highlighted comment
+	pub highlighted_below: bool,
 	pub awards: Awards,
 	pub collapsed: bool,
 	pub is_filtered: bool,
 	pub more_count: i64,
+	/// IDs of the comments a "more" stub stands for, joined with commas
+	pub more_ids: String,
 	pub prefs: Preferences,
 }
 
+impl Comment {
+	/// The subreddit the comment was posted in, taken from its post's link.
+	pub fn subreddit(&self) -> &str {
+		self.post_link.strip_prefix("/r/").and_then(|link| link.split('/').next()).unwrap_or_default()
+	}
+
+	/// The ID of the post the comment belongs to, taken from its post's link.
+	pub fn post_id(&self) -> &str {
+		self.post_link.split('/').skip_while(|part| *part != "comments").nth(1).unwrap_or_default()
+	}
+}
+
 #[derive(Default, Clone)]
 pub struct Award {
 	pub name: String,
@@ -492,6 +775,11 @@ impl Awards {
 
 		Self(parsed)
 	}
+
+	// Total number of awards given, counting repeated awards individually
+	pub fn total_count(&self) -> i64 {
+		self.iter().map(|award| award.count).sum()
+	}
 }
 
 #[derive(Template)]
@@ -534,9 +822,11 @@ pub struct User {
 	pub banner: String,
 	pub description: String,
 	pub nsfw: bool,
+	/// Whether Reddit suspended the account
+	pub suspended: bool,
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 // Subreddit struct containing metadata about community
 pub struct Subreddit {
 	pub name: String,
@@ -561,11 +851,43 @@ pub struct Params {
 	pub before: Option<String>,
 }
 
+/// Colors a user tag can be shown in
+pub const TAG_COLORS: [&str; 6] = ["gray", "red", "orange", "green", "blue", "purple"];
+
+/// A label the visitor attached to a Reddit user, shown next to their name
+#[derive(Clone)]
+pub struct UserTag {
+	pub name: String,
+	pub color: String,
+	pub label: String,
+}
+
+impl UserTag {
+	// Parse a `name:color:label` entry of the `user_tags` cookie, where
+	// spaces in the label are stored as underscores
+	fn parse(entry: &str) -> Option<Self> {
+		let mut fields = entry.splitn(3, ':');
+		let name = fields.next().filter(|name| !name.is_empty())?;
+		let color = fields.next().filter(|color| TAG_COLORS.contains(color))?;
+		let label = fields.next().filter(|label| !label.is_empty())?;
+
+		Some(Self {
+			name: name.to_string(),
+			color: color.to_string(),
+			label: label.replace('_', " "),
+		})
+	}
+
+	/// The entry for this tag in the `user_tags` cookie
+	pub fn to_entry(&self) -> String {
+		format!("{}:{}:{}", self.name, self.color, self.label.replace(' ', "_"))
+	}
+}
+
 #[derive(Default)]
 pub struct Preferences {
 	pub available_themes: Vec<String>,
 	pub theme: String,
-	pub front_page: String,
 	pub layout: String,
 	pub wide: String,
 	pub show_nsfw: String,
@@ -574,11 +896,30 @@ pub struct Preferences {
 	pub use_hls: String,
 	pub autoplay_videos: String,
 	pub disable_visit_reddit_confirmation: String,
-	pub comment_sort: String,
-	pub post_sort: String,
 	pub subscriptions: Vec<String>,
 	pub filters: Vec<String>,
+	pub hidden_posts: Vec<String>,
+	/// Folders subscriptions were moved to, as (subreddit, folder) pairs
+	pub folders: Vec<(String, String)>,
+	pub user_tags: Vec<UserTag>,
+	/// Named multireddits, as (name, subreddits joined with "+") pairs
+	pub multis: Vec<(String, String)>,
 	pub hide_awards: String,
+	/// Whether the page was requested in "no participation" mode. This isn't
+	/// stored in a cookie; see `no_participation`.
+	pub no_participation: bool,
+	pub show_hidden: String,
+	pub date_format: String,
+	pub data_saver: String,
+	pub read_only_badges: String,
+	pub geo_filter: String,
+	pub blur_nsfw_text: String,
+	pub hide_sidebar: String,
+	pub collapse_info_panels: String,
+	pub collapse_subscriptions: String,
+	pub dim_media: String,
+	pub flair_accents: String,
+	pub preferred_resolution: String,
 }
 
 #[derive(RustEmbed)]
@@ -587,7 +928,7 @@ pub struct Preferences {
 pub struct ThemeAssets;
 
 impl Preferences {
-	// Build preferences from cookies
+	// Build preferences from cookies, ignoring values that aren't valid for them
 	pub fn new(req: &Request) -> Self {
 		// Read available theme names from embedded css files.
 		// Always make the default "system" theme available.
@@ -596,10 +937,10 @@ impl Preferences {
 			let chunks: Vec<&str> = file.as_ref().split(".css").collect();
 			themes.push(chunks[0].to_owned())
 		}
+		themes.extend(crate::themes::names());
 		Self {
 			available_themes: themes,
 			theme: setting(req, "theme"),
-			front_page: setting(req, "front_page"),
 			layout: setting(req, "layout"),
 			wide: setting(req, "wide"),
 			show_nsfw: setting(req, "show_nsfw"),
@@ -608,13 +949,88 @@ impl Preferences {
 			hide_hls_notification: setting(req, "hide_hls_notification"),
 			autoplay_videos: setting(req, "autoplay_videos"),
 			disable_visit_reddit_confirmation: setting(req, "disable_visit_reddit_confirmation"),
-			comment_sort: setting(req, "comment_sort"),
-			post_sort: setting(req, "post_sort"),
 			subscriptions: setting(req, "subscriptions").split('+').map(String::from).filter(|s| !s.is_empty()).collect(),
 			filters: setting(req, "filters").split('+').map(String::from).filter(|s| !s.is_empty()).collect(),
+			hidden_posts: setting(req, "hidden_posts").split('+').map(String::from).filter(|s| !s.is_empty()).collect(),
+			folders: setting(req, "folders")
+				.split('+')
+				.filter_map(|entry| entry.split_once(':'))
+				.map(|(sub, folder)| (sub.to_string(), folder.to_string()))
+				.collect(),
+			user_tags: setting(req, "user_tags").split('+').filter_map(UserTag::parse).collect(),
+			multis: setting(req, "multis")
+				.split('+')
+				.filter_map(|entry| entry.split_once(':'))
+				.map(|(name, subs)| (name.to_string(), subs.replace(',', "+")))
+				.collect(),
 			hide_awards: setting(req, "hide_awards"),
+			no_participation: no_participation(req),
+			show_hidden: setting(req, "show_hidden"),
+			date_format: setting(req, "date_format"),
+			data_saver: setting(req, "data_saver"),
+			read_only_badges: setting(req, "read_only_badges"),
+			geo_filter: setting(req, "geo_filter"),
+			blur_nsfw_text: setting(req, "blur_nsfw_text"),
+			hide_sidebar: setting(req, "hide_sidebar"),
+			collapse_info_panels: setting(req, "collapse_info_panels"),
+			collapse_subscriptions: setting(req, "collapse_subscriptions"),
+			dim_media: setting(req, "dim_media"),
+			flair_accents: setting(req, "flair_accents"),
+			preferred_resolution: setting(req, "preferred_resolution"),
 		}
 	}
+
+	/// The folder a subscription was moved to, or an empty string
+	pub fn folder(&self, sub: &str) -> &str {
+		self.folders.iter().find(|(s, _)| s.eq_ignore_ascii_case(sub)).map_or("", |(_, folder)| folder.as_str())
+	}
+
+	/// Whether the sidebar next to subreddit and user pages is shown
+	pub fn show_sidebar(&self) -> bool {
+		self.hide_sidebar != "on"
+	}
+
+	/// Whether subreddit and user info panels, and the subreddits found by a
+	/// search, start out expanded
+	pub fn info_panels_open(&self) -> bool {
+		self.collapse_info_panels != "on"
+	}
+
+	/// Whether subscriptions in the feed list start out expanded
+	pub fn subscriptions_open(&self) -> bool {
+		self.collapse_subscriptions != "on"
+	}
+
+	/// The tag the visitor attached to a user, if any
+	pub fn user_tag(&self, name: &str) -> Option<&UserTag> {
+		self.user_tags.iter().find(|tag| tag.name.eq_ignore_ascii_case(name))
+	}
+
+	/// Whether every subreddit of a `+`-joined multireddit is subscribed to
+	pub fn subscribed_to_all(&self, multi: &str) -> bool {
+		multi.split('+').all(|sub| self.subscriptions.iter().any(|s| s.eq_ignore_ascii_case(sub)))
+	}
+
+	/// The subreddits of a named multireddit, joined with "+"
+	pub fn multi(&self, name: &str) -> Option<&str> {
+		self.multis.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, subs)| subs.as_str())
+	}
+
+	/// Subscriptions grouped by folder, with folders in alphabetical order.
+	/// Subscriptions outside of any folder come first, under an empty name.
+	pub fn subscription_folders(&self) -> Vec<(String, Vec<String>)> {
+		let mut groups: Vec<(String, Vec<String>)> = Vec::new();
+		for sub in &self.subscriptions {
+			let folder = self.folder(sub);
+			match groups.iter_mut().find(|(name, _)| name == folder) {
+				Some((_, subs)) => subs.push(sub.clone()),
+				None => groups.push((folder.to_string(), vec![sub.clone()])),
+			}
+		}
+		groups.sort_by_key(|(name, _)| name.to_lowercase());
+
+		groups
+	}
 }
 
 /// Gets a `HashSet` of filters from the cookie in the given `Request`.
@@ -645,10 +1061,73 @@ pub fn filter_posts(posts: &mut Vec<Post>, filters: &HashSet<String>) -> (u64, b
 	}
 }
 
+/// Removes the posts a user has hidden from a `Vec<Post>`, unless they have
+/// chosen to show hidden posts.
+pub fn remove_hidden_posts(posts: &mut Vec<Post>, req: &Request) {
+	if setting(req, "show_hidden") == "on" {
+		return;
+	}
+
+	let hidden = setting(req, "hidden_posts")
+		.split('+')
+		.map(String::from)
+		.filter(|s| !s.is_empty())
+		.collect::<HashSet<String>>();
+	posts.retain(|p| !hidden.contains(&p.id));
+}
+
+/// Custom emoji of a subreddit, including Reddit's own, by name. URLs point at
+/// the emoji proxy.
+pub async fn subreddit_emojis(sub: &str) -> HashMap<String, String> {
+	let mut emojis = HashMap::new();
+	if let Ok(response) = json(format!("/api/v1/{}/emojis/all.json", sub), false).await {
+		// Emoji are grouped by owner, such as "snoomojis" and the subreddit's ID
+		for group in response.as_object().into_iter().flat_map(|groups| groups.values()) {
+			for (name, emoji) in group.as_object().into_iter().flatten() {
+				if let Some(url) = emoji["url"].as_str() {
+					emojis.insert(name.clone(), format_url(url));
+				}
+			}
+		}
+	}
+	emojis
+}
+
+static EMOJI_PLACEHOLDER_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r":([\w-]+):").unwrap());
+
+/// Splits `text` into text and emoji parts wherever it names one of `emojis`
+/// as `:name:`. Returns no parts if it names none.
+pub fn emoji_parts(text: &str, emojis: &HashMap<String, String>) -> Vec<FlairPart> {
+	let part = |flair_part_type: &str, value: &str| FlairPart {
+		flair_part_type: flair_part_type.to_string(),
+		value: value.to_string(),
+	};
+
+	let mut parts = Vec::new();
+	let mut last = 0;
+	for caps in EMOJI_PLACEHOLDER_REGEX.captures_iter(text) {
+		if let (Some(placeholder), Some(url)) = (caps.get(0), emojis.get(&caps[1])) {
+			if placeholder.start() > last {
+				parts.push(part("text", &text[last..placeholder.start()]));
+			}
+			parts.push(part("emoji", url));
+			last = placeholder.end();
+		}
+	}
+
+	if !parts.is_empty() && last < text.len() {
+		parts.push(part("text", &text[last..]));
+	}
+	parts
+}
+
 /// Creates a [`Post`] from a provided JSON.
 pub async fn parse_post(post: &serde_json::Value) -> Post {
+	crate::schema::check(post);
+
 	// Grab UTC time as unix timestamp
-	let (rel_time, created) = time(post["data"]["created_utc"].as_f64().unwrap_or_default());
+	let created_ts = post["data"]["created_utc"].as_f64().unwrap_or_default();
+	let (rel_time, created) = time(created_ts);
 	// Parse post score and upvote ratio
 	let score = post["data"]["score"].as_i64().unwrap_or_default();
 	let ratio: f64 = post["data"]["upvote_ratio"].as_f64().unwrap_or(1.0) * 100.0;
@@ -676,6 +1155,7 @@ pub async fn parse_post(post: &serde_json::Value) -> Post {
 	Post {
 		id: val(post, "id"),
 		title: val(post, "title"),
+		title_parts: Vec::new(),
 		community: val(post, "subreddit"),
 		body,
 		author: Author {
@@ -704,6 +1184,10 @@ pub async fn parse_post(post: &serde_json::Value) -> Post {
 			width: post["data"]["thumbnail_width"].as_i64().unwrap_or_default(),
 			height: post["data"]["thumbnail_height"].as_i64().unwrap_or_default(),
 			poster: String::new(),
+			size: 0,
+			scrubber_url: String::new(),
+			audio_sources: Vec::new(),
+			resolutions: thumbnail_resolutions(&post["data"]),
 		},
 		flair: Flair {
 			flair_parts: FlairPart::parse(
@@ -724,13 +1208,16 @@ pub async fn parse_post(post: &serde_json::Value) -> Post {
 			stickied: post["data"]["stickied"].as_bool().unwrap_or_default() || post["data"]["pinned"].as_bool().unwrap_or(false),
 		},
 		domain: val(post, "domain"),
+		link_title: String::new(),
 		rel_time,
 		created,
+		created_ts,
 		num_duplicates: post["data"]["num_duplicates"].as_u64().unwrap_or(0),
 		comments: format_num(post["data"]["num_comments"].as_i64().unwrap_or_default()),
 		gallery,
 		awards,
 		nsfw: post["data"]["over_18"].as_bool().unwrap_or_default(),
+		crosspost: Crosspost::parse(&post["data"]),
 	}
 }
 
@@ -744,19 +1231,126 @@ pub fn param(path: &str, value: &str) -> Option<String> {
 }
 
 // Retrieve the value of a setting by name
+// Values that aren't valid for the setting are ignored, as if it was unset.
 pub fn setting(req: &Request, name: &str) -> String {
+	let valid = |value: &String| crate::settings::is_valid(name, value);
+
 	// Parse a cookie value from request
 	req
 		.cookie(name)
-		.unwrap_or_else(|| {
-			// If there is no cookie for this setting, try receiving a default from the config
-			if let Some(default) = crate::config::get_setting(&format!("LIBREDDIT_DEFAULT_{}", name.to_uppercase())) {
-				Cookie::new(name, default)
-			} else {
-				Cookie::named(name)
-			}
+		.map(|cookie| cookie.value().to_string())
+		.filter(valid)
+		// If there is no cookie for this setting, try receiving a default from the config
+		.or_else(|| crate::config::get_setting(&format!("LIBREDDIT_DEFAULT_{}", name.to_uppercase())).filter(valid))
+		.unwrap_or_default()
+}
+
+/// Query string for a Reddit listing endpoint: the request's own parameters,
+/// with `limit` set from the page size preference. The `after` and `before`
+/// tokens name posts rather than offsets, so they hold for any page size.
+pub fn listing_query(req: &Request) -> String {
+	let params = req.uri().search_params();
+	params.set("limit", &listing_limit(req));
+	format!("?{}", String::from(params.to_string()))
+}
+
+/// A `sort` parameter with the default sort chosen in the preference `name`,
+/// to add to a listing query unless the request picks a sort itself.
+pub fn default_sort_query(req: &Request, name: &str) -> String {
+	match setting(req, name) {
+		sort if sort.is_empty() || req.uri().search_params().has("sort") => String::new(),
+		sort => format!("&sort={}", sort),
+	}
+}
+
+/// Number of items to request per listing page.
+pub fn listing_limit(req: &Request) -> String {
+	match setting(req, "limit") {
+		limit if limit.is_empty() => "25".to_string(),
+		limit => limit,
+	}
+}
+
+/// Region to narrow r/popular to: the `geo_filter` preference, or else one
+/// guessed from the request.
+pub fn geo_filter(req: &Request) -> String {
+	match setting(req, "geo_filter").as_str() {
+		"" | "auto" => {
+			let accept_language = req.headers().get("Accept-Language").ok().flatten().unwrap_or_default();
+			guess_geo_filter(&accept_language, req.client_country().as_deref())
+		}
+		geo_filter => geo_filter.to_string(),
+	}
+}
+
+// Languages mostly spoken in a single region Reddit has a filter for
+const LANGUAGE_REGIONS: [(&str, &str); 17] = [
+	("bg", "BG"),
+	("cs", "CZ"),
+	("de", "DE"),
+	("el", "GR"),
+	("fi", "FI"),
+	("fr", "FR"),
+	("hr", "HR"),
+	("hu", "HU"),
+	("is", "IS"),
+	("it", "IT"),
+	("ja", "JP"),
+	("pl", "PL"),
+	("ro", "RO"),
+	("sr", "RS"),
+	("sv", "SE"),
+	("th", "TH"),
+	("tr", "TR"),
+];
+
+// Pick a region from the Accept-Language header, preferring explicit regions
+// ("en-GB") over the client's country, and the country over a language alone
+fn guess_geo_filter(accept_language: &str, country: Option<&str>) -> String {
+	let known = |region: &str| crate::settings::GEO_FILTERS.iter().skip(2).any(|&(value, _)| value == region);
+	let languages: Vec<&str> = accept_language.split(',').filter_map(|tag| tag.split(';').next()).map(str::trim).collect();
+
+	languages
+		.iter()
+		.filter_map(|tag| tag.split_once('-').map(|(_, region)| region.to_uppercase()))
+		.find(|region| known(region))
+		.or_else(|| country.filter(|country| known(country)).map(ToString::to_string))
+		.or_else(|| {
+			languages.iter().find_map(|tag| {
+				LANGUAGE_REGIONS
+					.iter()
+					.find(|&&(language, _)| tag.eq_ignore_ascii_case(language))
+					.map(|&(_, region)| region.to_string())
+			})
+		})
+		.unwrap_or_else(|| "GLOBAL".to_string())
+}
+
+/// Returns true if the request asks for "no participation" mode, either by
+/// arriving on an `np.` subdomain or by carrying `?np=1`. Communities link to
+/// threads this way to discourage visitors from voting or joining in.
+pub fn no_participation(req: &Request) -> bool {
+	let uri = req.uri();
+	uri.hostname().starts_with("np.") || uri.search_params().get("np").as_deref() == Some("1")
+}
+
+// Links to other pages of the instance
+static LOCAL_LINK_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(<a\s[^>]*?href=")(/(?:[^/\\"][^"]*)?)""#).unwrap());
+
+/// Adds `np=1` to the links of a page to other pages of the instance, so
+/// "no participation" mode asked for with `?np=1` lasts while following them.
+pub fn carry_no_participation(html: &str) -> String {
+	LOCAL_LINK_REGEX
+		.replace_all(html, |caps: &regex::Captures| {
+			let link = &caps[2];
+			let (path, fragment) = link.find('#').map_or((link, ""), |i| link.split_at(i));
+			let np = match path.split_once('?') {
+				Some((_, query)) if query.split("&amp;").any(|param| param == "np=1") => "",
+				Some(_) => "&amp;np=1",
+				None => "?np=1",
+			};
+			format!("{}{}{}{}\"", &caps[1], path, np, fragment)
 		})
-		.value()
 		.to_string()
 }
 
@@ -778,6 +1372,7 @@ static REGEX_URL_OLD: Lazy<Regex> = Lazy::new(|| Regex::new(r"https://old\.reddi
 static REGEX_URL_NP: Lazy<Regex> = Lazy::new(|| Regex::new(r"https://np\.reddit\.com/(.*)").unwrap());
 static REGEX_URL_PLAIN: Lazy<Regex> = Lazy::new(|| Regex::new(r"https://reddit\.com/(.*)").unwrap());
 static REGEX_URL_VIDEOS: Lazy<Regex> = Lazy::new(|| Regex::new(r"https://v\.redd\.it/(.*)/DASH_([0-9]{2,4}(\.mp4|$|\?source=fallback))").unwrap());
+static REGEX_URL_AUDIO: Lazy<Regex> = Lazy::new(|| Regex::new(r"https://v\.redd\.it/(.*)/DASH_((audio|AUDIO_[0-9]{2,3})(\.mp4|$|\?source=fallback))").unwrap());
 static REGEX_URL_VIDEOS_HLS: Lazy<Regex> = Lazy::new(|| Regex::new(r"https://v\.redd\.it/(.+)/(HLSPlaylist\.m3u8.*)$").unwrap());
 static REGEX_URL_IMAGES: Lazy<Regex> = Lazy::new(|| Regex::new(r"https://i\.redd\.it/(.*)").unwrap());
 static REGEX_URL_THUMBS_A: Lazy<Regex> = Lazy::new(|| Regex::new(r"https://a\.thumbs\.redditmedia\.com/(.*)").unwrap());
@@ -787,6 +1382,7 @@ static REGEX_URL_PREVIEW: Lazy<Regex> = Lazy::new(|| Regex::new(r"https://previe
 static REGEX_URL_EXTERNAL_PREVIEW: Lazy<Regex> = Lazy::new(|| Regex::new(r"https://external\-preview\.redd\.it/(.*)").unwrap());
 static REGEX_URL_STYLES: Lazy<Regex> = Lazy::new(|| Regex::new(r"https://styles\.redditmedia\.com/(.*)").unwrap());
 static REGEX_URL_STATIC_MEDIA: Lazy<Regex> = Lazy::new(|| Regex::new(r"https://www\.redditstatic\.com/(.*)").unwrap());
+static REGEX_URL_EMOTES: Lazy<Regex> = Lazy::new(|| Regex::new(r"https://reddit-econ-prod-assets-permanent\.s3\.amazonaws\.com/(.*)").unwrap());
 
 // Direct urls to proxy if proxy is enabled
 pub fn format_url(url: &str) -> String {
@@ -812,7 +1408,7 @@ pub fn format_url(url: &str) -> String {
 					}
 				};
 
-				( $first_fn:expr, $($other_fns:expr), *) => {
+				( $first_fn:expr, $($other_fns:expr), * $(,)?) => {
 					{
 						let result = $first_fn;
 						if result.is_empty() {
@@ -831,7 +1427,11 @@ pub fn format_url(url: &str) -> String {
 				"old.reddit.com" => capture(&REGEX_URL_OLD, "/", 1),
 				"np.reddit.com" => capture(&REGEX_URL_NP, "/", 1),
 				"reddit.com" => capture(&REGEX_URL_PLAIN, "/", 1),
-				"v.redd.it" => chain!(capture(&REGEX_URL_VIDEOS, "/vid/", 2), capture(&REGEX_URL_VIDEOS_HLS, "/hls/", 2)),
+				"v.redd.it" => chain!(
+					capture(&REGEX_URL_VIDEOS, "/vid/", 2),
+					capture(&REGEX_URL_AUDIO, "/vid/", 2),
+					capture(&REGEX_URL_VIDEOS_HLS, "/hls/", 2)
+				),
 				"i.redd.it" => capture(&REGEX_URL_IMAGES, "/img/", 1),
 				"a.thumbs.redditmedia.com" => capture(&REGEX_URL_THUMBS_A, "/thumb/a/", 1),
 				"b.thumbs.redditmedia.com" => capture(&REGEX_URL_THUMBS_B, "/thumb/b/", 1),
@@ -840,35 +1440,170 @@ pub fn format_url(url: &str) -> String {
 				"external-preview.redd.it" => capture(&REGEX_URL_EXTERNAL_PREVIEW, "/preview/external-pre/", 1),
 				"styles.redditmedia.com" => capture(&REGEX_URL_STYLES, "/style/", 1),
 				"www.redditstatic.com" => capture(&REGEX_URL_STATIC_MEDIA, "/static/", 1),
+				"reddit-econ-prod-assets-permanent.s3.amazonaws.com" => capture(&REGEX_URL_EMOTES, "/emote/", 1),
 				_ => url.to_string(),
 			}
 		})
 	}
 }
 
-static REDDIT_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"href="(https|http|)://(www\.|old\.|np\.|amp\.|)(reddit\.com|redd\.it)/"#).unwrap());
-static REDDIT_PREVIEW_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"https://external-preview\.redd\.it(.*)[^?]").unwrap());
+// File extensions of audio formats browsers can play in an <audio> element
+const AUDIO_EXTENSIONS: [&str; 8] = [".mp3", ".m4a", ".aac", ".ogg", ".oga", ".opus", ".wav", ".flac"];
 
-// Rewrite Reddit links to Libreddit in body of text
-pub fn rewrite_urls(input_text: &str) -> String {
-	let text1 =
-		// Rewrite Reddit links to Libreddit
-		REDDIT_REGEX.replace_all(input_text, r#"href="/"#)
-			.to_string()
-			// Remove (html-encoded) "\" from URLs.
-			.replace("%5C", "")
-			.replace('\\', "");
-
-	// Rewrite external media previews to Libreddit
-	if REDDIT_PREVIEW_REGEX.is_match(&text1) {
-		REDDIT_PREVIEW_REGEX
-			.replace_all(&text1, format_url(REDDIT_PREVIEW_REGEX.find(&text1).map(|x| x.as_str()).unwrap_or_default()))
-			.to_string()
-	} else {
-		text1
+// Determine whether a URL points at audio-only media that can be played
+// through the media proxy. Links to other hosts are left as plain links, since
+// the Content-Security-Policy only allows media from this instance.
+pub fn is_audio_url(url: &str) -> bool {
+	if REGEX_URL_AUDIO.is_match(url) {
+		return true;
+	}
+
+	let path = url.split(['?', '#']).next().unwrap_or_default().to_lowercase();
+	AUDIO_EXTENSIONS.iter().any(|ext| path.ends_with(ext)) && format_url(url).starts_with('/')
+}
+
+// Splits a Reddit URL into its subdomain, domain and path
+static REDDIT_URL_REGEX: Lazy<Regex> =
+	Lazy::new(|| Regex::new(r"^(?:(?:https?:)?//)?((?:[a-z0-9-]+\.)*?)(reddit\.com|redd\.it|redditmedia\.com|redditstatic\.com)(/.*)?$").unwrap());
+
+// Matches link and media attributes pointing at Reddit
+static REDDIT_LINK_REGEX: Lazy<Regex> =
+	Lazy::new(|| Regex::new(r#"(href|src)="((?:(?:https?:)?//)?(?:[a-z0-9-]+\.)*(?:reddit\.com|redd\.it|redditmedia\.com|redditstatic\.com)(?:/[^"]*)?)""#).unwrap());
+
+/// Whether `path` stays on this instance when used as a link or redirect.
+/// Browsers follow `//host` and `/\host` to another site.
+pub fn is_local_path(path: &str) -> bool {
+	path.starts_with('/') && !path.starts_with("//") && !path.starts_with("/\\")
+}
+
+/// Maps an absolute Reddit URL to the path serving the same content on this
+/// instance: pages (posts, subreddits, users, wikis) keep their path, short
+/// `redd.it` links go through the short link route and media goes through the
+/// proxy. Returns `None` for URLs that aren't Reddit's, or that point at
+/// something this instance can't serve.
+pub fn instance_path(url: &str) -> Option<String> {
+	let caps = REDDIT_URL_REGEX.captures(url)?;
+	let subdomain = caps.get(1).map_or("", |m| m.as_str());
+	let path = caps.get(3).map_or("/", |m| m.as_str());
+
+	let local = match (subdomain, &caps[2]) {
+		// Pages, whichever flavour of Reddit they come from
+		("" | "www." | "old." | "new." | "np." | "m." | "amp." | "i.", "reddit.com") => path.to_string(),
+		// Short links to posts are handled by the `/:id` route
+		("", "redd.it") => path.to_string(),
+		// Anything else is media, which is routed through the proxy
+		(_, domain) => format_url(&format!("https://{}{}{}", subdomain, domain, path)),
+	};
+
+	is_local_path(&local).then_some(local)
+}
+
+// Typical size of a photo, in tenths of a byte per pixel
+const IMAGE_TENTHS_OF_BYTES_PER_PIXEL: u64 = 3;
+
+// Estimate the size of an image from its dimensions, as Reddit doesn't report
+// file sizes
+fn estimate_image_size(width: i64, height: i64) -> u64 {
+	(width.max(0) as u64) * (height.max(0) as u64) * IMAGE_TENTHS_OF_BYTES_PER_PIXEL / 10
+}
+
+/// Formats a byte count as an approximate size, such as "~340 KB". Returns an
+/// empty string for 0, which stands for an unknown size.
+pub fn format_bytes(bytes: u64) -> String {
+	match bytes {
+		0 => String::new(),
+		1..=999_999 => format!("~{} KB", (bytes / 1000).max(1)),
+		_ => format!("~{:.1} MB", bytes as f64 / 1_000_000.0),
 	}
 }
 
+// Rewrite Reddit links and media in body of text to point at Libreddit. This
+// is the one pass that all post bodies, comments, wikis and sidebars go
+// through, so that no link bounces users back to Reddit.
+pub fn rewrite_urls(input_text: &str) -> String {
+	// Remove (html-encoded) "\" from URLs.
+	let text = input_text.replace("%5C", "").replace('\\', "");
+
+	let text = REDDIT_LINK_REGEX.replace_all(&text, |caps: &regex::Captures| match instance_path(&caps[2]) {
+		Some(path) => format!("{}=\"{}\"", &caps[1], path),
+		None => caps[0].to_string(),
+	});
+
+	restructure_blocks(&text)
+}
+
+// Matches the opening and closing tags of tables and blockquotes
+static BLOCK_TAG_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"<(/?)(table|blockquote)>").unwrap());
+
+// Deepest blockquote nesting that gets its own style
+const MAX_QUOTE_DEPTH: usize = 4;
+
+// Wrap tables in a container that scrolls horizontally on narrow screens, and
+// tag blockquotes with how deeply they are nested so that each level can be
+// told apart.
+fn restructure_blocks(text: &str) -> String {
+	let mut depth = 0;
+	BLOCK_TAG_REGEX
+		.replace_all(text, |caps: &regex::Captures| match (&caps[1], &caps[2]) {
+			("", "table") => "<div class=\"md_table\"><table>".to_string(),
+			("/", "table") => "</table></div>".to_string(),
+			("", _) => {
+				depth += 1;
+				format!("<blockquote class=\"quote_depth_{}\">", depth.min(MAX_QUOTE_DEPTH))
+			}
+			_ => {
+				depth = depth.saturating_sub(1);
+				caps[0].to_string()
+			}
+		})
+		.to_string()
+}
+
+static REGEX_INLINE_MEDIA: Lazy<Regex> = Lazy::new(|| Regex::new(r"!\[(img|gif)\]\(([^)\s]+)\)").unwrap());
+
+// Largest width or height, in pixels, at which inline comment media is shown
+const INLINE_MEDIA_MAX_SIZE: i64 = 240;
+
+/// Replaces Reddit's inline media markup in a comment body, such as
+/// `![img](emote|t5_2qh1i|1234)` or `![gif](giphy|abc123)`, with the matching
+/// entry of the comment's `media_metadata`, served through the media proxy.
+/// Media is scaled down to fit within `INLINE_MEDIA_MAX_SIZE`. If `hide` is
+/// set, or the media can't be proxied, a plain link is rendered instead.
+pub fn render_inline_media(body: &str, media_metadata: &Value, hide: bool) -> String {
+	REGEX_INLINE_MEDIA
+		.replace_all(body, |caps: &regex::Captures| {
+			let media = &media_metadata[&caps[2]];
+			let source = &media["s"];
+			let url = format_url(source["gif"].as_str().or_else(|| source["u"].as_str()).unwrap_or_default());
+
+			// Leave the markup untouched if Reddit didn't tell us about this media
+			if media["status"] != "valid" || url.is_empty() {
+				return caps[0].to_string();
+			}
+
+			if hide || !url.starts_with('/') {
+				return format!("<a href=\"{}\">[{}]</a>", url, &caps[1]);
+			}
+
+			let (mut width, mut height) = (source["x"].as_i64().unwrap_or_default(), source["y"].as_i64().unwrap_or_default());
+			if width > INLINE_MEDIA_MAX_SIZE || height > INLINE_MEDIA_MAX_SIZE {
+				let largest = width.max(height);
+				width = width * INLINE_MEDIA_MAX_SIZE / largest;
+				height = height * INLINE_MEDIA_MAX_SIZE / largest;
+			}
+
+			if width > 0 && height > 0 {
+				format!(
+					"<img loading=\"lazy\" class=\"inline_media\" alt=\"{}\" src=\"{}\" width=\"{}\" height=\"{}\">",
+					&caps[1], url, width, height
+				)
+			} else {
+				format!("<img loading=\"lazy\" class=\"inline_media\" alt=\"{}\" src=\"{}\">", &caps[1], url)
+			}
+		})
+		.to_string()
+}
+
 // Format vote count to a string that will be displayed.
 // Append `m` and `k` for millions and thousands respectively, and
 // round to the nearest tenth.
@@ -884,6 +1619,33 @@ pub fn format_num(num: i64) -> (String, String) {
 	(truncated, num.to_string())
 }
 
+/// Formats a UNIX timestamp for display according to the `date_format`
+/// preference: `iso` (ISO 8601), `locale` (full date and time), or
+/// otherwise relative to now.
+pub fn format_time(created: f64, date_format: &str) -> String {
+	let time = OffsetDateTime::from_unix_timestamp(created.round() as i64).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+
+	match date_format {
+		"iso" => time.format(format_description!("[year]-[month]-[day]T[hour]:[minute]Z")).unwrap_or_default(),
+		"locale" => time
+			.format(format_description!("[month repr:short] [day padding:none], [year], [hour]:[minute] UTC"))
+			.unwrap_or_default(),
+		_ => self::time(created).0,
+	}
+}
+
+/// Formats a date without a time of day according to the `date_format`
+/// preference. Relative dates are shown in the short form used elsewhere.
+pub fn format_date(created: f64, date_format: &str) -> String {
+	let time = OffsetDateTime::from_unix_timestamp(created.round() as i64).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+
+	match date_format {
+		"iso" => time.format(format_description!("[year]-[month]-[day]")).unwrap_or_default(),
+		"locale" => time.format(format_description!("[month repr:short] [day padding:none], [year]")).unwrap_or_default(),
+		_ => time.format(format_description!("[month repr:short] [day] '[year repr:last_two]")).unwrap_or_default(),
+	}
+}
+
 // Parse a relative and absolute time from a UNIX timestamp
 pub fn time(created: f64) -> (String, String) {
 	let time = OffsetDateTime::from_unix_timestamp(created.round() as i64).unwrap_or(OffsetDateTime::UNIX_EPOCH);
@@ -953,17 +1715,31 @@ pub fn redirect(path: String) -> Response {
 
 /// Renders a generic error landing page.
 pub async fn error(req: Request, msg: impl ToString) -> Result<Response, String> {
+	if msg.to_string() == crate::client::BLOCKED {
+		return crate::status::blocked(&req);
+	}
+
+	error_page(&req, msg, 404)
+}
+
+/// Renders an error page for content that was deleted, removed or suspended
+/// and won't come back, with a 410 status so caches can keep the answer.
+pub async fn gone(req: Request, msg: impl ToString) -> Result<Response, String> {
+	error_page(&req, msg, 410)
+}
+
+fn error_page(req: &Request, msg: impl ToString, status: u16) -> Result<Response, String> {
 	let url = req.uri().pathname();
 	let body = ErrorTemplate {
 		msg: msg.to_string(),
-		prefs: Preferences::new(&req),
+		prefs: Preferences::new(req),
 		url,
 	}
 	.render()
 	.unwrap_or_default();
 
 	let mut init = ResponseInit::new();
-	init.status(404);
+	init.status(status);
 	let res = Response::new_with_opt_str_and_init(Some(body.as_str()), &init).map_err(wasm_error)?;
 
 	res.headers().set("content-type", "text/html").ok();
@@ -971,6 +1747,20 @@ pub async fn error(req: Request, msg: impl ToString) -> Result<Response, String>
 	Ok(res)
 }
 
+/// Why a post is gone, from its `removed_by_category`, as in "This post was
+/// removed by Reddit". `None` if the post wasn't removed.
+pub fn removal_reason(category: &str, community: &str) -> Option<String> {
+	match category {
+		"" => None,
+		"deleted" | "author" => Some("deleted by its author".to_string()),
+		"moderator" | "automod_filtered" => Some(format!("removed by the moderators of r/{}", community)),
+		"copyright_takedown" => Some("removed by Reddit after a copyright notice".to_string()),
+		"content_takedown" | "legal_operations" => Some("removed by Reddit after a legal request".to_string()),
+		"reddit" | "anti_evil_ops" | "community_ops" => Some("removed by Reddit".to_string()),
+		_ => Some("removed".to_string()),
+	}
+}
+
 /// Returns true if the config/env variable `LIBREDDIT_SFW_ONLY` carries the
 /// value `on`.
 ///
@@ -985,6 +1775,12 @@ pub fn sfw_only() -> bool {
 	}
 }
 
+/// Whether user pages should carry a `noindex` meta tag, as configured by the
+/// instance operator.
+pub fn noindex_user_pages() -> bool {
+	crate::config::get_setting("LIBREDDIT_ROBOTS_NOINDEX_USERS").is_some_and(|val| val == "on")
+}
+
 // Determines if a request shoud redirect to a nsfw landing gate.
 pub fn should_be_nsfw_gated(req: &Request, req_url: &str) -> bool {
 	let sfw_instance = sfw_only();
@@ -1050,7 +1846,21 @@ pub fn wasm_error(val: JsValue) -> String {
 
 #[cfg(test)]
 mod tests {
-	use super::{format_num, format_url, rewrite_urls};
+	use super::{
+		audio_sources, carry_no_participation, emoji_parts, format_bytes, format_date, format_num, format_time, format_url, guess_geo_filter, instance_path, is_audio_url,
+		removal_reason, render_inline_media, resolutions, rewrite_urls, Crosspost, GalleryMedia, Media, MediaKind, Poll,
+	};
+	use regex::Regex;
+	use serde_json::json;
+	use std::collections::HashMap;
+
+	#[test]
+	fn removal_reason_works() {
+		assert_eq!(removal_reason("", "rust"), None);
+		assert_eq!(removal_reason("moderator", "rust").as_deref(), Some("removed by the moderators of r/rust"));
+		assert_eq!(removal_reason("deleted", "rust").as_deref(), Some("deleted by its author"));
+		assert_eq!(removal_reason("something_new", "rust").as_deref(), Some("removed"));
+	}
 
 	#[test]
 	fn format_num_works() {
@@ -1061,13 +1871,21 @@ mod tests {
 		assert_eq!(format_num(1_999_999), ("2.0m".to_string(), "1999999".to_string()));
 	}
 
+	#[test]
+	fn format_bytes_works() {
+		assert_eq!(format_bytes(0), "");
+		assert_eq!(format_bytes(512), "~1 KB");
+		assert_eq!(format_bytes(340_500), "~340 KB");
+		assert_eq!(format_bytes(1_240_000), "~1.2 MB");
+	}
+
 	#[test]
 	fn rewrite_urls_removes_backslashes() {
 		let comment_body_html =
 			r#"<a href=\"https://www.reddit.com/r/linux%5C_gaming/comments/x/just%5C_a%5C_test%5C/\">https://www.reddit.com/r/linux\\_gaming/comments/x/just\\_a\\_test/</a>"#;
 		assert_eq!(
 			rewrite_urls(comment_body_html),
-			r#"<a href="https://www.reddit.com/r/linux_gaming/comments/x/just_a_test/">https://www.reddit.com/r/linux_gaming/comments/x/just_a_test/</a>"#
+			r#"<a href="/r/linux_gaming/comments/x/just_a_test/">https://www.reddit.com/r/linux_gaming/comments/x/just_a_test/</a>"#
 		)
 	}
 
@@ -1087,6 +1905,8 @@ mod tests {
 			"/preview/pre/qwerty.jpg?auto=webp&s=asdf"
 		);
 		assert_eq!(format_url("https://v.redd.it/foo/DASH_360.mp4?source=fallback"), "/vid/foo/360.mp4");
+		assert_eq!(format_url("https://v.redd.it/foo/DASH_audio.mp4"), "/vid/foo/audio.mp4");
+		assert_eq!(format_url("https://v.redd.it/foo/DASH_AUDIO_128.mp4?source=fallback"), "/vid/foo/AUDIO_128.mp4");
 		assert_eq!(
 			format_url("https://v.redd.it/foo/HLSPlaylist.m3u8?a=bar&v=1&f=sd"),
 			"/hls/foo/HLSPlaylist.m3u8?a=bar&v=1&f=sd"
@@ -1099,4 +1919,337 @@ mod tests {
 		assert_eq!(format_url("nsfw"), "");
 		assert_eq!(format_url("spoiler"), "");
 	}
+
+	#[test]
+	fn test_format_time() {
+		// 2023-05-04 13:07:12 UTC
+		let created = 1_683_205_632.0;
+		assert_eq!(format_time(created, "iso"), "2023-05-04T13:07Z");
+		assert_eq!(format_time(created, "locale"), "May 4, 2023, 13:07 UTC");
+		assert_eq!(format_date(created, "iso"), "2023-05-04");
+		assert_eq!(format_date(created, "relative"), "May 04 '23");
+	}
+
+	#[test]
+	fn test_is_audio_url() {
+		assert!(is_audio_url("https://v.redd.it/foo/DASH_audio.mp4"));
+		assert!(is_audio_url("https://v.redd.it/foo/DASH_AUDIO_64.mp4?source=fallback"));
+		assert!(!is_audio_url("https://v.redd.it/foo/DASH_720.mp4"));
+		assert!(!is_audio_url("https://i.redd.it/foobar.jpg"));
+		// Audio hosted outside of Reddit can't be proxied
+		assert!(!is_audio_url("https://example.com/episode.mp3"));
+	}
+
+	#[test]
+	fn test_render_inline_media() {
+		let metadata = serde_json::json!({
+			"emote|t5_2qh1i|1234": {
+				"status": "valid",
+				"s": { "x": 20, "y": 20, "u": "https://reddit-econ-prod-assets-permanent.s3.amazonaws.com/asset-manager/t5_2qh1i/foo.png" }
+			},
+			"giphy|abc123": {
+				"status": "valid",
+				"s": { "x": 480, "y": 240, "gif": "https://i.redd.it/abc123.gif" }
+			}
+		});
+
+		assert_eq!(
+			render_inline_media("<p>![img](emote|t5_2qh1i|1234)</p>", &metadata, false),
+			r#"<p><img loading="lazy" class="inline_media" alt="img" src="/emote/asset-manager/t5_2qh1i/foo.png" width="20" height="20"></p>"#
+		);
+		assert_eq!(
+			render_inline_media("![gif](giphy|abc123)", &metadata, false),
+			r#"<img loading="lazy" class="inline_media" alt="gif" src="/img/abc123.gif" width="240" height="120">"#
+		);
+		assert_eq!(render_inline_media("![gif](giphy|abc123)", &metadata, true), r#"<a href="/img/abc123.gif">[gif]</a>"#);
+		assert_eq!(render_inline_media("![img](unknown)", &metadata, false), "![img](unknown)");
+	}
+
+	#[test]
+	fn instance_path_works() {
+		assert_eq!(
+			instance_path("https://www.reddit.com/r/rust/comments/abc/title/"),
+			Some("/r/rust/comments/abc/title/".into())
+		);
+		assert_eq!(instance_path("https://old.reddit.com/r/rust/?sort=new"), Some("/r/rust/?sort=new".into()));
+		assert_eq!(instance_path("np.reddit.com/user/spez"), Some("/user/spez".into()));
+		assert_eq!(instance_path("https://reddit.com"), Some("/".into()));
+		assert_eq!(instance_path("https://redd.it/abc123"), Some("/abc123".into()));
+		assert_eq!(instance_path("//amp.reddit.com/r/rust/comments/abc/title/"), Some("/r/rust/comments/abc/title/".into()));
+		assert_eq!(instance_path("https://i.redd.it/foobar.jpg"), Some("/img/foobar.jpg".into()));
+		assert_eq!(instance_path("https://evilreddit.com/r/rust"), None);
+		assert_eq!(instance_path("https://reddit.com.example.org/r/rust"), None);
+		assert_eq!(instance_path("https://example.org/?q=reddit.com"), None);
+		assert_eq!(instance_path("https://www.reddit.com//evil.com"), None);
+		assert_eq!(instance_path("https://www.reddit.com/\\evil.com"), None);
+		assert_eq!(instance_path("https://redd.it//evil.com/abc"), None);
+	}
+
+	#[test]
+	fn is_local_path_works() {
+		assert!(is_local_path("/settings#theme"));
+		assert!(is_local_path("/r/rust?sort=new"));
+		assert!(!is_local_path("//evil.com"));
+		assert!(!is_local_path("/\\evil.com"));
+		assert!(!is_local_path("https://evil.com"));
+	}
+
+	#[test]
+	fn rewrite_urls_rewrites_reddit_links() {
+		let cases = [
+			(r#"<a href="https://www.reddit.com/r/rust/">r/rust</a>"#, r#"<a href="/r/rust/">r/rust</a>"#),
+			(r#"<a href="http://old.reddit.com/user/spez">spez</a>"#, r#"<a href="/user/spez">spez</a>"#),
+			(
+				r#"<a href="https://np.reddit.com/r/rust/comments/abc/title/def/?context=3">c</a>"#,
+				r#"<a href="/r/rust/comments/abc/title/def/?context=3">c</a>"#,
+			),
+			(r#"<a href="https://amp.reddit.com/r/rust/wiki/index">wiki</a>"#, r#"<a href="/r/rust/wiki/index">wiki</a>"#),
+			(r#"<a href="https://new.reddit.com/r/rust">new</a>"#, r#"<a href="/r/rust">new</a>"#),
+			(r#"<a href="https://m.reddit.com/r/rust">m</a>"#, r#"<a href="/r/rust">m</a>"#),
+			(r#"<a href="https://reddit.com">home</a>"#, r#"<a href="/">home</a>"#),
+			(r#"<a href="https://redd.it/abc123">short</a>"#, r#"<a href="/abc123">short</a>"#),
+			(r#"<a href="https://i.redd.it/foobar.jpg">img</a>"#, r#"<a href="/img/foobar.jpg">img</a>"#),
+			(
+				r#"<img src="https://preview.redd.it/qwerty.jpg?auto=webp&amp;s=asdf">"#,
+				r#"<img src="/preview/pre/qwerty.jpg?auto=webp&amp;s=asdf">"#,
+			),
+			(
+				r#"<a href="https://external-preview.redd.it/foo.jpg?auto=webp&amp;s=bar">a</a> <a href="https://external-preview.redd.it/baz.jpg">b</a>"#,
+				r#"<a href="/preview/external-pre/foo.jpg?auto=webp&amp;s=bar">a</a> <a href="/preview/external-pre/baz.jpg">b</a>"#,
+			),
+		];
+		for (input, expected) in cases {
+			assert_eq!(rewrite_urls(input), expected);
+		}
+	}
+
+	#[test]
+	fn rewrite_urls_restructures_blocks() {
+		assert_eq!(
+			rewrite_urls("<table><tr><td>a</td></tr></table>"),
+			r#"<div class="md_table"><table><tr><td>a</td></tr></table></div>"#
+		);
+		assert_eq!(
+			rewrite_urls("<blockquote><blockquote><p>a</p></blockquote></blockquote><blockquote>b</blockquote>"),
+			r#"<blockquote class="quote_depth_1"><blockquote class="quote_depth_2"><p>a</p></blockquote></blockquote><blockquote class="quote_depth_1">b</blockquote>"#
+		);
+	}
+
+	#[test]
+	fn rewrite_urls_keeps_other_links() {
+		let cases = [
+			r#"<a href="https://example.com/?u=https://www.reddit.com/">example</a>"#,
+			r#"<a href="https://notreddit.com/r/rust">lookalike</a>"#,
+			r#"<a href="/r/rust">relative</a>"#,
+			r#"<p>https://www.reddit.com/r/rust</p>"#,
+		];
+		for input in cases {
+			assert_eq!(rewrite_urls(input), input);
+		}
+	}
+
+	#[test]
+	fn guess_geo_filter_works() {
+		assert_eq!(guess_geo_filter("en-GB,en;q=0.9", Some("US")), "GB");
+		assert_eq!(guess_geo_filter("en-US,en;q=0.5", None), "US");
+		assert_eq!(guess_geo_filter("en", Some("CA")), "CA");
+		assert_eq!(guess_geo_filter("de,en;q=0.5", Some("CN")), "DE");
+		assert_eq!(guess_geo_filter("zh-CN", None), "GLOBAL");
+		assert_eq!(guess_geo_filter("", None), "GLOBAL");
+	}
+
+	#[test]
+	fn media_kind_classifies_posts() {
+		let listing: serde_json::Value = serde_json::from_str(include_str!("testdata/posts.json")).unwrap();
+		let expected = [
+			// Reddit-hosted video, with an image preview as well
+			("1a2b3c", MediaKind::Video),
+			// Crosspost of that video
+			("1a2b3d", MediaKind::Video),
+			// GIF hosted elsewhere, with a video preview
+			("1a2b3e", MediaKind::Gif),
+			// GIF converted to MP4
+			("1a2b3f", MediaKind::Gif),
+			("1a2b3g", MediaKind::Image),
+			("1a2b3h", MediaKind::SelfPost),
+			("1a2b3i", MediaKind::Gallery),
+			("1a2b3j", MediaKind::Link),
+		];
+
+		let posts = listing["data"]["children"].as_array().unwrap();
+		assert_eq!(posts.len(), expected.len());
+		for (post, (id, kind)) in posts.iter().zip(expected) {
+			assert_eq!(post["data"]["id"], id);
+			assert_eq!(MediaKind::classify(&post["data"]), kind, "{}", id);
+		}
+		assert_eq!(MediaKind::SelfPost.to_str(), "self");
+	}
+
+	#[test]
+	fn gallery_media_parses_in_order() {
+		let items = json!([
+			{ "media_id": "b", "caption": "Second", "outbound_url": "https://example.com/" },
+			{ "media_id": "failed" },
+			{ "media_id": "a" },
+		]);
+		let metadata = json!({
+			"a": { "status": "valid", "m": "image/jpg", "s": { "u": "https://preview.redd.it/a.jpg?s=1", "x": 2000, "y": 1000 }, "p": [
+				{ "u": "https://preview.redd.it/a.jpg?width=320", "x": 320 },
+				{ "u": "https://preview.redd.it/a.jpg?width=640", "x": 640 },
+				{ "u": "https://preview.redd.it/a.jpg?width=960", "x": 960 },
+			] },
+			"b": { "status": "valid", "m": "image/gif", "s": { "gif": "https://i.redd.it/b.gif", "x": 400, "y": 300 } },
+			"failed": { "status": "failed" },
+		});
+
+		let gallery = GalleryMedia::parse(&items, &metadata);
+		assert_eq!(gallery.len(), 2);
+		assert_eq!((gallery[0].caption.as_str(), gallery[0].outbound_url.as_str()), ("Second", "https://example.com/"));
+		assert_eq!(gallery[0].url, gallery[0].preview_url);
+		assert_eq!(gallery[1].preview_url, format_url("https://preview.redd.it/a.jpg?width=640"));
+		assert_eq!(gallery[1].width, 2000);
+	}
+
+	#[test]
+	fn audio_sources_works() {
+		assert_eq!(
+			audio_sources(&json!({ "fallback_url": "https://v.redd.it/abc/DASH_720.mp4?source=fallback", "has_audio": true })),
+			vec!["/vid/abc/AUDIO_128.mp4", "/vid/abc/AUDIO_64.mp4", "/vid/abc/audio.mp4"]
+		);
+		assert!(audio_sources(&json!({ "fallback_url": "https://v.redd.it/abc/DASH_720.mp4", "has_audio": false })).is_empty());
+		assert!(audio_sources(&json!({ "fallback_url": "https://v.redd.it/abc/DASH_480.mp4", "is_gif": true })).is_empty());
+	}
+
+	#[test]
+	fn preferred_url_picks_resolution() {
+		let media = Media {
+			url: "/img/abc.jpg".to_string(),
+			alt_url: String::new(),
+			width: 2000,
+			height: 1000,
+			poster: String::new(),
+			size: 0,
+			scrubber_url: String::new(),
+			audio_sources: Vec::new(),
+			resolutions: resolutions(&json!({ "resolutions": [
+				{ "url": "https://preview.redd.it/abc.jpg?width=108", "width": 108 },
+				{ "url": "https://preview.redd.it/abc.jpg?width=320", "width": 320 },
+				{ "url": "https://preview.redd.it/abc.jpg?width=640", "width": 640 },
+			] })),
+		};
+		assert_eq!(media.preferred_url("source"), "/img/abc.jpg");
+		assert_eq!(media.preferred_url("low"), "/preview/pre/abc.jpg?width=320");
+		assert_eq!(media.preferred_url("medium"), "/preview/pre/abc.jpg?width=640");
+		assert_eq!(media.listing_url("source"), "/img/abc.jpg?w=1080");
+		assert_eq!(media.listing_url("low"), "/preview/pre/abc.jpg?width=320");
+		assert_eq!(media.download_url("A cat!").as_deref(), Some("/download/img/abc.jpg?title=A%20cat%21"));
+	}
+
+	#[test]
+	fn crosspost_parses_parent() {
+		let crosspost = Crosspost::parse(&json!({ "crosspost_parent_list": [{
+			"subreddit": "rust",
+			"author": "op",
+			"title": "Original",
+			"permalink": "/r/rust/comments/abc/original/",
+			"selftext_html": "<div class=\"md\"><p>Text</p></div>",
+		}] }))
+		.unwrap();
+		assert_eq!((crosspost.community.as_str(), crosspost.permalink.as_str()), ("rust", "/r/rust/comments/abc/original/"));
+		assert_eq!(crosspost.body, "<div class=\"md\"><p>Text</p></div>");
+		assert!(Crosspost::parse(&json!({ "crosspost_parent_list": [] })).is_none());
+		assert!(Crosspost::parse(&json!({})).is_none());
+	}
+
+	#[test]
+	fn poll_parses_results() {
+		let poll = Poll::parse(&json!({
+			"total_vote_count": 3,
+			"voting_end_timestamp": 1_600_000_000_000.0,
+			"options": [
+				{ "id": "1", "text": "Yes", "vote_count": 2 },
+				{ "id": "2", "text": "No", "vote_count": 1 },
+			],
+		}))
+		.unwrap();
+		assert_eq!(poll.most_votes(), 2);
+		assert_eq!(poll.percentage(&poll.poll_options[0]), Some(67));
+		assert_eq!(poll.percentage(&poll.poll_options[1]), Some(33));
+		assert!(Poll::parse(&json!(null)).is_none());
+	}
+
+	#[test]
+	fn emoji_parts_works() {
+		let emojis = HashMap::from([("snoo".to_string(), "/emoji/a/snoo".to_string())]);
+		let parts: Vec<(String, String)> = emoji_parts("Hi :snoo: and :unknown:", &emojis).into_iter().map(|p| (p.flair_part_type, p.value)).collect();
+		assert_eq!(
+			parts,
+			vec![
+				("text".to_string(), "Hi ".to_string()),
+				("emoji".to_string(), "/emoji/a/snoo".to_string()),
+				("text".to_string(), " and :unknown:".to_string()),
+			]
+		);
+		assert!(emoji_parts("No emoji here", &emojis).is_empty());
+	}
+
+	// Every template, by file name, as it is on disk
+	fn templates() -> Vec<(String, String)> {
+		std::fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/templates"))
+			.unwrap()
+			.map(|entry| entry.unwrap().path())
+			.map(|path| (path.file_name().unwrap().to_string_lossy().to_string(), std::fs::read_to_string(&path).unwrap()))
+			.collect()
+	}
+
+	#[test]
+	fn templates_have_landmarks() {
+		let templates = templates();
+		let base = &templates.iter().find(|(name, _)| name == "base.html").unwrap().1;
+		assert!(base.contains(r##"<a id="skip_link" href="#main">"##));
+		assert!(base.contains(r#"<nav aria-label="#));
+
+		for (name, html) in &templates {
+			// The skip link targets whichever <main> the page renders
+			for main in Regex::new(r"<main\b[^>]*>").unwrap().find_iter(html) {
+				assert!(main.as_str().contains(r#"id="main""#), "{}: {}", name, main.as_str());
+			}
+			// Pages with several complementary regions need them named
+			for aside in Regex::new(r"<aside\b[^>]*>").unwrap().find_iter(html) {
+				assert!(aside.as_str().contains("aria-label="), "{}: {}", name, aside.as_str());
+			}
+		}
+	}
+
+	#[test]
+	fn form_controls_are_labeled() {
+		let control = Regex::new(r"(?s)<(input|select|textarea)\b[^>]*>").unwrap();
+		let label_for = Regex::new(r#"<label[^>]*for="([^"]+)""#).unwrap();
+		let id = Regex::new(r#"\bid="([^"]+)""#).unwrap();
+		let unlabeled_type = Regex::new(r#"type="(hidden|submit|button|image|reset)""#).unwrap();
+
+		for (name, html) in templates() {
+			let labeled_ids: Vec<&str> = label_for.captures_iter(&html).map(|c| c.get(1).unwrap().as_str()).collect();
+			for found in control.find_iter(&html) {
+				let tag = found.as_str();
+				let before = &html[..found.start()];
+				let wrapped = before.rfind("<label").map_or(false, |open| before.rfind("</label>").map_or(true, |close| open > close));
+				let labeled =
+					tag.contains("aria-label") || wrapped || unlabeled_type.is_match(tag) || id.captures(tag).map_or(false, |c| labeled_ids.contains(&c.get(1).unwrap().as_str()));
+				assert!(labeled, "{} has an unlabeled control: {}", name, tag);
+			}
+		}
+	}
+
+	#[test]
+	fn carry_no_participation_works() {
+		assert_eq!(
+			carry_no_participation(r#"<a href="/r/rust?sort=new&amp;t=day">a</a> <a class="x" href="/r/rust/comments/abc#c">b</a> <a href="/">c</a>"#),
+			r#"<a href="/r/rust?sort=new&amp;t=day&amp;np=1">a</a> <a class="x" href="/r/rust/comments/abc?np=1#c">b</a> <a href="/?np=1">c</a>"#
+		);
+
+		// Links elsewhere, resources and links already in the mode are left alone
+		let untouched = r#"<a href="https://example.org/">a</a> <a href="//example.org/">b</a> <link href="/style.css"> <a href="/r/rust?np=1">c</a>"#;
+		assert_eq!(carry_no_participation(untouched), untouched);
+	}
 }
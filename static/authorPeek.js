@@ -0,0 +1,35 @@
+// @license http://www.gnu.org/licenses/agpl-3.0.html AGPL-3.0
+(function () {
+    var buttons = document.querySelectorAll("button.author_peek");
+    Array.prototype.forEach.call(buttons, function (button) {
+        button.hidden = false;
+        button.addEventListener("click", function (event) {
+            // Don't collapse the comment the button sits in
+            event.preventDefault();
+
+            var panel = button.closest("details").querySelector(":scope > .peek_panel");
+            panel.hidden = !panel.hidden;
+            if (panel.hidden || panel.dataset.loaded) {
+                return;
+            }
+
+            panel.dataset.loaded = "true";
+            panel.textContent = "Loading…";
+            fetch(button.dataset.peek)
+                .then(function (response) {
+                    if (!response.ok) {
+                        throw new Error(response.statusText);
+                    }
+                    return response.text();
+                })
+                .then(function (html) {
+                    panel.innerHTML = html;
+                })
+                .catch(function () {
+                    panel.textContent = "Couldn't load recent comments.";
+                    delete panel.dataset.loaded;
+                });
+        });
+    });
+})();
+// @license-end
@@ -0,0 +1,87 @@
+// @license http://www.gnu.org/licenses/agpl-3.0.html AGPL-3.0
+(function () {
+    var thread = document.querySelector("[data-post-id]");
+    if (!thread) {
+        return;
+    }
+
+    var postId = thread.getAttribute("data-post-id");
+    var storageKey = "collapsed_" + postId;
+
+    // Threads with more collapsed comments than this are only remembered
+    // when localStorage is available, as cookies are limited in size
+    var maxCookieComments = 50;
+
+    // Collapsed comments are remembered for 30 days
+    var maxAge = 30 * 24 * 60 * 60;
+
+    var storage = null;
+    try {
+        storage = window.localStorage;
+        storage.setItem("collapsed_test", "1");
+        storage.removeItem("collapsed_test");
+    } catch (e) {
+        storage = null;
+    }
+
+    function load() {
+        var value = null;
+        if (storage) {
+            value = storage.getItem(storageKey);
+        } else {
+            document.cookie.split("; ").forEach(function (cookie) {
+                var parts = cookie.split("=");
+                if (parts[0] === storageKey) {
+                    value = decodeURIComponent(parts[1] || "");
+                }
+            });
+        }
+        return value ? value.split(",").filter(function (id) { return id !== ""; }) : [];
+    }
+
+    function save(ids) {
+        if (storage) {
+            if (ids.length > 0) {
+                storage.setItem(storageKey, ids.join(","));
+            } else {
+                storage.removeItem(storageKey);
+            }
+        } else if (ids.length > 0 && ids.length <= maxCookieComments) {
+            document.cookie = storageKey + "=" + encodeURIComponent(ids.join(",")) + "; path=/; max-age=" + maxAge + "; samesite=strict";
+        } else {
+            document.cookie = storageKey + "=; path=/; max-age=0; samesite=strict";
+        }
+    }
+
+    var collapsed = load();
+
+    // Restore the collapsed state of comments from the previous visit
+    collapsed.forEach(function (id) {
+        var comment = document.getElementById(id);
+        var details = comment && comment.querySelector(":scope > details.comment_right");
+        if (details) {
+            details.removeAttribute("open");
+        }
+    });
+
+    // The toggle event doesn't bubble, so listen for it while capturing
+    document.addEventListener("toggle", function (event) {
+        var details = event.target;
+        if (!details.classList || !details.classList.contains("comment_right")) {
+            return;
+        }
+
+        var id = details.parentNode.id;
+        var index = collapsed.indexOf(id);
+        if (details.open && index !== -1) {
+            collapsed.splice(index, 1);
+        } else if (!details.open && index === -1) {
+            collapsed.push(id);
+        } else {
+            return;
+        }
+
+        save(collapsed);
+    }, true);
+})();
+// @license-end
@@ -0,0 +1,29 @@
+// @license http://www.gnu.org/licenses/agpl-3.0.html AGPL-3.0
+(function () {
+    if (!navigator.clipboard) {
+        return;
+    }
+
+    var blocks = document.querySelectorAll(".post_body pre, .comment_body pre");
+    Array.prototype.forEach.call(blocks, function (pre) {
+        var wrapper = document.createElement("div");
+        wrapper.className = "code_block";
+        pre.parentNode.insertBefore(wrapper, pre);
+        wrapper.appendChild(pre);
+
+        var button = document.createElement("button");
+        button.type = "button";
+        button.className = "copy_code";
+        button.textContent = "copy";
+        button.addEventListener("click", function () {
+            navigator.clipboard.writeText(pre.textContent).then(function () {
+                button.textContent = "copied";
+                setTimeout(function () {
+                    button.textContent = "copy";
+                }, 2000);
+            });
+        });
+        wrapper.appendChild(button);
+    });
+})();
+// @license-end
@@ -0,0 +1,21 @@
+// @license http://www.gnu.org/licenses/agpl-3.0.html AGPL-3.0
+(function () {
+    if (!navigator.clipboard) {
+        return;
+    }
+
+    var buttons = document.querySelectorAll("button.copy_permalink");
+    Array.prototype.forEach.call(buttons, function (button) {
+        button.hidden = false;
+        button.addEventListener("click", function () {
+            var link = button.previousElementSibling;
+            navigator.clipboard.writeText(link.href).then(function () {
+                button.textContent = "copied";
+                setTimeout(function () {
+                    button.textContent = "copy link";
+                }, 2000);
+            });
+        });
+    });
+})();
+// @license-end
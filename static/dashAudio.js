@@ -0,0 +1,62 @@
+// @license http://www.gnu.org/licenses/agpl-3.0.html AGPL-3.0
+(function () {
+    // Reddit serves the sound of its videos as a separate track, so play it
+    // alongside the video, following its position, speed and volume
+    var tracks = document.querySelectorAll("audio.dash_audio:not([data-synced])");
+    tracks.forEach(function (audio) {
+        var video = audio.previousElementSibling;
+        if (!video || video.tagName !== "VIDEO") {
+            return;
+        }
+        audio.setAttribute("data-synced", "");
+
+        // How far, in seconds, the tracks may drift apart
+        var maxDrift = 0.3;
+
+        function sync() {
+            if (Math.abs(audio.currentTime - video.currentTime) > maxDrift) {
+                audio.currentTime = video.currentTime;
+            }
+        }
+
+        function play() {
+            sync();
+            var playing = audio.play();
+            // Browsers may refuse to play sound that the visitor didn't start
+            if (playing && playing.catch) {
+                playing.catch(function () {});
+            }
+        }
+
+        video.addEventListener("play", play);
+        video.addEventListener("playing", play);
+        video.addEventListener("pause", function () {
+            audio.pause();
+        });
+        video.addEventListener("waiting", function () {
+            audio.pause();
+        });
+        video.addEventListener("seeking", function () {
+            audio.currentTime = video.currentTime;
+        });
+        video.addEventListener("timeupdate", function () {
+            if (!video.paused) {
+                sync();
+            }
+        });
+        video.addEventListener("ratechange", function () {
+            audio.playbackRate = video.playbackRate;
+        });
+        video.addEventListener("volumechange", function () {
+            audio.volume = video.volume;
+            audio.muted = video.muted;
+        });
+
+        audio.volume = video.volume;
+        audio.muted = video.muted;
+        if (!video.paused) {
+            play();
+        }
+    });
+})();
+// @license-end
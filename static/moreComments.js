@@ -0,0 +1,35 @@
+// @license http://www.gnu.org/licenses/agpl-3.0.html AGPL-3.0
+(function () {
+    if (!window.fetch) {
+        return;
+    }
+
+    // Load "more comments" stubs in place rather than on their own page
+    document.addEventListener("click", function (event) {
+        var link = event.target.closest && event.target.closest("a.more_comments");
+        if (!link || event.ctrlKey || event.metaKey || event.shiftKey) {
+            return;
+        }
+
+        event.preventDefault();
+        if (link.getAttribute("aria-busy")) {
+            return;
+        }
+        link.setAttribute("aria-busy", "true");
+        link.textContent = "Loading…";
+
+        fetch(link.href + "&fragment=1").then(function (res) {
+            if (!res.ok) {
+                throw new Error(res.status);
+            }
+            return res.text();
+        }).then(function (html) {
+            var fragment = document.createElement("template");
+            fragment.innerHTML = html;
+            link.parentNode.replaceChild(fragment.content, link);
+        }).catch(function () {
+            window.location.href = link.href;
+        });
+    });
+})();
+// @license-end
@@ -73,5 +73,48 @@
             video.setAttribute("autoplay", "");
         });
     }
+
+    // Show frames from the low resolution "scrubber" copy of a video while
+    // the pointer is over the bottom of the player, where the seek bar is
+    function attachScrubber(video) {
+        var preview = document.createElement("video");
+        preview.className = "video_scrubber";
+        preview.muted = true;
+        preview.preload = "none";
+        preview.src = video.dataset.scrubber;
+
+        var wrapper = video.parentNode;
+        wrapper.classList.add("has_scrubber");
+        wrapper.appendChild(preview);
+
+        video.addEventListener("mousemove", function (event) {
+            var rect = video.getBoundingClientRect();
+            var x = event.clientX - rect.left;
+            if (rect.bottom - event.clientY > 40 || x < 0 || x > rect.width) {
+                preview.classList.remove("visible");
+                return;
+            }
+
+            if (preview.preload === "none") {
+                preview.preload = "auto";
+                preview.load();
+            }
+
+            var duration = preview.duration || video.duration;
+            if (!duration || !isFinite(duration)) {
+                return;
+            }
+
+            preview.currentTime = duration * x / rect.width;
+            preview.style.left = video.offsetLeft + Math.min(Math.max(x - preview.offsetWidth / 2, 0), rect.width - preview.offsetWidth) + "px";
+            preview.classList.add("visible");
+        });
+
+        video.addEventListener("mouseleave", function () {
+            preview.classList.remove("visible");
+        });
+    }
+
+    document.querySelectorAll("video[data-scrubber]").forEach(attachScrubber);
 })();
 // @license-end
\ No newline at end of file
@@ -0,0 +1,27 @@
+// @license http://www.gnu.org/licenses/agpl-3.0.html AGPL-3.0
+(function () {
+    var input = document.getElementById("settings_filter");
+    if (!input) {
+        return;
+    }
+
+    // The filter box only works with JavaScript, so it starts out hidden
+    input.hidden = false;
+
+    // Show only the settings whose label matches, and the sections holding them
+    input.addEventListener("input", function () {
+        var query = input.value.trim().toLowerCase();
+        document.querySelectorAll("#settings fieldset").forEach(function (section) {
+            var matches = 0;
+            section.querySelectorAll(".prefs-group").forEach(function (group) {
+                var match = group.textContent.toLowerCase().indexOf(query) !== -1;
+                group.hidden = !match;
+                if (match) {
+                    matches++;
+                }
+            });
+            section.hidden = matches === 0;
+        });
+    });
+})();
+// @license-end
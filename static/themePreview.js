@@ -0,0 +1,14 @@
+// @license http://www.gnu.org/licenses/agpl-3.0.html AGPL-3.0
+(function () {
+    var select = document.getElementById("theme");
+    var preview = document.getElementById("theme_preview");
+    if (!select || !preview) {
+        return;
+    }
+
+    // Show the selected theme in the preview pane before it is saved
+    select.addEventListener("change", function () {
+        preview.className = select.value === "system" ? "" : select.value;
+    });
+})();
+// @license-end
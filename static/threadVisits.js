@@ -0,0 +1,63 @@
+// @license http://www.gnu.org/licenses/agpl-3.0.html AGPL-3.0
+(function () {
+    var thread = document.querySelector("[data-post-id]");
+    if (!thread) {
+        return;
+    }
+
+    var storage = null;
+    try {
+        storage = window.localStorage;
+        storage.setItem("visited_test", "1");
+        storage.removeItem("visited_test");
+    } catch (e) {
+        return;
+    }
+
+    var storageKey = "visited_" + thread.getAttribute("data-post-id");
+    var now = Math.floor(Date.now() / 1000);
+
+    // Visits are remembered for 30 days
+    var maxAge = 30 * 24 * 60 * 60;
+
+    // Mark comments posted since the previous visit
+    var lastVisit = parseInt(storage.getItem(storageKey), 10);
+    if (lastVisit) {
+        var count = 0;
+        var comments = document.querySelectorAll(".comment[data-created]");
+        Array.prototype.forEach.call(comments, function (comment) {
+            if (parseFloat(comment.getAttribute("data-created")) <= lastVisit) {
+                return;
+            }
+
+            count++;
+            comment.classList.add("new_comment");
+            var created = comment.querySelector(":scope > details > summary > .created");
+            if (created) {
+                var badge = document.createElement("span");
+                badge.className = "new_badge";
+                badge.textContent = "new";
+                created.parentNode.insertBefore(badge, created.nextSibling);
+            }
+        });
+
+        var commentCount = document.getElementById("comment_count");
+        if (count > 0 && commentCount) {
+            var note = document.createElement("span");
+            note.id = "new_comment_count";
+            note.textContent = " (" + count + " new since your last visit)";
+            commentCount.insertBefore(note, document.getElementById("sorted_by"));
+        }
+    }
+
+    // Forget threads that weren't visited for a while
+    for (var i = storage.length - 1; i >= 0; i--) {
+        var key = storage.key(i);
+        if (key && key.indexOf("visited_") === 0 && now - parseInt(storage.getItem(key), 10) > maxAge) {
+            storage.removeItem(key);
+        }
+    }
+
+    storage.setItem(storageKey, String(now));
+})();
+// @license-end